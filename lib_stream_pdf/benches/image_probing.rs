@@ -0,0 +1,47 @@
+use std::{
+    io::{Cursor},
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{DynamicImage, ImageOutputFormat, RgbImage};
+use lib_stream_pdf::{PDFImage};
+
+/// About the size of a scanned manga page
+const WIDTH: u32 = 1800;
+const HEIGHT: u32 = 2600;
+
+fn make_image_bytes(format: ImageOutputFormat) -> Vec<u8> {
+    let image = RgbImage::from_fn(WIDTH, HEIGHT, |x, y| image::Rgb([
+        (x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8,
+    ]));
+    let mut image_bytes = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(image).write_to(&mut image_bytes, format).unwrap();
+    image_bytes.into_inner()
+}
+
+/// Decoding the whole image (what used to happen for every JPEG) against only reading the header
+fn jpeg_passthrough(c: &mut Criterion) {
+    let jpeg_bytes = make_image_bytes(ImageOutputFormat::Jpeg(90));
+    let mut group = c.benchmark_group("jpeg_passthrough");
+    group.bench_function("full_decode", |b| b.iter(|| {
+        image::load_from_memory(black_box(&jpeg_bytes)).unwrap()
+    }));
+    group.bench_function("header_only", |b| b.iter(|| {
+        PDFImage::from_bytes(black_box(jpeg_bytes.clone()), false).unwrap()
+    }));
+    group.finish();
+}
+
+fn read_dimensions(c: &mut Criterion) {
+    let png_bytes = make_image_bytes(ImageOutputFormat::Png);
+    let mut group = c.benchmark_group("png_dimensions");
+    group.bench_function("full_decode", |b| b.iter(|| {
+        image::load_from_memory(black_box(&png_bytes)).unwrap()
+    }));
+    group.bench_function("header_only", |b| b.iter(|| {
+        PDFImage::read_dimensions(black_box(&png_bytes)).unwrap()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, jpeg_passthrough, read_dimensions);
+criterion_main!(benches);
@@ -0,0 +1,137 @@
+use crate::{
+    objects::{Dictionary, Name, Object},
+};
+
+/// The order that pages get read (and shown side by side) in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ReadingDirection {
+    LeftToRight,
+    RightToLeft,
+}
+impl ReadingDirection {
+    fn pdf_name(&self) -> Name {
+        match self {
+            Self::LeftToRight => Name::new("L2R"),
+            Self::RightToLeft => Name::new("R2L"),
+        }
+    }
+}
+
+/// How the viewer lays out the pages when the document is opened.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageLayout {
+    SinglePage,
+    OneColumn,
+    /// Two columns with the odd pages on the left
+    TwoColumnLeft,
+    /// Two columns with the odd pages on the right
+    TwoColumnRight,
+    /// Two pages at a time with the odd pages on the left
+    TwoPageLeft,
+    /// Two pages at a time with the odd pages on the right
+    TwoPageRight,
+}
+impl PageLayout {
+    fn pdf_name(&self) -> Name {
+        match self {
+            Self::SinglePage => Name::new("SinglePage"),
+            Self::OneColumn => Name::new("OneColumn"),
+            Self::TwoColumnLeft => Name::new("TwoColumnLeft"),
+            Self::TwoColumnRight => Name::new("TwoColumnRight"),
+            Self::TwoPageLeft => Name::new("TwoPageLeft"),
+            Self::TwoPageRight => Name::new("TwoPageRight"),
+        }
+    }
+}
+
+/// What the viewer shows alongside the pages when the document is opened.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageMode {
+    UseNone,
+    UseOutlines,
+    UseThumbs,
+    FullScreen,
+}
+impl PageMode {
+    fn pdf_name(&self) -> Name {
+        match self {
+            Self::UseNone => Name::new("UseNone"),
+            Self::UseOutlines => Name::new("UseOutlines"),
+            Self::UseThumbs => Name::new("UseThumbs"),
+            Self::FullScreen => Name::new("FullScreen"),
+        }
+    }
+}
+
+/// Settings for how a viewer should present the document. Anything left unset uses the
+/// viewer's default.
+#[derive(Default)]
+pub struct CatalogOptions {
+    direction: Option<ReadingDirection>,
+    display_doc_title: Option<bool>,
+    fit_window: Option<bool>,
+    hide_toolbar: Option<bool>,
+    page_layout: Option<PageLayout>,
+    page_mode: Option<PageMode>,
+}
+impl CatalogOptions {
+    pub fn new() -> CatalogOptions {
+        CatalogOptions::default()
+    }
+    pub fn with_direction(mut self, direction: ReadingDirection) -> CatalogOptions {
+        self.direction = Some(direction);
+        self
+    }
+    /// Show the document's title in the window instead of the file name
+    pub fn with_display_doc_title(mut self, display_doc_title: bool) -> CatalogOptions {
+        self.display_doc_title = Some(display_doc_title);
+        self
+    }
+    /// Resize the window to fit the first page
+    pub fn with_fit_window(mut self, fit_window: bool) -> CatalogOptions {
+        self.fit_window = Some(fit_window);
+        self
+    }
+    pub fn with_hide_toolbar(mut self, hide_toolbar: bool) -> CatalogOptions {
+        self.hide_toolbar = Some(hide_toolbar);
+        self
+    }
+    pub fn with_page_layout(mut self, page_layout: PageLayout) -> CatalogOptions {
+        self.page_layout = Some(page_layout);
+        self
+    }
+    pub fn with_page_mode(mut self, page_mode: PageMode) -> CatalogOptions {
+        self.page_mode = Some(page_mode);
+        self
+    }
+}
+
+/// Only the options that were set get written, so existing catalog entries are kept otherwise
+pub fn add_to_catalog(options: &CatalogOptions, catalog: &mut Dictionary) {
+    let mut viewer_preferences = match catalog.get(&Name::viewer_preferences()) {
+        Some(Object::Dictionary(viewer_preferences)) => viewer_preferences.clone(),
+        _ => Dictionary::new(),
+    };
+    if let Some(direction) = options.direction {
+        viewer_preferences.insert(Name::direction(), direction.pdf_name());
+    }
+    if let Some(display_doc_title) = options.display_doc_title {
+        viewer_preferences.insert(Name::display_doc_title(), display_doc_title);
+    }
+    if let Some(fit_window) = options.fit_window {
+        viewer_preferences.insert(Name::fit_window(), fit_window);
+    }
+    if let Some(hide_toolbar) = options.hide_toolbar {
+        viewer_preferences.insert(Name::hide_toolbar(), hide_toolbar);
+    }
+    if !viewer_preferences.is_empty() {
+        catalog.insert(Name::viewer_preferences(), viewer_preferences);
+    }
+
+    if let Some(page_layout) = options.page_layout {
+        catalog.insert(Name::page_layout(), page_layout.pdf_name());
+    }
+    if let Some(page_mode) = options.page_mode {
+        catalog.insert(Name::page_mode(), page_mode.pdf_name());
+    }
+}
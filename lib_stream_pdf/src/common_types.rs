@@ -1,8 +1,57 @@
 
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Justify {
     Start,
     End,
     Center,
     SpaceBetween,
 }
+
+/// How an image has to be flipped or turned to be upright, from its EXIF orientation
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Orientation {
+    Normal,
+    FlipHorizontal,
+    Rotate180,
+    FlipVertical,
+    /// Flipped over the top-left to bottom-right diagonal
+    Transpose,
+    /// Turned 90 degrees clockwise
+    Rotate90,
+    /// Flipped over the top-right to bottom-left diagonal
+    Transverse,
+    /// Turned 90 degrees counter-clockwise
+    Rotate270,
+}
+impl Orientation {
+    pub fn from_exif(orientation: u16) -> Orientation {
+        match orientation {
+            2 => Self::FlipHorizontal,
+            3 => Self::Rotate180,
+            4 => Self::FlipVertical,
+            5 => Self::Transpose,
+            6 => Self::Rotate90,
+            7 => Self::Transverse,
+            8 => Self::Rotate270,
+            _ => Self::Normal,
+        }
+    }
+    /// The width and height get swapped when it's turned on its side
+    pub fn swaps_dimensions(&self) -> bool {
+        matches!(self, Self::Transpose | Self::Rotate90 | Self::Transverse | Self::Rotate270)
+    }
+    /// The matrix that takes the unit square of the stored image to the unit square of the upright one
+    pub fn image_matrix(&self) -> [i32; 6] {
+        match self {
+            Self::Normal => [1, 0, 0, 1, 0, 0],
+            Self::FlipHorizontal => [-1, 0, 0, 1, 1, 0],
+            Self::Rotate180 => [-1, 0, 0, -1, 1, 1],
+            Self::FlipVertical => [1, 0, 0, -1, 0, 1],
+            Self::Transpose => [0, -1, -1, 0, 1, 1],
+            Self::Rotate90 => [0, -1, 1, 0, 0, 1],
+            Self::Transverse => [0, 1, 1, 0, 0, 0],
+            Self::Rotate270 => [0, 1, -1, 0, 1, 0],
+        }
+    }
+}
@@ -0,0 +1,64 @@
+/// How hard Flate tries to make the streams smaller
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FlateLevel {
+    /// A zlib level from 0 (not compressed) to 9 (the smallest)
+    Zlib(u32),
+    /// Searches much longer than zlib for a smaller stream, which usually saves a few more percent.
+    /// It's a lot slower, so it's meant for final archival builds.
+    Zopfli,
+}
+
+/// How much of the colour (not the brightness) of re-encoded JPEGs is kept
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ChromaSubsampling {
+    /// Every pixel keeps its colour (4:4:4)
+    Full,
+    /// Every 2 pixels across share their colour (4:2:2)
+    Half,
+    /// Every 2x2 block of pixels shares its colour (4:2:0)
+    Quarter,
+}
+
+/// The settings for everything that gets compressed, from content streams to images
+#[derive(Clone, Debug)]
+pub struct CompressionOptions {
+    flate_level: FlateLevel,
+    jpeg_quality: u8,
+    chroma_subsampling: ChromaSubsampling,
+}
+impl Default for CompressionOptions {
+    fn default() -> CompressionOptions {
+        CompressionOptions {
+            flate_level: FlateLevel::Zlib(9),
+            jpeg_quality: 90,
+            chroma_subsampling: ChromaSubsampling::Full,
+        }
+    }
+}
+impl CompressionOptions {
+    pub fn new() -> CompressionOptions {
+        CompressionOptions::default()
+    }
+    /// Zlib levels above 9 are taken as 9. Defaults to 9.
+    pub fn with_flate_level(mut self, flate_level: FlateLevel) -> CompressionOptions {
+        self.flate_level = match flate_level {
+            FlateLevel::Zlib(level) => FlateLevel::Zlib(level.min(9)),
+            FlateLevel::Zopfli => FlateLevel::Zopfli,
+        };
+        self
+    }
+    /// From 1 to 100. Defaults to 90.
+    pub fn with_jpeg_quality(mut self, jpeg_quality: u8) -> CompressionOptions {
+        self.jpeg_quality = jpeg_quality.clamp(1, 100);
+        self
+    }
+    /// Defaults to `Full`, since the colours of manga pages are often just a few pixels wide
+    pub fn with_chroma_subsampling(mut self, chroma_subsampling: ChromaSubsampling) -> CompressionOptions {
+        self.chroma_subsampling = chroma_subsampling;
+        self
+    }
+
+    pub fn flate_level(&self) -> FlateLevel { self.flate_level }
+    pub fn jpeg_quality(&self) -> u8 { self.jpeg_quality }
+    pub fn chroma_subsampling(&self) -> ChromaSubsampling { self.chroma_subsampling }
+}
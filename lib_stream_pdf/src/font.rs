@@ -0,0 +1,454 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{Write},
+    path::{Path},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+use subsetter::{GlyphRemapper};
+use ttf_parser::{
+    Face, GlyphId, RawFace, Tag, name_id,
+    gsub::{SingleSubstitution, SubstitutionSubtable},
+    os2::{Permissions},
+};
+use crate::{
+    PDFError, PDFResult,
+    compression::{FlateLevel},
+    objects::{Dictionary, Name, Object, ObjectId, Stream},
+};
+
+/// A TrueType or OpenType (CFF) font that gets subset down to the glyphs that are used.
+pub struct PDFFont {
+    font_bytes: Vec<u8>,
+    face_index: u32,
+    postscript_name: String,
+    units_per_em: f64,
+    ascender: i16,
+    descender: i16,
+    cap_height: i16,
+    italic_angle: f64,
+    bounding_box: [i16; 4],
+    is_fixed_pitch: bool,
+    is_italic: bool,
+    is_cff: bool,
+    glyph_ids: HashMap<char, u16>,
+    /// Indexed by the glyph ID
+    advance_widths: Vec<u16>,
+    /// Indexed by the glyph ID. Empty if the font doesn't have vertical metrics (`vhea`/`vmtx`).
+    advance_heights: Vec<u16>,
+    /// Indexed by the glyph ID. The top of each glyph's vertical em box.
+    vertical_origins: Vec<i16>,
+    /// The glyphs to use instead in vertical text (from the `vert` and `vrt2` GSUB features)
+    vertical_glyph_ids: HashMap<u16, u16>,
+}
+impl PDFFont {
+    pub fn from_path(font_path: impl AsRef<Path>, face_index: u32) -> PDFResult<PDFFont> {
+        let font_path = font_path.as_ref();
+        let font_bytes = fs::read(font_path)?;
+        match Self::from_bytes(font_bytes, face_index) {
+            Err(PDFError::BadFont(message)) => {
+                Err(PDFError::BadFont(format!("{} path={}", message, font_path.display())))
+            },
+            other => other,
+        }
+    }
+    /// `face_index` picks the font out of a collection (TTC/OTC). Use 0 for a normal font file.
+    pub fn from_bytes(font_bytes: Vec<u8>, face_index: u32) -> PDFResult<PDFFont> {
+        let face = Face::parse(&font_bytes, face_index)
+            .map_err(|error| PDFError::BadFont(error.to_string()))?;
+        if face.permissions() == Some(Permissions::Restricted) {
+            return Err(PDFError::BadFont("The font's license doesn't allow embedding".to_string()));
+        }
+        let tables = face.tables();
+        let is_cff = tables.cff.is_some();
+        if !is_cff && tables.glyf.is_none() {
+            return Err(PDFError::BadFont("Only TrueType or CFF outlines can be embedded".to_string()));
+        }
+
+        let postscript_name = face.names().into_iter()
+            .filter(|name| name.name_id == name_id::POST_SCRIPT_NAME)
+            .find_map(|name| name.to_string())
+            .unwrap_or_else(|| "Font".to_string());
+        let global_bounding_box = face.global_bounding_box();
+
+        let mut glyph_ids = HashMap::new();
+        if let Some(cmap) = tables.cmap {
+            for subtable in cmap.subtables.into_iter().filter(|subtable| subtable.is_unicode()) {
+                subtable.codepoints(|code_point| {
+                    let glyph_id = char::from_u32(code_point)
+                        .and_then(|character| Some( (character, subtable.glyph_index(code_point)?) ));
+                    if let Some((character, glyph_id)) = glyph_id {
+                        glyph_ids.entry(character).or_insert(glyph_id.0);
+                    }
+                });
+            }
+        }
+        let advance_widths = (0..face.number_of_glyphs())
+            .map(|glyph_id| face.glyph_hor_advance(GlyphId(glyph_id)).unwrap_or(0))
+            .collect();
+        let (advance_heights, vertical_origins) = read_vertical_metrics(&face);
+        let vertical_glyph_ids = read_vertical_substitutes(&face);
+
+        Ok(PDFFont {
+            postscript_name: make_name_safe(&postscript_name),
+            units_per_em: face.units_per_em() as f64,
+            ascender: face.ascender(),
+            descender: face.descender(),
+            cap_height: face.capital_height().unwrap_or_else(|| face.ascender()),
+            italic_angle: face.italic_angle() as f64,
+            bounding_box: [
+                global_bounding_box.x_min, global_bounding_box.y_min,
+                global_bounding_box.x_max, global_bounding_box.y_max,
+            ],
+            is_fixed_pitch: face.is_monospaced(),
+            is_italic: face.is_italic(),
+            is_cff,
+            glyph_ids,
+            advance_widths,
+            advance_heights,
+            vertical_origins,
+            vertical_glyph_ids,
+            font_bytes,
+            face_index,
+        })
+    }
+
+    pub fn has_glyph(&self, character: char) -> bool { self.glyph_ids.contains_key(&character) }
+    /// Any characters that the font doesn't have will use the missing glyph (0)
+    pub fn glyph_id(&self, character: char) -> u16 {
+        self.glyph_ids.get(&character).copied().unwrap_or(0)
+    }
+    /// In 1/1000ths of the font size
+    pub fn advance_width(&self, glyph_id: u16) -> f64 {
+        let advance_width = self.advance_widths.get(glyph_id as usize).copied().unwrap_or(0);
+        self.to_text_space(advance_width as f64)
+    }
+    /// How wide the text will be at this font size
+    pub fn text_width(&self, text: &str, font_size: f64) -> f64 {
+        let total_width: f64 = text.chars()
+            .map(|character| self.advance_width(self.glyph_id(character)))
+            .sum();
+        total_width * font_size / 1000.0
+    }
+    /// In 1/1000ths of the font size. Fonts without vertical metrics use 1 em for every glyph.
+    pub fn advance_height(&self, glyph_id: u16) -> f64 {
+        match self.advance_heights.get(glyph_id as usize) {
+            Some(&advance_height) => self.to_text_space(advance_height as f64),
+            None => 1000.0,
+        }
+    }
+    /// The version of the glyph that's meant for vertical text (like rotated brackets), if there is one
+    pub fn vertical_glyph_id(&self, glyph_id: u16) -> Option<u16> {
+        self.vertical_glyph_ids.get(&glyph_id).copied()
+    }
+    /// How tall the text will be when it's written vertically at this font size
+    pub fn vertical_text_height(&self, text: &str, font_size: f64) -> f64 {
+        crate::vertical_text::split_vertical_runs(self, text).iter()
+            .map(|vertical_run| vertical_run.height(self, font_size))
+            .sum()
+    }
+    /// How far the font goes above the baseline at this font size
+    pub fn ascent(&self, font_size: f64) -> f64 {
+        self.to_text_space(self.ascender as f64) * font_size / 1000.0
+    }
+    /// How far the font goes below the baseline at this font size (negative)
+    pub fn descent(&self, font_size: f64) -> f64 {
+        self.to_text_space(self.descender as f64) * font_size / 1000.0
+    }
+}
+impl PDFFont {
+    /// Scales font units to the 1/1000ths that PDF font metrics use
+    fn to_text_space(&self, font_units: f64) -> f64 {
+        font_units * 1000.0 / self.units_per_em
+    }
+    /// How far above the horizontal baseline the glyph's vertical origin is (in 1/1000ths)
+    fn vertical_origin(&self, glyph_id: u16) -> f64 {
+        let vertical_origin = self.vertical_origins.get(glyph_id as usize).copied()
+            .unwrap_or(self.ascender);
+        self.to_text_space(vertical_origin as f64)
+    }
+    /// Rounded off for the font dictionaries
+    fn to_text_units(&self, font_units: i16) -> i64 {
+        self.to_text_space(font_units as f64).round() as i64
+    }
+
+    fn make_font_descriptor(&self, font_name: &Name, font_file_ref: ObjectId) -> Dictionary {
+        // Always symbolic since the glyphs aren't from the standard Latin character set
+        let mut flags = 4;
+        if self.is_fixed_pitch { flags |= 1; }
+        if self.is_italic { flags |= 64; }
+
+        let mut font_descriptor = Dictionary::new();
+        font_descriptor.insert(Name::type_name(), Name::font_descriptor());
+        font_descriptor.insert(Name::font_name(), font_name.clone());
+        font_descriptor.insert(Name::flags(), flags);
+        let bounding_box: Vec<Object> = self.bounding_box.iter()
+            .map(|&edge| self.to_text_units(edge).into())
+            .collect();
+        font_descriptor.insert(Name::font_b_box(), bounding_box);
+        font_descriptor.insert(Name::italic_angle(), self.italic_angle);
+        font_descriptor.insert(Name::ascent(), self.to_text_units(self.ascender));
+        font_descriptor.insert(Name::descent(), self.to_text_units(self.descender));
+        font_descriptor.insert(Name::cap_height(), self.to_text_units(self.cap_height));
+        // Fonts don't store this, so use a common guess like every other writer
+        font_descriptor.insert(Name::stem_v(), 80);
+        let font_file_key = if self.is_cff { Name::font_file3() } else { Name::font_file2() };
+        font_descriptor.insert(font_file_key, font_file_ref);
+        font_descriptor
+    }
+}
+
+/// Fragment writers use the same subsets as the document they get appended to
+pub type SharedFontSubset = Arc< Mutex<FontSubset> >;
+/// Nothing panics part way through changing a subset, so it's still fine if something else panicked
+pub fn lock_subset(font_subset: &SharedFontSubset) -> MutexGuard<'_, FontSubset> {
+    font_subset.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The glyphs of a font that the document has used so far
+pub struct FontSubset {
+    font: Arc<PDFFont>,
+    remapper: GlyphRemapper,
+    /// The text for each of the new glyph IDs
+    to_unicode: BTreeMap<u16, String>,
+    vertical_font_id: ObjectId,
+    /// The vertical font only gets written if a page used it
+    is_used_vertically: bool,
+}
+impl FontSubset {
+    pub fn new(font: Arc<PDFFont>, vertical_font_id: ObjectId) -> FontSubset {
+        FontSubset {
+            font,
+            remapper: GlyphRemapper::new(),
+            to_unicode: BTreeMap::new(),
+            vertical_font_id,
+            is_used_vertically: false,
+        }
+    }
+
+    /// Only there if a page used the font for vertical text
+    pub fn vertical_font_id(&self) -> Option<ObjectId> {
+        if self.is_used_vertically { Some(self.vertical_font_id) } else { None }
+    }
+    pub fn set_used_vertically(&mut self) { self.is_used_vertically = true; }
+    /// The ID is kept for the vertical font even if it never gets written
+    pub fn reserved_vertical_font_id(&self) -> ObjectId { self.vertical_font_id }
+
+    /// Gives back the glyph ID in the subset font (which is also the CID)
+    pub fn use_glyph(&mut self, glyph_id: u16, text: &str) -> u16 {
+        let new_glyph_id = self.remapper.remap(glyph_id);
+        if new_glyph_id != 0 {
+            self.to_unicode.entry(new_glyph_id).or_insert_with(|| text.to_string());
+        }
+        new_glyph_id
+    }
+    /// Uses every glyph that the text could be shown with, both horizontally and vertically
+    pub fn use_text(&mut self, text: &str) {
+        for character in text.chars() {
+            let mut character_bytes = [0; 4];
+            let character_text = character.encode_utf8(&mut character_bytes);
+            let glyph_id = self.font.glyph_id(character);
+            self.use_glyph(glyph_id, character_text);
+            if let Some(vertical_glyph_id) = self.font.vertical_glyph_id(glyph_id) {
+                self.use_glyph(vertical_glyph_id, character_text);
+            }
+        }
+    }
+
+    pub fn make_font_file(&self, flate_level: FlateLevel) -> PDFResult<Stream> {
+        let subset_bytes = subsetter::subset(&self.font.font_bytes, self.font.face_index, &self.remapper)
+            .map_err(|error| PDFError::BadFont(error.to_string()))?;
+        let mut font_file_dictionary = Dictionary::new();
+        let font_file_bytes = if self.font.is_cff {
+            // Only the CFF table gets embedded for OpenType fonts with CFF outlines
+            font_file_dictionary.insert(Name::subtype(), Name::new("CIDFontType0C"));
+            RawFace::parse(&subset_bytes, 0).ok()
+                .and_then(|raw_face| raw_face.table(Tag::from_bytes(b"CFF ")))
+                .ok_or_else(|| PDFError::BadFont("The subset is missing its CFF table".to_string()))?
+                .to_vec()
+        } else {
+            font_file_dictionary.insert(Name::length1(), subset_bytes.len());
+            subset_bytes
+        };
+        let compressed_font_file = crate::utils::flate_compress(&font_file_bytes, None, flate_level)?;
+        font_file_dictionary.insert(Name::filter(), Name::flate_decode());
+        Ok(Stream::new(font_file_dictionary, compressed_font_file))
+    }
+    pub fn make_font_descriptor(&self, font_file_ref: ObjectId) -> Dictionary {
+        self.font.make_font_descriptor(&self.font_name(), font_file_ref)
+    }
+    /// `CIDFontType2` for TrueType outlines or `CIDFontType0` for CFF outlines
+    pub fn make_cid_font(&self, font_descriptor_ref: ObjectId) -> Dictionary {
+        let mut cid_system_info = Dictionary::new();
+        cid_system_info.insert(Name::registry(), "Adobe");
+        cid_system_info.insert(Name::ordering(), "Identity");
+        cid_system_info.insert(Name::supplement(), 0);
+
+        // Every CID is used (starting from 0), so the widths are one long run
+        let widths: Vec<Object> = self.remapper.remapped_gids()
+            .map(|glyph_id| (self.font.advance_width(glyph_id).round() as i64).into())
+            .collect();
+
+        let mut cid_font = Dictionary::new();
+        cid_font.insert(Name::type_name(), Name::font());
+        if self.font.is_cff {
+            cid_font.insert(Name::subtype(), Name::new("CIDFontType0"));
+        } else {
+            cid_font.insert(Name::subtype(), Name::new("CIDFontType2"));
+            // The subset font's glyph IDs are the CIDs
+            cid_font.insert(Name::cid_to_gid_map(), Name::new("Identity"));
+        }
+        cid_font.insert(Name::base_font(), self.font_name());
+        cid_font.insert(Name::cid_system_info(), cid_system_info);
+        cid_font.insert(Name::font_descriptor(), font_descriptor_ref);
+        cid_font.insert(Name::dw(), 1000);
+        cid_font.insert(Name::w(), vec![Object::from(0), widths.into()]);
+        if self.is_used_vertically {
+            // Each CID gets its vertical advance and where its vertical origin is
+            let vertical_metrics: Vec<Object> = self.remapper.remapped_gids()
+                .flat_map(|glyph_id| vec![
+                    -(self.font.advance_height(glyph_id).round() as i64),
+                    (self.font.advance_width(glyph_id) / 2.0).round() as i64,
+                    self.font.vertical_origin(glyph_id).round() as i64,
+                ])
+                .map(Object::from)
+                .collect();
+            cid_font.insert(Name::w2(), vec![Object::from(0), vertical_metrics.into()]);
+        }
+        cid_font
+    }
+    /// Maps each CID back to its text so that it can be copied or searched
+    pub fn make_to_unicode_cmap(&self, flate_level: FlateLevel) -> PDFResult<Stream> {
+        let mut cmap: Vec<u8> = Vec::new();
+        cmap.write_all(b"/CIDInit /ProcSet findresource begin\n\
+            12 dict begin\n\
+            begincmap\n\
+            /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+            /CMapName /Adobe-Identity-UCS def\n\
+            /CMapType 2 def\n\
+            1 begincodespacerange\n<0000> <FFFF>\nendcodespacerange\n")?;
+        let mappings: Vec<(&u16, &String)> = self.to_unicode.iter().collect();
+        // Each section can only have up to 100 mappings
+        for chunk in mappings.chunks(100) {
+            writeln!(&mut cmap, "{} beginbfchar", chunk.len())?;
+            for (cid, text) in chunk {
+                write!(&mut cmap, "<{:04X}> <", cid)?;
+                for utf16_part in text.encode_utf16() {
+                    write!(&mut cmap, "{:04X}", utf16_part)?;
+                }
+                cmap.write_all(b">\n")?;
+            }
+            cmap.write_all(b"endbfchar\n")?;
+        }
+        cmap.write_all(b"endcmap\n\
+            CMapName currentdict /CMap defineresource pop\n\
+            end\n\
+            end\n")?;
+
+        let compressed_cmap = crate::utils::flate_compress(&cmap, None, flate_level)?;
+        let mut stream_dictionary = Dictionary::new();
+        stream_dictionary.insert(Name::filter(), Name::flate_decode());
+        Ok(Stream::new(stream_dictionary, compressed_cmap))
+    }
+    pub fn make_type0_font(&self, cid_font_ref: ObjectId, to_unicode_ref: ObjectId) -> Dictionary {
+        self.make_type0_font_with_encoding(cid_font_ref, to_unicode_ref, Name::new("Identity-H"))
+    }
+    /// Shares the CID font with the horizontal font, but writes from top to bottom
+    pub fn make_vertical_type0_font(&self, cid_font_ref: ObjectId, to_unicode_ref: ObjectId) -> Dictionary {
+        self.make_type0_font_with_encoding(cid_font_ref, to_unicode_ref, Name::new("Identity-V"))
+    }
+}
+impl FontSubset {
+    fn make_type0_font_with_encoding(&self, cid_font_ref: ObjectId, to_unicode_ref: ObjectId,
+    encoding: Name) -> Dictionary {
+        let mut type0_font = Dictionary::new();
+        type0_font.insert(Name::type_name(), Name::font());
+        type0_font.insert(Name::subtype(), Name::new("Type0"));
+        type0_font.insert(Name::base_font(), self.font_name());
+        type0_font.insert(Name::encoding(), encoding);
+        type0_font.insert(Name::descendant_fonts(), vec![cid_font_ref]);
+        type0_font.insert(Name::to_unicode(), to_unicode_ref);
+        type0_font
+    }
+    /// A subset font's name starts with a tag that's different for each subset (like `ABCDEF+`).
+    /// The same subset always gets the same tag, so the output is reproducible.
+    fn font_name(&self) -> Name {
+        let glyph_bytes: Vec<u8> = self.remapper.remapped_gids()
+            .flat_map(|glyph_id| glyph_id.to_be_bytes())
+            .collect();
+        let mut hash = crate::utils::content_hash(&[self.font.postscript_name.as_bytes(), &glyph_bytes]);
+        let subset_tag: String = (0..6).map(|_| {
+            let letter = (b'A' + (hash % 26) as u8) as char;
+            hash /= 26;
+            letter
+        }).collect();
+        Name::new(format!("{}+{}", subset_tag, self.font.postscript_name))
+    }
+}
+
+/// Fonts can have spaces and other characters in their names that aren't allowed in `/BaseFont`
+fn make_name_safe(postscript_name: &str) -> String {
+    postscript_name.chars()
+        .filter(|character| character.is_ascii_graphic() &&
+            !crate::utils::DELIMITER_CHARS.contains(&(*character as u8)))
+        .collect()
+}
+
+/// Gives back the advance heights and vertical origins, which are both empty if the font doesn't
+/// have vertical metrics
+fn read_vertical_metrics(face: &Face) -> (Vec<u16>, Vec<i16>) {
+    if face.tables().vmtx.is_none() {
+        return (Vec::new(), Vec::new());
+    }
+    let advance_heights = (0..face.number_of_glyphs())
+        .map(|glyph_id| face.glyph_ver_advance(GlyphId(glyph_id)).unwrap_or(0))
+        .collect();
+    let vertical_origins = (0..face.number_of_glyphs())
+        .map(|glyph_id| {
+            let glyph_id = GlyphId(glyph_id);
+            // CFF fonts usually have a VORG table. Otherwise it's the space above the glyph's top.
+            face.glyph_y_origin(glyph_id)
+                .or_else(|| Some(face.glyph_ver_side_bearing(glyph_id)? +
+                    face.glyph_bounding_box(glyph_id)?.y_max))
+                .unwrap_or_else(|| face.vertical_ascender().unwrap_or_else(|| face.ascender()))
+        })
+        .collect();
+    (advance_heights, vertical_origins)
+}
+
+/// Only single substitutions make sense without doing full text shaping
+fn read_vertical_substitutes(face: &Face) -> HashMap<u16, u16> {
+    let mut vertical_glyph_ids = HashMap::new();
+    let gsub = match face.tables().gsub {
+        Some(gsub) => gsub,
+        None => return vertical_glyph_ids,
+    };
+    let vertical_features = (0..gsub.features.len())
+        .filter_map(|feature_index| gsub.features.get(feature_index))
+        .filter(|feature| feature.tag == Tag::from_bytes(b"vert") || feature.tag == Tag::from_bytes(b"vrt2"));
+    for feature in vertical_features {
+        let lookups = feature.lookup_indices.into_iter()
+            .filter_map(|lookup_index| gsub.lookups.get(lookup_index));
+        for lookup in lookups {
+            for subtable in lookup.subtables.into_iter::<SubstitutionSubtable>() {
+                let single_substitution = match subtable {
+                    SubstitutionSubtable::Single(single_substitution) => single_substitution,
+                    _ => continue,
+                };
+                for glyph_id in 0..face.number_of_glyphs() {
+                    let vertical_glyph_id = match single_substitution {
+                        SingleSubstitution::Format1 { coverage, delta } => coverage.get(GlyphId(glyph_id))
+                            .map(|_| (glyph_id as i32 + delta as i32) as u16),
+                        SingleSubstitution::Format2 { coverage, substitutes } => coverage.get(GlyphId(glyph_id))
+                            .and_then(|coverage_index| substitutes.get(coverage_index))
+                            .map(|substitute| substitute.0),
+                    };
+                    if let Some(vertical_glyph_id) = vertical_glyph_id {
+                        vertical_glyph_ids.entry(glyph_id).or_insert(vertical_glyph_id);
+                    }
+                }
+            }
+        }
+    }
+    vertical_glyph_ids
+}
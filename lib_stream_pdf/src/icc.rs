@@ -0,0 +1,185 @@
+use std::{
+    ops::{Range},
+};
+use image::{
+    DynamicImage, GrayAlphaImage, GrayImage, ImageError, ImageFormat, RgbImage, RgbaImage,
+    error::{DecodingError},
+};
+use moxcms::{
+    CmsError, ColorProfile, DataColorSpace, Layout, LocalizableString, ProfileText, TransformOptions,
+};
+use crate::{
+    PDFError, PDFResult,
+    Name, Dictionary, Stream,
+    compression::{FlateLevel},
+};
+
+/// The gamma of the standard gray profile
+const GRAY_GAMMA: f32 = 2.2;
+/// Where the creation date goes in a profile's header
+const PROFILE_DATE_RANGE: Range<usize> = 24..36;
+/// 2000-01-01 00:00:00, as big endian year, month, day, hours, minutes and seconds
+const STANDARD_PROFILE_DATE: [u8; 12] = [0x07, 0xD0, 0, 1, 0, 1, 0, 0, 0, 0, 0, 0];
+
+/// What happens to the colour profiles of the source images
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColourProfiles {
+    /// Every image keeps its own profile. Each different profile only gets written once.
+    Keep,
+    /// The colours get changed to sRGB (or Gray Gamma 2.2 for gray images), so only those profiles
+    /// get written. CMYK images without a profile are left as they are.
+    ConvertToStandard,
+}
+
+/// Finds the ICC profile in a JPEG or PNG. Profiles that can't be read are ignored.
+pub fn read_icc_profile(image_bytes: &[u8]) -> Option<Vec<u8>> {
+    let profile = crate::jpeg::read_icc_profile(image_bytes)
+        .or_else(|| crate::png::read_icc_profile(image_bytes))?;
+    ColorProfile::new_from_slice(&profile).ok()?;
+    Some(profile)
+}
+
+/// 1 for gray, 3 for RGB and 4 for CMYK, from the colour space in the profile's header
+pub fn profile_components(profile: &[u8]) -> Option<u8> {
+    match profile.get(16..20)? {
+        b"GRAY" => Some(1),
+        b"RGB " => Some(3),
+        b"CMYK" => Some(4),
+        _ => None,
+    }
+}
+
+/// The profile for images with this many components after they're converted (there isn't one for CMYK)
+pub fn standard_profile(components: u8) -> Option<Vec<u8>> {
+    let profile = match components {
+        1 => {
+            let mut profile = ColorProfile::new_gray_with_gamma(GRAY_GAMMA);
+            profile.description = Some(ProfileText::Localizable(vec![LocalizableString::new(
+                "en".to_string(), "US".to_string(), "Gray Gamma 2.2".to_string(),
+            )]));
+            profile
+        },
+        3 => ColorProfile::new_srgb(),
+        _ => return None,
+    };
+    // Encoding the built in profiles can't fail
+    let mut profile = profile.encode().ok()?;
+    // They're dated when they're encoded, so without a fixed date the same profile would be different
+    // every second, and get written again
+    profile[PROFILE_DATE_RANGE].copy_from_slice(&STANDARD_PROFILE_DATE);
+    Some(profile)
+}
+
+/// Profile streams are the only ones with the number of components (`/N`) and no `/Type`
+pub fn is_profile_stream(dictionary: &Dictionary) -> bool {
+    dictionary.get(&Name::n()).is_some() && dictionary.get(&Name::type_name()).is_none()
+}
+/// An ICCBased colour space, which uses the device colour space as its alternate
+pub fn make_profile_stream(profile: &[u8], flate_level: FlateLevel) -> PDFResult<Stream> {
+    let components = profile_components(profile)
+        .ok_or_else(|| PDFError::BadColourProfile("The colour space isn't gray, RGB or CMYK".to_string()))?;
+    let alternate = match components {
+        1 => Name::device_gray(),
+        3 => Name::device_rgb(),
+        _ => Name::device_cmyk(),
+    };
+    let mut profile_dictionary = Dictionary::new();
+    profile_dictionary.insert(Name::n(), components);
+    profile_dictionary.insert(Name::alternate(), alternate);
+    profile_dictionary.insert(Name::filter(), Name::flate_decode());
+    let compressed_profile = crate::utils::flate_compress(profile, None, flate_level)?;
+    Ok(Stream::new(profile_dictionary, compressed_profile))
+}
+
+/// Converts a grid of test colours and sees if any of them change. Images with a standard profile
+/// don't have to be decoded to be converted.
+pub fn is_standard(profile: &[u8]) -> bool {
+    const STEPS: u8 = 16;
+    let (components, layout) = match profile_components(profile) {
+        Some(1) => (1, Layout::Gray),
+        Some(3) => (3, Layout::Rgb),
+        _ => return false,
+    };
+    let levels: Vec<u8> = (0..=STEPS)
+        .map(|step| (step as u32 * u8::MAX as u32 / STEPS as u32) as u8)
+        .collect();
+    let mut test_samples = Vec::new();
+    if components == 1 {
+        test_samples.extend_from_slice(&levels);
+    } else {
+        for &red in levels.iter() {
+            for &green in levels.iter() {
+                for &blue in levels.iter() {
+                    test_samples.extend_from_slice(&[red, green, blue]);
+                }
+            }
+        }
+    }
+    match transform(profile, layout, &test_samples, components) {
+        Ok(converted_samples) => test_samples.iter().zip(&converted_samples)
+            .all(|(&sample, &converted_sample)| sample.abs_diff(converted_sample) <= 1),
+        Err(_) => false,
+    }
+}
+
+/// Changes the colours of a decoded gray or RGB image from its profile to the standard one.
+/// Any alpha is kept as it is.
+pub fn convert_to_standard(image: DynamicImage, profile: &[u8]) -> PDFResult<DynamicImage> {
+    let (width, height) = (image.width(), image.height());
+    let converted_image = match (profile_components(profile), image.color().has_alpha()) {
+        (Some(1), false) => {
+            let samples = transform(profile, Layout::Gray, image.into_luma8().as_raw(), 1)?;
+            GrayImage::from_raw(width, height, samples).map(DynamicImage::ImageLuma8)
+        },
+        (Some(1), true) => {
+            let samples = transform(profile, Layout::GrayAlpha, image.into_luma_alpha8().as_raw(), 1)?;
+            GrayAlphaImage::from_raw(width, height, samples).map(DynamicImage::ImageLumaA8)
+        },
+        (Some(3), false) => {
+            let samples = transform(profile, Layout::Rgb, image.into_rgb8().as_raw(), 3)?;
+            RgbImage::from_raw(width, height, samples).map(DynamicImage::ImageRgb8)
+        },
+        (Some(3), true) => {
+            let samples = transform(profile, Layout::Rgba, image.into_rgba8().as_raw(), 3)?;
+            RgbaImage::from_raw(width, height, samples).map(DynamicImage::ImageRgba8)
+        },
+        _ => return Err(PDFError::BadColourProfile("Only gray and RGB images can be converted".to_string())),
+    };
+    // The transform gives back as many samples as it was given
+    Ok(converted_image.unwrap())
+}
+
+/// Decodes a CMYK JPEG itself, since the image crate only gives back RGB, and converts it to sRGB
+pub fn convert_cmyk_jpeg_to_standard(jpeg_bytes: &[u8], profile: &[u8]) -> PDFResult<DynamicImage> {
+    let mut decoder = jpeg_decoder::Decoder::new(jpeg_bytes);
+    let cmyk_samples = decoder.decode()
+        .map_err(|error| ImageError::Decoding(DecodingError::new(ImageFormat::Jpeg.into(), error)))?;
+    // The info is always there once it's been decoded
+    let info = decoder.info().unwrap();
+    if info.pixel_format != jpeg_decoder::PixelFormat::CMYK32 || profile_components(profile) != Some(4) {
+        return Err(PDFError::BadColourProfile("The JPEG and its profile have to be CMYK".to_string()));
+    }
+    let rgb_samples = transform(profile, Layout::Rgba, &cmyk_samples, 3)?;
+    let rgb_image = RgbImage::from_raw(info.width as u32, info.height as u32, rgb_samples).unwrap();
+    Ok(DynamicImage::ImageRgb8(rgb_image))
+}
+
+/// Converts the samples to the standard profile with the given number of colour components.
+/// Alpha stays in the output if the layout has it.
+fn transform(profile: &[u8], layout: Layout, samples: &[u8], standard_components: u8) -> PDFResult<Vec<u8>> {
+    let to_pdf_error = |error: CmsError| PDFError::BadColourProfile(error.to_string());
+    let source_profile = ColorProfile::new_from_slice(profile).map_err(to_pdf_error)?;
+    let (standard_profile, standard_layout) = match (standard_components, layout) {
+        (1, Layout::GrayAlpha) => (ColorProfile::new_gray_with_gamma(GRAY_GAMMA), Layout::GrayAlpha),
+        (1, _) => (ColorProfile::new_gray_with_gamma(GRAY_GAMMA), Layout::Gray),
+        (_, Layout::Rgba) if source_profile.color_space != DataColorSpace::Cmyk => (ColorProfile::new_srgb(), Layout::Rgba),
+        _ => (ColorProfile::new_srgb(), Layout::Rgb),
+    };
+    let transform = source_profile
+        .create_transform_8bit(layout, &standard_profile, standard_layout, TransformOptions::default())
+        .map_err(to_pdf_error)?;
+    let pixel_count = samples.len() / layout.channels();
+    let mut converted_samples = vec![0; pixel_count * standard_layout.channels()];
+    transform.transform(samples, &mut converted_samples).map_err(to_pdf_error)?;
+    Ok(converted_samples)
+}
@@ -0,0 +1,81 @@
+use crate::{
+    compression::{CompressionOptions},
+    icc::{ColourProfiles},
+};
+
+/// Which encoding an image ended up with
+#[derive(Debug, Copy, Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ImageEncoding {
+    /// The JPEG or PNG data was used as it was
+    Passthrough,
+    Jpeg,
+    Flate,
+    /// One bit for each pixel (with JBIG2, CCITT Group 4 or Flate)
+    Bilevel,
+    /// Indexes into a palette
+    Indexed,
+}
+
+/// How to choose the encoding of an image. Every encoding that's close enough to the original gets
+/// tried, and the smallest one is kept.
+#[derive(Clone, Debug)]
+pub struct EncodingPolicy {
+    compression: CompressionOptions,
+    min_psnr: f64,
+    quantise_tolerance: u8,
+    colour_profiles: ColourProfiles,
+}
+impl Default for EncodingPolicy {
+    fn default() -> EncodingPolicy {
+        EncodingPolicy {
+            compression: CompressionOptions::new(),
+            min_psnr: 40.0,
+            quantise_tolerance: 8,
+            colour_profiles: ColourProfiles::Keep,
+        }
+    }
+}
+impl EncodingPolicy {
+    pub fn new() -> EncodingPolicy {
+        EncodingPolicy::default()
+    }
+    /// The Flate level and JPEG settings that each encoding is made with
+    pub fn with_compression(mut self, compression: CompressionOptions) -> EncodingPolicy {
+        self.compression = compression;
+        self
+    }
+    /// The lowest PSNR (in dB) that a lossy encoding can have compared to the original.
+    /// Infinity only allows lossless encodings.
+    pub fn with_min_psnr(mut self, min_psnr: f64) -> EncodingPolicy {
+        self.min_psnr = min_psnr;
+        self
+    }
+    /// How far each colour component can be moved to fit the image in a palette
+    pub fn with_quantise_tolerance(mut self, quantise_tolerance: u8) -> EncodingPolicy {
+        self.quantise_tolerance = quantise_tolerance;
+        self
+    }
+    pub fn with_colour_profiles(mut self, colour_profiles: ColourProfiles) -> EncodingPolicy {
+        self.colour_profiles = colour_profiles;
+        self
+    }
+
+    pub fn compression(&self) -> &CompressionOptions { &self.compression }
+    pub fn min_psnr(&self) -> f64 { self.min_psnr }
+    pub fn quantise_tolerance(&self) -> u8 { self.quantise_tolerance }
+    pub fn colour_profiles(&self) -> ColourProfiles { self.colour_profiles }
+    pub fn allows_lossy(&self) -> bool { self.min_psnr.is_finite() }
+}
+
+/// The peak signal-to-noise ratio (in dB) between the samples of two images. It's infinite if they
+/// are the same.
+pub fn psnr(original: &[u8], changed: &[u8]) -> f64 {
+    let squared_error: u64 = original.iter().zip(changed)
+        .map(|(&sample1, &sample2)| (sample1.abs_diff(sample2) as u64).pow(2))
+        .sum();
+    if squared_error == 0 {
+        return f64::INFINITY;
+    }
+    let mean_squared_error = squared_error as f64 / original.len() as f64;
+    10.0 * (u8::MAX as f64).powi(2).log10() - 10.0 * mean_squared_error.log10()
+}
@@ -0,0 +1,333 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fs,
+    path::{PathBuf},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    thread::{self, JoinHandle},
+};
+use crate::{
+    PDFError, PDFImage, PDFResult,
+    compression::{CompressionOptions},
+    image_encoding::{EncodingPolicy},
+};
+
+/// Decoded images are taken to use this much for each pixel (RGBA)
+const DECODED_BYTES_PER_PIXEL: u64 = 4;
+
+/// Makes a `PDFImage` out of the bytes of an image file
+pub type PrepareImage = Arc<dyn Fn(Vec<u8>) -> PDFResult<PDFImage> + Send + Sync>;
+
+#[derive(Clone)]
+enum Preparation {
+    Lossless(bool, CompressionOptions),
+    Policy(EncodingPolicy),
+    Custom(PrepareImage),
+}
+
+/// An image file for the pipeline to make into a `PDFImage`
+#[derive(Clone)]
+pub struct ImageJob {
+    path: PathBuf,
+    preparation: Preparation,
+}
+impl ImageJob {
+    /// Gets prepared like `PDFImage::from_path`
+    pub fn new(path: impl Into<PathBuf>, lossless: bool) -> ImageJob {
+        ImageJob::with_compression(path, lossless, CompressionOptions::new())
+    }
+    /// Gets prepared like `PDFImage::from_bytes_with_compression`
+    pub fn with_compression(path: impl Into<PathBuf>, lossless: bool, compression: CompressionOptions) -> ImageJob {
+        ImageJob { path: path.into(), preparation: Preparation::Lossless(lossless, compression) }
+    }
+    /// Gets prepared like `PDFImage::from_path_with_policy`
+    pub fn with_policy(path: impl Into<PathBuf>, policy: EncodingPolicy) -> ImageJob {
+        ImageJob { path: path.into(), preparation: Preparation::Policy(policy) }
+    }
+    /// Gets prepared by the function, like with `PDFImage::from_image_jbig2` on the decoded image
+    pub fn with_function(path: impl Into<PathBuf>, prepare: PrepareImage) -> ImageJob {
+        ImageJob { path: path.into(), preparation: Preparation::Custom(prepare) }
+    }
+
+    fn prepare(&self, image_bytes: Vec<u8>) -> PDFResult<PDFImage> {
+        let pdf_image = match &self.preparation {
+            Preparation::Lossless(lossless, compression) =>
+                PDFImage::from_bytes_with_compression(image_bytes, *lossless, compression),
+            Preparation::Policy(policy) => PDFImage::from_bytes_with_policy(image_bytes, policy),
+            Preparation::Custom(prepare) => prepare(image_bytes),
+        };
+        crate::pdf_image::add_path_to_error(pdf_image, &self.path)
+    }
+}
+
+/// Prepares images on worker threads, so they can be decoded and encoded while the document is
+/// being written. The images come back in the same order as the jobs, so the document is the same as
+/// when they're made one at a time.
+#[derive(Clone, Debug)]
+pub struct ImagePipeline {
+    thread_count: usize,
+    memory_budget: u64,
+}
+impl Default for ImagePipeline {
+    fn default() -> ImagePipeline {
+        ImagePipeline {
+            thread_count: thread::available_parallelism().map(|count| count.get()).unwrap_or(1),
+            memory_budget: 1 << 30,
+        }
+    }
+}
+impl ImagePipeline {
+    pub fn new() -> ImagePipeline {
+        ImagePipeline::default()
+    }
+    /// Defaults to the number of cores
+    pub fn with_thread_count(mut self, thread_count: usize) -> ImagePipeline {
+        self.thread_count = thread_count.max(1);
+        self
+    }
+    /// Roughly how many bytes the images being decoded and the images waiting to be taken can use.
+    /// The next image to be taken (from each group) is always prepared, even if it goes over.
+    /// Defaults to 1 GiB.
+    pub fn with_memory_budget(mut self, memory_budget: u64) -> ImagePipeline {
+        self.memory_budget = memory_budget;
+        self
+    }
+
+    /// Starts preparing the images. Any that haven't been prepared yet are skipped once the
+    /// `PreparedImages` is dropped.
+    pub fn prepare(&self, jobs: Vec<ImageJob>) -> PreparedImages {
+        // There's always a group for the jobs
+        self.prepare_groups(vec![jobs]).pop().unwrap()
+    }
+    /// Like `prepare`, but each group of jobs gets its own `PreparedImages`, so the groups can be
+    /// taken on different threads (like when each one is a fragment). They all share the same worker
+    /// threads and memory budget, and the jobs are started from the first group to the last.
+    pub fn prepare_groups(&self, job_groups: Vec< Vec<ImageJob> >) -> Vec<PreparedImages> {
+        let job_count: usize = job_groups.iter().map(Vec::len).sum();
+        let thread_count = self.thread_count.min(job_count);
+        let groups: Vec<GroupState> = job_groups.iter()
+            .map(|jobs| GroupState {
+                job_paths: jobs.iter().map(|job| job.path.clone()).collect(),
+                next_image: 0,
+                prepared_images: BTreeMap::new(),
+                dropped: false,
+            })
+            .collect();
+        let group_count = groups.len();
+        let jobs = job_groups.into_iter().enumerate()
+            .flat_map(|(group, jobs)| {
+                jobs.into_iter().enumerate().map(move |(index, job)| (JobId { group, index }, job))
+            })
+            .collect();
+        let pipeline = Arc::new(Pipeline {
+            state: Mutex::new(PipelineState {
+                jobs,
+                groups,
+                memory_used: 0,
+                running_workers: thread_count,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+            memory_budget: self.memory_budget,
+        });
+        let handles = (0..thread_count)
+            .map(|_| {
+                let pipeline = pipeline.clone();
+                thread::spawn(move || pipeline.run_worker())
+            })
+            .collect();
+        let workers = Arc::new(Workers { pipeline: pipeline.clone(), handles });
+        (0..group_count)
+            .map(|group| PreparedImages { pipeline: pipeline.clone(), _workers: workers.clone(), group })
+            .collect()
+    }
+}
+
+/// Gives back each image in the order of the jobs, waiting for it to be prepared if it needs to
+pub struct PreparedImages {
+    pipeline: Arc<Pipeline>,
+    /// The threads are stopped once every group has been dropped
+    _workers: Arc<Workers>,
+    group: usize,
+}
+impl Iterator for PreparedImages {
+    type Item = PDFResult<PDFImage>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut state = self.pipeline.lock();
+        loop {
+            let state_fields = &mut *state;
+            let group = &mut state_fields.groups[self.group];
+            let next_image = group.next_image;
+            if next_image == group.job_paths.len() {
+                return None;
+            }
+            if let Some((pdf_image, memory_size)) = group.prepared_images.remove(&next_image) {
+                group.next_image += 1;
+                state_fields.memory_used -= memory_size;
+                self.pipeline.changed.notify_all();
+                return Some(pdf_image);
+            }
+            // Workers only stop early if they panicked, and then the rest of the images can't be prepared
+            if state_fields.running_workers == 0 {
+                group.next_image += 1;
+                return Some(Err(PDFError::ImageWorkerStopped(group.job_paths[next_image].clone())));
+            }
+            state = self.pipeline.wait(state);
+        }
+    }
+}
+impl Drop for PreparedImages {
+    fn drop(&mut self) {
+        let mut state = self.pipeline.lock();
+        let state_fields = &mut *state;
+        state_fields.jobs.retain(|(job_id, _)| job_id.group != self.group);
+        let group = &mut state_fields.groups[self.group];
+        group.dropped = true;
+        let memory_size: u64 = group.prepared_images.values().map(|&(_, memory_size)| memory_size).sum();
+        group.prepared_images.clear();
+        state_fields.memory_used -= memory_size;
+        self.pipeline.changed.notify_all();
+    }
+}
+
+/// The worker threads, which are shared by every group
+struct Workers {
+    pipeline: Arc<Pipeline>,
+    handles: Vec< JoinHandle<()> >,
+}
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.pipeline.lock().stopped = true;
+        self.pipeline.changed.notify_all();
+        for handle in self.handles.drain(..) {
+            // Any panic was already reported by the worker's thread
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct JobId {
+    group: usize,
+    /// The index within the group
+    index: usize,
+}
+
+struct GroupState {
+    /// For the errors of images that a worker couldn't finish
+    job_paths: Vec<PathBuf>,
+    /// The index of the next image to be taken
+    next_image: usize,
+    /// Keyed by the job index, with how much memory each one is using
+    prepared_images: BTreeMap<usize, (PDFResult<PDFImage>, u64)>,
+    /// Once the `PreparedImages` is dropped, its images aren't needed anymore
+    dropped: bool,
+}
+
+struct PipelineState {
+    /// The jobs that haven't been started
+    jobs: VecDeque<(JobId, ImageJob)>,
+    groups: Vec<GroupState>,
+    memory_used: u64,
+    running_workers: usize,
+    stopped: bool,
+}
+
+struct Pipeline {
+    state: Mutex<PipelineState>,
+    /// Gets notified whenever an image is prepared or taken, or the pipeline is stopped
+    changed: Condvar,
+    memory_budget: u64,
+}
+impl Pipeline {
+    /// Workers don't panic while they hold the lock, so the state is still fine if one did
+    fn lock(&self) -> MutexGuard<'_, PipelineState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+    fn wait<'a>(&self, state: MutexGuard<'a, PipelineState>) -> MutexGuard<'a, PipelineState> {
+        self.changed.wait(state).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn run_worker(&self) {
+        let mut worker_guard = WorkerGuard { pipeline: self, current_job: None };
+        loop {
+            let (job_id, job) = {
+                let mut state = self.lock();
+                match state.jobs.pop_front() {
+                    Some(job) if !state.stopped => job,
+                    _ => return,
+                }
+            };
+            worker_guard.current_job = Some((job_id, 0));
+            let image_bytes = fs::read(&job.path);
+            let memory_estimate = match &image_bytes {
+                Ok(image_bytes) => estimate_memory(image_bytes),
+                Err(_) => 0,
+            };
+            {
+                let mut state = self.lock();
+                // The next image of each group is always prepared, so that it can't get stuck
+                while !state.stopped && !state.groups[job_id.group].dropped
+                && job_id.index != state.groups[job_id.group].next_image
+                && state.memory_used + memory_estimate > self.memory_budget {
+                    state = self.wait(state);
+                }
+                if state.stopped {
+                    return;
+                }
+                if state.groups[job_id.group].dropped {
+                    worker_guard.current_job = None;
+                    continue;
+                }
+                state.memory_used += memory_estimate;
+            }
+            worker_guard.current_job = Some((job_id, memory_estimate));
+
+            let pdf_image = image_bytes
+                .map_err(Into::into)
+                .and_then(|image_bytes| job.prepare(image_bytes));
+            let memory_size = pdf_image.as_ref().map(crate::pdf_image::memory_size).unwrap_or(0);
+            let mut state = self.lock();
+            state.memory_used -= memory_estimate;
+            let state_fields = &mut *state;
+            let group = &mut state_fields.groups[job_id.group];
+            if !group.dropped {
+                state_fields.memory_used += memory_size;
+                group.prepared_images.insert(job_id.index, (pdf_image, memory_size));
+            }
+            worker_guard.current_job = None;
+            self.changed.notify_all();
+        }
+    }
+}
+
+/// Lets the `PreparedImages` know when a worker stops. If it panicked, the image it was preparing
+/// gets an error and its memory is given back, so the other workers can keep going.
+struct WorkerGuard<'a> {
+    pipeline: &'a Pipeline,
+    /// The job being prepared, and the memory that was taken for it so far
+    current_job: Option<(JobId, u64)>,
+}
+impl <'a> Drop for WorkerGuard<'a> {
+    fn drop(&mut self) {
+        let mut state = self.pipeline.lock();
+        if let Some((job_id, memory_estimate)) = self.current_job.take() {
+            state.memory_used -= memory_estimate;
+            let group = &mut state.groups[job_id.group];
+            if !group.dropped {
+                let error = PDFError::ImageWorkerStopped(group.job_paths[job_id.index].clone());
+                group.prepared_images.insert(job_id.index, (Err(error), 0));
+            }
+        }
+        state.running_workers -= 1;
+        self.pipeline.changed.notify_all();
+    }
+}
+
+/// The file, and the decoded pixels if the size can be read from the header
+fn estimate_memory(image_bytes: &[u8]) -> u64 {
+    let decoded_size = match PDFImage::read_dimensions(image_bytes) {
+        Ok((width, height)) => width as u64 * height as u64 * DECODED_BYTES_PER_PIXEL,
+        Err(_) => 0,
+    };
+    image_bytes.len() as u64 + decoded_size
+}
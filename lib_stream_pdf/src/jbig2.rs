@@ -0,0 +1,578 @@
+use std::{
+    collections::{HashMap},
+};
+use image::{GrayImage};
+use crate::{PDFError, PDFResult};
+
+/// The probability estimates for the arithmetic coder:
+/// (Qe, index after an MPS, index after an LPS, if the MPS switches after an LPS)
+#[rustfmt::skip]
+const QE_TABLE: [(u32, usize, usize, bool); 47] = [
+    (0x5601, 1, 1, true), (0x3401, 2, 6, false), (0x1801, 3, 9, false), (0x0AC1, 4, 12, false),
+    (0x0521, 5, 29, false), (0x0221, 38, 33, false), (0x5601, 7, 6, true), (0x5401, 8, 14, false),
+    (0x4801, 9, 14, false), (0x3801, 10, 14, false), (0x3001, 11, 17, false), (0x2401, 12, 18, false),
+    (0x1C01, 13, 20, false), (0x1601, 29, 21, false), (0x5601, 15, 14, true), (0x5401, 16, 14, false),
+    (0x5101, 17, 15, false), (0x4801, 18, 16, false), (0x3801, 19, 17, false), (0x3401, 20, 18, false),
+    (0x3001, 21, 19, false), (0x2801, 22, 19, false), (0x2401, 23, 20, false), (0x2201, 24, 21, false),
+    (0x1C01, 25, 22, false), (0x1801, 26, 23, false), (0x1601, 27, 24, false), (0x1401, 28, 25, false),
+    (0x1201, 29, 26, false), (0x1101, 30, 27, false), (0x0AC1, 31, 28, false), (0x09C1, 32, 29, false),
+    (0x08A1, 33, 30, false), (0x0521, 34, 31, false), (0x0441, 35, 32, false), (0x02A1, 36, 33, false),
+    (0x0221, 37, 34, false), (0x0141, 38, 35, false), (0x0111, 39, 36, false), (0x0085, 40, 37, false),
+    (0x0049, 41, 38, false), (0x0025, 42, 39, false), (0x0015, 43, 40, false), (0x0009, 44, 41, false),
+    (0x0005, 45, 42, false), (0x0001, 45, 43, false), (0x5601, 46, 46, false),
+];
+/// The nominal adaptive template pixels for generic template 0
+const ADAPTIVE_PIXELS: [(i32, i32); 4] = [(3, -1), (-3, -1), (2, -2), (-2, -2)];
+/// The context that the typical prediction bit uses with template 0
+const TYPICAL_PREDICTION_CONTEXT: usize = 0x9B25;
+/// Symbols whose tops are in the same 2^n rows share a strip in the text region
+const LOG_STRIP_SIZE: u16 = 0;
+/// Shapes bigger than this (like panel borders) are left for the generic region
+const MAX_SYMBOL_SIZE: u32 = 256;
+
+const SEGMENT_SYMBOL_DICTIONARY: u8 = 0;
+const SEGMENT_IMMEDIATE_TEXT_REGION: u8 = 6;
+const SEGMENT_IMMEDIATE_GENERIC_REGION: u8 = 38;
+const SEGMENT_PAGE_INFORMATION: u8 = 48;
+
+/// How shapes that show up more than once (like lettering) get stored as symbols
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum SymbolMatching {
+    /// Everything goes in a single generic region
+    Off,
+    /// Only shapes that are exactly the same share a symbol, so the image is still lossless
+    #[default]
+    Exact,
+    /// Shapes that are the same size can share a symbol if at most this fraction (from 0 to 1) of
+    /// their pixels are different. This is lossy, and it can swap letters that look alike.
+    Similar(f64),
+}
+
+/// Encodes the image as an embedded JBIG2 stream (for `/JBIG2Decode`), where anything darker than
+/// the threshold is black
+pub fn encode_image(image: &GrayImage, threshold: u8, symbol_matching: SymbolMatching) -> PDFResult< Vec<u8> > {
+    let mut page = Bitmap::from_image(image, threshold);
+    let (symbols, instances) = match symbol_matching {
+        SymbolMatching::Off => (Vec::new(), Vec::new()),
+        SymbolMatching::Exact => find_symbols(&mut page, 0.0),
+        // This also catches NaN
+        SymbolMatching::Similar(max_difference) if !(0.0..=1.0).contains(&max_difference) => {
+            return Err(PDFError::BadSymbolMatching(max_difference));
+        },
+        SymbolMatching::Similar(max_difference) => find_symbols(&mut page, max_difference),
+    };
+
+    let mut output = Vec::new();
+    let mut page_information = Vec::new();
+    page_information.extend_from_slice(&page.width.to_be_bytes());
+    page_information.extend_from_slice(&page.height.to_be_bytes());
+    // No resolution
+    page_information.extend_from_slice(&[0; 8]);
+    // The page is lossless unless symbols were swapped, with a white background that gets ORed
+    let is_lossless = !matches!(symbol_matching, SymbolMatching::Similar(_));
+    page_information.push(is_lossless as u8);
+    // No striping
+    page_information.extend_from_slice(&[0, 0]);
+    write_segment(&mut output, 0, SEGMENT_PAGE_INFORMATION, &[], false, &page_information);
+
+    let mut segment_number = 1;
+    if !symbols.is_empty() {
+        let symbol_dictionary_number = segment_number;
+        write_segment(&mut output, symbol_dictionary_number, SEGMENT_SYMBOL_DICTIONARY, &[], true,
+            &encode_symbol_dictionary(&symbols));
+        segment_number += 1;
+        write_segment(&mut output, segment_number, SEGMENT_IMMEDIATE_TEXT_REGION, &[symbol_dictionary_number],
+            false, &encode_text_region(&page, &symbols, &instances));
+        segment_number += 1;
+    }
+    write_segment(&mut output, segment_number, SEGMENT_IMMEDIATE_GENERIC_REGION, &[], false,
+        &encode_generic_region_segment(&page));
+    Ok(output)
+}
+
+/// Black pixels are 1
+#[derive(Clone, Eq, PartialEq, Hash)]
+struct Bitmap {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+impl Bitmap {
+    fn new(width: u32, height: u32) -> Bitmap {
+        Bitmap { width, height, pixels: vec![0; width as usize * height as usize] }
+    }
+    fn from_image(image: &GrayImage, threshold: u8) -> Bitmap {
+        let (width, height) = image.dimensions();
+        let pixels = image.as_raw().iter()
+            .map(|&pixel| (pixel < threshold) as u8)
+            .collect();
+        Bitmap { width, height, pixels }
+    }
+    /// Anything outside of the bitmap is white
+    fn get(&self, x: i32, y: i32) -> u8 {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            0
+        } else {
+            self.pixels[y as usize * self.width as usize + x as usize]
+        }
+    }
+    fn set(&mut self, x: u32, y: u32, pixel: u8) {
+        self.pixels[y as usize * self.width as usize + x as usize] = pixel;
+    }
+    fn row(&self, y: u32) -> &[u8] {
+        let start = y as usize * self.width as usize;
+        &self.pixels[start..start + self.width as usize]
+    }
+    fn count_differences(&self, other: &Bitmap) -> usize {
+        self.pixels.iter().zip(&other.pixels)
+            .filter(|(pixel, other_pixel)| pixel != other_pixel)
+            .count()
+    }
+}
+
+/// A group of touching black pixels
+struct Component {
+    /// The top left corner
+    x: u32,
+    y: u32,
+    shape: Bitmap,
+    pixels: Vec<(u32, u32)>,
+}
+
+/// Where a symbol gets drawn on the page, from its top left corner
+struct SymbolInstance {
+    symbol_id: usize,
+    x: u32,
+    y: u32,
+}
+
+/// Takes the shapes that can share symbols out of the page. Shapes match if at most
+/// `max_difference` of their pixels are different. The symbols are sorted by height and then width,
+/// the same way that they get stored in the dictionary.
+fn find_symbols(page: &mut Bitmap, max_difference: f64) -> (Vec<Bitmap>, Vec<SymbolInstance>) {
+    // Each class has the shape that the others matched against, and where they all are
+    let mut classes: Vec<(Bitmap, Vec<Component>)> = Vec::new();
+    let mut exact_classes: HashMap<Bitmap, usize> = HashMap::new();
+    let mut classes_by_size: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for component in find_connected_components(page) {
+        let shape = &component.shape;
+        let class_index = match exact_classes.get(shape) {
+            Some(&class_index) => Some(class_index),
+            None if max_difference > 0.0 => {
+                let max_different_pixels = (max_difference * shape.pixels.len() as f64) as usize;
+                classes_by_size.get(&(shape.width, shape.height))
+                    .and_then(|class_indices| class_indices.iter().copied().find(|&class_index| {
+                        classes[class_index].0.count_differences(shape) <= max_different_pixels
+                    }))
+            },
+            None => None,
+        };
+        match class_index {
+            Some(class_index) => classes[class_index].1.push(component),
+            None => {
+                exact_classes.insert(shape.clone(), classes.len());
+                classes_by_size.entry((shape.width, shape.height)).or_default().push(classes.len());
+                classes.push((shape.clone(), vec![component]));
+            },
+        }
+    }
+
+    // A shape that only shows up once is cheaper to leave in the generic region
+    let mut classes: Vec<_> = classes.into_iter()
+        .filter(|(_, components)| components.len() > 1)
+        .collect();
+    classes.sort_by_key(|(shape, _)| (shape.height, shape.width));
+    let mut symbols = Vec::with_capacity(classes.len());
+    let mut instances = Vec::new();
+    for (symbol_id, (shape, components)) in classes.into_iter().enumerate() {
+        for component in components {
+            for (x, y) in component.pixels {
+                page.set(x, y, 0);
+            }
+            instances.push(SymbolInstance { symbol_id, x: component.x, y: component.y });
+        }
+        symbols.push(shape);
+    }
+    (symbols, instances)
+}
+
+/// Finds the groups of touching black pixels (including diagonally)
+fn find_connected_components(page: &Bitmap) -> Vec<Component> {
+    let mut is_visited = vec![false; page.pixels.len()];
+    let mut components = Vec::new();
+    let mut stack = Vec::new();
+    for start_y in 0..page.height {
+        for start_x in 0..page.width {
+            let start_index = (start_y * page.width + start_x) as usize;
+            if page.pixels[start_index] == 0 || is_visited[start_index] {
+                continue;
+            }
+            is_visited[start_index] = true;
+            stack.push((start_x, start_y));
+            let mut pixels = Vec::new();
+            let (mut left, mut top, mut right, mut bottom) = (start_x, start_y, start_x, start_y);
+            while let Some((x, y)) = stack.pop() {
+                pixels.push((x, y));
+                left = left.min(x);
+                top = top.min(y);
+                right = right.max(x);
+                bottom = bottom.max(y);
+                for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)] {
+                    let (next_x, next_y) = (x as i32 + dx, y as i32 + dy);
+                    if page.get(next_x, next_y) == 0 {
+                        continue;
+                    }
+                    let next_index = (next_y as u32 * page.width + next_x as u32) as usize;
+                    if !is_visited[next_index] {
+                        is_visited[next_index] = true;
+                        stack.push((next_x as u32, next_y as u32));
+                    }
+                }
+            }
+            let (width, height) = (right - left + 1, bottom - top + 1);
+            if width > MAX_SYMBOL_SIZE || height > MAX_SYMBOL_SIZE {
+                continue;
+            }
+            let mut shape = Bitmap::new(width, height);
+            for &(x, y) in &pixels {
+                shape.set(x - left, y - top, 1);
+            }
+            components.push(Component { x: left, y: top, shape, pixels });
+        }
+    }
+    components
+}
+
+/// The symbols have to already be sorted by height
+fn encode_symbol_dictionary(symbols: &[Bitmap]) -> Vec<u8> {
+    let mut data = Vec::new();
+    // Arithmetic coding with generic template 0, and no refinement or aggregation
+    data.extend_from_slice(&[0, 0]);
+    write_adaptive_pixels(&mut data);
+    // Every symbol is new and exported
+    data.extend_from_slice(&(symbols.len() as u32).to_be_bytes());
+    data.extend_from_slice(&(symbols.len() as u32).to_be_bytes());
+
+    let mut encoder = ArithmeticEncoder::new();
+    let mut generic_contexts = vec![ContextState::default(); 1 << 16];
+    let mut delta_height_contexts = IntegerContexts::new();
+    let mut delta_width_contexts = IntegerContexts::new();
+    let mut export_contexts = IntegerContexts::new();
+    let mut class_height = 0;
+    let mut symbol_index = 0;
+    while symbol_index < symbols.len() {
+        let height = symbols[symbol_index].height;
+        encoder.encode_integer(&mut delta_height_contexts, Some(height as i32 - class_height as i32));
+        class_height = height;
+        let mut symbol_width = 0;
+        while symbols.get(symbol_index).is_some_and(|symbol| symbol.height == height) {
+            let symbol = &symbols[symbol_index];
+            encoder.encode_integer(&mut delta_width_contexts, Some(symbol.width as i32 - symbol_width as i32));
+            symbol_width = symbol.width;
+            encoder.encode_generic_region(&mut generic_contexts, symbol, false);
+            symbol_index += 1;
+        }
+        // The end of the height class
+        encoder.encode_integer(&mut delta_width_contexts, None);
+    }
+    // The export flags are runs that start with the symbols that aren't exported
+    encoder.encode_integer(&mut export_contexts, Some(0));
+    encoder.encode_integer(&mut export_contexts, Some(symbols.len() as i32));
+    data.extend(encoder.finish());
+    data
+}
+
+/// Every symbol instance is drawn in its own strip by its top left corner
+fn encode_text_region(page: &Bitmap, symbols: &[Bitmap], instances: &[SymbolInstance]) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_region_information(&mut data, page.width, page.height);
+    // Arithmetic coding, the TOPLEFT corner, and OR for combining
+    data.extend_from_slice(&(LOG_STRIP_SIZE << 2 | 0x0010).to_be_bytes());
+    data.extend_from_slice(&(instances.len() as u32).to_be_bytes());
+
+    let strip_size = 1 << LOG_STRIP_SIZE;
+    let mut sorted_instances: Vec<&SymbolInstance> = instances.iter().collect();
+    sorted_instances.sort_by_key(|instance| (instance.y / strip_size, instance.x));
+    let mut symbol_code_length = 0;
+    while (1 << symbol_code_length) < symbols.len() {
+        symbol_code_length += 1;
+    }
+
+    let mut encoder = ArithmeticEncoder::new();
+    let mut delta_t_contexts = IntegerContexts::new();
+    let mut first_s_contexts = IntegerContexts::new();
+    let mut delta_s_contexts = IntegerContexts::new();
+    let mut instance_t_contexts = IntegerContexts::new();
+    let mut symbol_id_contexts = vec![ContextState::default(); 1 << (symbol_code_length + 1)];
+    // The starting strip
+    encoder.encode_integer(&mut delta_t_contexts, Some(0));
+    let (mut strip_t, mut first_s) = (0, 0);
+    let mut instance_index = 0;
+    while instance_index < sorted_instances.len() {
+        let strip_index = sorted_instances[instance_index].y / strip_size;
+        let t = (strip_index * strip_size) as i32;
+        encoder.encode_integer(&mut delta_t_contexts, Some((t - strip_t) / strip_size as i32));
+        strip_t = t;
+        let mut current_s = None;
+        while sorted_instances.get(instance_index).is_some_and(|instance| instance.y / strip_size == strip_index) {
+            let instance = sorted_instances[instance_index];
+            let s = instance.x as i32;
+            match current_s {
+                None => {
+                    encoder.encode_integer(&mut first_s_contexts, Some(s - first_s));
+                    first_s = s;
+                },
+                Some(current_s) => encoder.encode_integer(&mut delta_s_contexts, Some(s - current_s)),
+            }
+            if strip_size > 1 {
+                encoder.encode_integer(&mut instance_t_contexts, Some(instance.y as i32 - t));
+            }
+            encoder.encode_symbol_id(&mut symbol_id_contexts, symbol_code_length, instance.symbol_id);
+            // The next one is measured from the right side of this symbol
+            current_s = Some(s + symbols[instance.symbol_id].width as i32 - 1);
+            instance_index += 1;
+        }
+        // The end of the strip
+        encoder.encode_integer(&mut delta_s_contexts, None);
+    }
+    data.extend(encoder.finish());
+    data
+}
+
+fn encode_generic_region_segment(page: &Bitmap) -> Vec<u8> {
+    let mut data = Vec::new();
+    write_region_information(&mut data, page.width, page.height);
+    // Arithmetic coding with template 0 and typical prediction
+    data.push(0b1000);
+    write_adaptive_pixels(&mut data);
+    let mut encoder = ArithmeticEncoder::new();
+    let mut generic_contexts = vec![ContextState::default(); 1 << 16];
+    encoder.encode_generic_region(&mut generic_contexts, page, true);
+    data.extend(encoder.finish());
+    data
+}
+
+fn write_segment(output: &mut Vec<u8>, segment_number: u32, segment_type: u8, referred_segments: &[u32],
+is_retained: bool, data: &[u8]) {
+    output.extend_from_slice(&segment_number.to_be_bytes());
+    // The page association is a single byte
+    output.push(segment_type);
+    output.push(((referred_segments.len() as u8) << 5) | is_retained as u8);
+    for &referred_segment in referred_segments {
+        // The size of the numbers depends on this segment's number
+        if segment_number <= 256 {
+            output.push(referred_segment as u8);
+        } else if segment_number <= 65536 {
+            output.extend_from_slice(&(referred_segment as u16).to_be_bytes());
+        } else {
+            output.extend_from_slice(&referred_segment.to_be_bytes());
+        }
+    }
+    // Everything is on the first page
+    output.push(1);
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(data);
+}
+/// The region covers the whole page and gets ORed onto it
+fn write_region_information(data: &mut Vec<u8>, width: u32, height: u32) {
+    data.extend_from_slice(&width.to_be_bytes());
+    data.extend_from_slice(&height.to_be_bytes());
+    data.extend_from_slice(&[0; 8]);
+    data.push(0);
+}
+fn write_adaptive_pixels(data: &mut Vec<u8>) {
+    for (x, y) in ADAPTIVE_PIXELS {
+        data.push(x as i8 as u8);
+        data.push(y as i8 as u8);
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct ContextState {
+    index: usize,
+    more_probable_bit: bool,
+}
+
+/// The contexts for one of the integer types (like IADH or IADW)
+struct IntegerContexts {
+    states: Vec<ContextState>,
+}
+impl IntegerContexts {
+    fn new() -> IntegerContexts {
+        IntegerContexts { states: vec![ContextState::default(); 512] }
+    }
+}
+
+/// The MQ arithmetic encoder from Annex E
+struct ArithmeticEncoder {
+    interval: u32,
+    code: u32,
+    bits_until_byte: u32,
+    /// The byte that's waiting to be written, since a carry might still change it
+    pending_byte: Option<u32>,
+    output: Vec<u8>,
+}
+impl ArithmeticEncoder {
+    fn new() -> ArithmeticEncoder {
+        ArithmeticEncoder {
+            interval: 0x8000,
+            code: 0,
+            bits_until_byte: 12,
+            pending_byte: None,
+            output: Vec::new(),
+        }
+    }
+
+    fn encode_bit(&mut self, state: &mut ContextState, bit: bool) {
+        let (qe, next_mps_index, next_lps_index, does_switch) = QE_TABLE[state.index];
+        self.interval -= qe;
+        if bit == state.more_probable_bit {
+            if self.interval & 0x8000 != 0 {
+                self.code += qe;
+                return;
+            }
+            if self.interval < qe {
+                self.interval = qe;
+            } else {
+                self.code += qe;
+            }
+            state.index = next_mps_index;
+        } else {
+            if self.interval < qe {
+                self.code += qe;
+            } else {
+                self.interval = qe;
+            }
+            if does_switch {
+                state.more_probable_bit = !state.more_probable_bit;
+            }
+            state.index = next_lps_index;
+        }
+        while self.interval & 0x8000 == 0 {
+            self.interval <<= 1;
+            self.code <<= 1;
+            self.bits_until_byte -= 1;
+            if self.bits_until_byte == 0 {
+                self.byte_out();
+            }
+        }
+    }
+    /// Encodes an integer like the procedure in Annex A.2, with `None` for OOB
+    fn encode_integer(&mut self, contexts: &mut IntegerContexts, value: Option<i32>) {
+        let (is_negative, magnitude) = match value {
+            Some(value) => (value < 0, value.unsigned_abs()),
+            None => (true, 0),
+        };
+        // The prefix bits say how many bits the value has, and what gets added to it
+        let (prefix, prefix_length, bit_count, offset) = match magnitude {
+            0..=3 => (0b0, 1, 2, 0),
+            4..=19 => (0b10, 2, 4, 4),
+            20..=83 => (0b110, 3, 6, 20),
+            84..=339 => (0b1110, 4, 8, 84),
+            340..=4435 => (0b11110, 5, 12, 340),
+            _ => (0b11111, 5, 32, 4436),
+        };
+        let mut previous = 1;
+        let mut encode = |encoder: &mut ArithmeticEncoder, bit: bool| {
+            encoder.encode_bit(&mut contexts.states[previous], bit);
+            previous = if previous < 256 {
+                (previous << 1) | bit as usize
+            } else {
+                (((previous << 1) | bit as usize) & 511) | 256
+            };
+        };
+        encode(self, is_negative);
+        for i in (0..prefix_length).rev() {
+            encode(self, (prefix >> i) & 1 == 1);
+        }
+        let bits = magnitude - offset;
+        for i in (0..bit_count).rev() {
+            encode(self, (bits >> i) & 1 == 1);
+        }
+    }
+    /// Encodes a symbol ID like the procedure in Annex A.3
+    fn encode_symbol_id(&mut self, contexts: &mut [ContextState], code_length: u32, symbol_id: usize) {
+        let mut previous = 1;
+        for i in (0..code_length).rev() {
+            let bit = (symbol_id >> i) & 1 == 1;
+            self.encode_bit(&mut contexts[previous], bit);
+            previous = (previous << 1) | bit as usize;
+        }
+    }
+    /// Uses template 0 with the nominal adaptive pixels. Typical prediction skips over rows that
+    /// are the same as the one above.
+    fn encode_generic_region(&mut self, contexts: &mut [ContextState], bitmap: &Bitmap, use_typical_prediction: bool) {
+        let mut was_typical = false;
+        for y in 0..bitmap.height {
+            if use_typical_prediction {
+                let is_typical = y > 0 && bitmap.row(y) == bitmap.row(y - 1);
+                self.encode_bit(&mut contexts[TYPICAL_PREDICTION_CONTEXT], is_typical != was_typical);
+                was_typical = is_typical;
+                if is_typical {
+                    continue;
+                }
+            }
+            let y = y as i32;
+            for x in 0..bitmap.width as i32 {
+                let pixel = |dx: i32, dy: i32| bitmap.get(x + dx, y + dy) as usize;
+                let [a1, a2, a3, a4] = ADAPTIVE_PIXELS;
+                let context = pixel(-1, 0) | pixel(-2, 0) << 1 | pixel(-3, 0) << 2 | pixel(-4, 0) << 3 |
+                    pixel(a1.0, a1.1) << 4 |
+                    pixel(2, -1) << 5 | pixel(1, -1) << 6 | pixel(0, -1) << 7 | pixel(-1, -1) << 8 |
+                    pixel(-2, -1) << 9 |
+                    pixel(a2.0, a2.1) << 10 | pixel(a3.0, a3.1) << 11 |
+                    pixel(1, -2) << 12 | pixel(0, -2) << 13 | pixel(-1, -2) << 14 |
+                    pixel(a4.0, a4.1) << 15;
+                self.encode_bit(&mut contexts[context], pixel(0, 0) == 1);
+            }
+        }
+    }
+    /// Flushes out the rest of the code, and ends it with the 0xFFAC marker
+    fn finish(mut self) -> Vec<u8> {
+        let temp_code = self.code + self.interval;
+        self.code |= 0xFFFF;
+        if self.code >= temp_code {
+            self.code -= 0x8000;
+        }
+        self.code <<= self.bits_until_byte;
+        self.byte_out();
+        self.code <<= self.bits_until_byte;
+        self.byte_out();
+        let last_byte = self.pending_byte.take().unwrap_or(0);
+        self.output.push(last_byte as u8);
+        if last_byte != 0xFF {
+            self.output.push(0xFF);
+        }
+        self.output.push(0xAC);
+        self.output
+    }
+}
+impl ArithmeticEncoder {
+    fn byte_out(&mut self) {
+        match self.pending_byte {
+            // A byte after 0xFF only gets 7 bits so that a carry can't make a marker
+            Some(0xFF) => self.push_byte(20),
+            Some(_) if self.code >= 0x8000000 => {
+                // Carry into the byte that's waiting
+                let pending_byte = self.pending_byte.as_mut().unwrap();
+                *pending_byte += 1;
+                self.code &= 0x7FFFFFF;
+                if *pending_byte == 0xFF {
+                    self.push_byte(20);
+                } else {
+                    self.push_byte(19);
+                }
+            },
+            _ => self.push_byte(19),
+        }
+    }
+    /// Writes out the pending byte, and takes the next one from the top of the code
+    fn push_byte(&mut self, shift: u32) {
+        if let Some(pending_byte) = self.pending_byte {
+            self.output.push(pending_byte as u8);
+        }
+        self.pending_byte = Some(self.code >> shift);
+        self.code &= (1 << shift) - 1;
+        self.bits_until_byte = if shift == 20 { 7 } else { 8 };
+    }
+}
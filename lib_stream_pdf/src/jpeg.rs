@@ -0,0 +1,224 @@
+use crate::{
+    common_types::{Orientation},
+};
+
+/// Start of frame for baseline, extended sequential and progressive Huffman-coded JPEGs. These are
+/// the only kinds that every PDF reader can show. The others (lossless, hierarchical and
+/// arithmetic-coded) have to be re-encoded.
+const PASSTHROUGH_FRAMES: [u8; 3] = [0xC0, 0xC1, 0xC2];
+/// Start of image
+const SOI: u8 = 0xD8;
+/// End of image
+const EOI: u8 = 0xD9;
+/// Start of scan, where the compressed image data starts
+const SOS: u8 = 0xDA;
+/// Where EXIF (and XMP) is kept
+const APP1: u8 = 0xE1;
+/// Where the ICC profile is kept, split into pieces if it's too big for one segment
+const APP2: u8 = 0xE2;
+/// Where Adobe says how the colours were transformed
+const APP14: u8 = 0xEE;
+/// A comment
+const COM: u8 = 0xFE;
+/// The EXIF tag for the orientation
+const ORIENTATION_TAG: u16 = 0x0112;
+
+/// The colour transform from an Adobe APP14 segment
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AdobeTransform {
+    /// The components are stored as they are (RGB or CMYK)
+    Unknown,
+    YCbCr,
+    /// CMYK where the CMY components were turned into YCbCr
+    Ycck,
+}
+impl AdobeTransform {
+    fn from_byte(byte: u8) -> AdobeTransform {
+        match byte {
+            1 => Self::YCbCr,
+            2 => Self::Ycck,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// The parts of a JPEG header that matter for putting it in a PDF without re-encoding it
+pub struct JpegHeader {
+    /// Which start of frame marker the image has, which says how it was compressed
+    frame_marker: u8,
+    width: u32,
+    height: u32,
+    /// Bits per sample
+    precision: u8,
+    components: u8,
+    adobe_transform: Option<AdobeTransform>,
+    orientation: Orientation,
+}
+impl JpegHeader {
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    /// Only 8 bit JPEGs that were compressed in a way that every PDF reader understands can be used
+    /// as they are
+    pub fn can_pass_through(&self) -> bool {
+        self.precision == 8 && PASSTHROUGH_FRAMES.contains(&self.frame_marker)
+    }
+    /// 1 for gray, 3 for RGB (or YCbCr), 4 for CMYK (or YCCK)
+    pub fn components(&self) -> u8 { self.components }
+    pub fn adobe_transform(&self) -> Option<AdobeTransform> { self.adobe_transform }
+    pub fn orientation(&self) -> Orientation { self.orientation }
+}
+
+/// Only reads the segments before the image data. Gives back nothing if it's not a JPEG or the
+/// header is broken.
+pub fn read_header(jpeg_bytes: &[u8]) -> Option<JpegHeader> {
+    let mut frame = None;
+    let mut adobe_transform = None;
+    let mut orientation = Orientation::Normal;
+    for segment in Segments::new(jpeg_bytes)? {
+        let data = segment.data;
+        match segment.marker {
+            // Any start of frame, except for DHT, JPG and DAC which share the range
+            marker @ 0xC0..=0xCF if marker != 0xC4 && marker != 0xC8 && marker != 0xCC => {
+                if data.len() < 6 {
+                    return None;
+                }
+                let height = u16::from_be_bytes([data[1], data[2]]) as u32;
+                let width = u16::from_be_bytes([data[3], data[4]]) as u32;
+                frame = Some((marker, width, height, data[0], data[5]));
+            },
+            APP1 => {
+                if let Some(exif_orientation) = read_exif_orientation(data) {
+                    orientation = exif_orientation;
+                }
+            },
+            APP14 if data.len() >= 12 && data.starts_with(b"Adobe") => {
+                adobe_transform = Some(AdobeTransform::from_byte(data[11]));
+            },
+            _ => (),
+        }
+    }
+    let (frame_marker, width, height, precision, components) = frame?;
+    Some(JpegHeader { frame_marker, width, height, precision, components, adobe_transform, orientation })
+}
+
+/// Puts the pieces of the ICC profile back together, in the order of their sequence numbers
+pub fn read_icc_profile(jpeg_bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut pieces: Vec<(u8, &[u8])> = Segments::new(jpeg_bytes)?
+        .filter(|segment| segment.marker == APP2)
+        .filter_map(|segment| {
+            // The sequence number and the number of pieces come after the identifier
+            let data = segment.data.strip_prefix(b"ICC_PROFILE\0")?;
+            Some((*data.first()?, data.get(2..)?))
+        })
+        .collect();
+    if pieces.is_empty() {
+        return None;
+    }
+    pieces.sort_by_key(|&(sequence_number, _)| sequence_number);
+    Some(pieces.into_iter().flat_map(|(_, piece)| piece.iter().copied()).collect())
+}
+
+/// Takes out the comments and the APPn segments (EXIF, XMP, thumbnails, etc.), except for Adobe's
+/// since it says how to read the colours. The image data is copied as it is.
+pub fn strip_metadata(jpeg_bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut segments = Segments::new(jpeg_bytes)?;
+    let mut stripped_bytes = Vec::with_capacity(jpeg_bytes.len());
+    stripped_bytes.extend_from_slice(&[0xFF, SOI]);
+    for segment in segments.by_ref() {
+        let is_metadata = match segment.marker {
+            APP14 => !segment.data.starts_with(b"Adobe"),
+            0xE0..=0xEF | COM => true,
+            _ => false,
+        };
+        if !is_metadata {
+            stripped_bytes.extend_from_slice(segment.bytes);
+        }
+    }
+    stripped_bytes.extend_from_slice(segments.remaining());
+    Some(stripped_bytes)
+}
+
+/// Looks for the orientation in the first IFD of the EXIF's TIFF structure
+fn read_exif_orientation(data: &[u8]) -> Option<Orientation> {
+    let tiff = data.strip_prefix(b"Exif\0\0")?;
+    let big_endian = match tiff.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let read_u16 = |offset: usize| -> Option<u16> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let read_u32 = |offset: usize| -> Option<u32> {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?, *tiff.get(offset + 2)?, *tiff.get(offset + 3)?];
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+    let ifd_offset = read_u32(4)? as usize;
+    let entry_count = read_u16(ifd_offset)? as usize;
+    (0..entry_count)
+        // Each entry is 12 bytes (tag, type, count, value)
+        .map(|entry| ifd_offset + 2 + entry * 12)
+        .find(|&entry_offset| read_u16(entry_offset) == Some(ORIENTATION_TAG))
+        .and_then(|entry_offset| read_u16(entry_offset + 8))
+        .map(Orientation::from_exif)
+}
+
+struct Segment<'a> {
+    marker: u8,
+    /// Everything after the length
+    data: &'a [u8],
+    /// The whole segment, from its marker
+    bytes: &'a [u8],
+}
+
+/// Goes through the marker segments until the start of the scan
+struct Segments<'a> {
+    jpeg_bytes: &'a [u8],
+    position: usize,
+}
+impl <'a> Segments<'a> {
+    fn new(jpeg_bytes: &'a [u8]) -> Option<Segments<'a>> {
+        if jpeg_bytes.starts_with(&[0xFF, SOI]) {
+            Some(Segments { jpeg_bytes, position: 2 })
+        } else {
+            None
+        }
+    }
+    /// Everything that wasn't gone through yet, starting with the start of scan
+    fn remaining(&self) -> &'a [u8] { &self.jpeg_bytes[self.position..] }
+}
+impl <'a> Iterator for Segments<'a> {
+    type Item = Segment<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.jpeg_bytes;
+        let start = self.position;
+        if bytes.get(start) != Some(&0xFF) {
+            return None;
+        }
+        // Markers can be padded with any number of 0xFF
+        let mut marker_position = start;
+        while bytes.get(marker_position) == Some(&0xFF) {
+            marker_position += 1;
+        }
+        let marker = *bytes.get(marker_position)?;
+        let end = match marker {
+            SOS | EOI => return None,
+            // These markers don't have any data
+            0x01 | 0xD0..=0xD7 => marker_position + 1,
+            _ => {
+                let length_position = marker_position + 1;
+                // The length includes itself
+                let length = u16::from_be_bytes([*bytes.get(length_position)?, *bytes.get(length_position + 1)?]);
+                let end = length_position + length as usize;
+                if length < 2 || end > bytes.len() {
+                    return None;
+                }
+                end
+            },
+        };
+        self.position = end;
+        let data = bytes.get(marker_position + 3..end).unwrap_or(&[]);
+        Some(Segment { marker, data, bytes: &bytes[start..end] })
+    }
+}
@@ -1,36 +1,83 @@
+mod catalog;
 mod common_types;
+mod compression;
+mod font;
+mod icc;
+mod image_encoding;
+mod image_pipeline;
+mod jbig2;
+mod jpeg;
 mod objects;
 mod page;
+mod page_labels;
+mod paragraph;
 mod pdf_image;
+mod png;
+mod reader;
+mod text_layer;
 mod utils;
+mod vertical_text;
+mod xref;
 pub use crate::{
+    catalog::{CatalogOptions, PageLayout, PageMode, ReadingDirection},
     common_types::{Justify},
+    compression::{ChromaSubsampling, CompressionOptions, FlateLevel},
+    font::{PDFFont},
+    icc::{ColourProfiles},
+    image_encoding::{EncodingPolicy, ImageEncoding},
+    image_pipeline::{ImageJob, ImagePipeline, PrepareImage, PreparedImages},
+    jbig2::{SymbolMatching},
+    objects::{Dictionary, Name, Object, ObjectId, Stream},
     pdf_image::{PDFImage},
     page::{PDFPage},
+    page_labels::{PageLabel, PageLabelStyle},
+    paragraph::{Paragraph, TextBox},
+    reader::{DocumentReader},
+    text_layer::{TextLayer, TextLayerWord},
+    xref::{XrefMode},
 };
 
 use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
     io::{
-        BufWriter, Error as IOError, SeekFrom,
+        BufWriter, Cursor, Error as IOError, SeekFrom,
         prelude::*,
     },
-    fs::{File},
+    fs::{File, OpenOptions},
+    ops::{AddAssign},
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 use image::{ImageError};
 use crate::{
-    objects::{
-        Object, ObjectId, ObjectIdGenerator,
-        Dictionary, Name, Stream,
-    },
+    common_types::{Orientation},
+    font::{FontSubset, SharedFontSubset},
+    objects::{ObjectIdGenerator},
+    xref::{ObjectLocation, ObjectStreamBuilder, WrittenObject},
 };
 
 pub type PDFResult<T> = Result<T, PDFError>;
 #[derive(Debug)]
 pub enum PDFError {
+    BadColourProfile(String),
+    BadFont(String),
     BadImageColourType(String),
+    /// The fraction for `SymbolMatching::Similar` has to be from 0 to 1
+    BadSymbolMatching(f64),
+    BadTextLayer(String),
     ByteIndexTooLarge,
+    /// Images need at least one pixel
+    EmptyImage,
     FileAlreadyExists(PathBuf),
+    /// The image pipeline's worker panicked while preparing the image, or no workers were left to prepare it
+    ImageWorkerStopped(PathBuf),
+    /// The font was added to a different document
+    FontNotInDocument,
+    /// A compressed object can only be referenced from a cross-reference stream
+    ObjectNotInTable,
+    MalformedPDF(String),
+    UnsupportedFilter(String),
+    PageIndexOutOfRange(usize),
 
     IOError(IOError),
     ImageError(ImageError),
@@ -42,12 +89,46 @@ impl From<ImageError> for PDFError {
     fn from(error: ImageError) -> Self { Self::ImageError(error) }
 }
 
-pub struct DocumentWriter {
-    file: BufWriter<File>,
+/// Streams a PDF out to any seekable sink, defaulting to a buffered file.
+pub struct DocumentWriter<W: Write + Seek = BufWriter<File>> {
+    file: W,
+    /// Where the PDF header was written in the sink. All byte offsets are relative to this.
+    start_position: u64,
     id_generator: ObjectIdGenerator,
     written_objects: Vec<WrittenObject>,
+    xref_mode: XrefMode,
+    object_stream: ObjectStreamBuilder,
     pages_root_id: ObjectId,
     pages: Vec<PageRef>,
+    /// Anything from an existing catalog that needs to be kept when it gets rewritten
+    base_catalog: Dictionary,
+    /// The attributes that the pages inherit from an existing page tree root, which has to keep them
+    base_pages_root: Dictionary,
+    /// An existing document's `/Info`, which only has the fields that get set replaced
+    base_document_info: Dictionary,
+    /// The first part of an existing document's `/ID`, which stays the same through every update
+    base_document_id: Option< Vec<u8> >,
+    /// The cross-reference section that an incremental update comes after
+    previous_xref_start: Option<u64>,
+    /// Objects before this one were in the document before an incremental update
+    first_new_object_num: u32,
+    catalog_options: CatalogOptions,
+    /// Keyed by the index of the page that each label range starts on
+    page_labels: BTreeMap<usize, PageLabel>,
+    /// Keyed by the ID that the Type0 font will get written with
+    font_subsets: BTreeMap<ObjectId, SharedFontSubset>,
+    /// The fonts (and vertical fonts) that fragment writers were given. They're written by this
+    /// document, so the fragments' references to them are kept as they are.
+    lent_font_ids: BTreeSet<ObjectId>,
+    /// The fonts that this fragment writer was given, which the document it's appended to writes
+    borrowed_font_ids: BTreeSet<ObjectId>,
+    /// Keyed by the SHA-256 of each ICC profile, since many images share the same one
+    icc_profiles: HashMap<[u8; 32], ObjectId>,
+    /// Keyed by the SHA-256 of each image stream's dictionary and bytes, since an image that only has
+    /// the same hash as another one can't be left out
+    image_streams: HashMap<[u8; 32], ObjectId>,
+    duplicate_images: DuplicateImages,
+    compression: CompressionOptions,
 }
 impl DocumentWriter {
     pub fn stream_to_file(path: impl AsRef<Path>, overwrite: bool) -> PDFResult<DocumentWriter> {
@@ -55,46 +136,250 @@ impl DocumentWriter {
         if !overwrite && path.exists() {
             return Err(PDFError::FileAlreadyExists(path.to_path_buf()));
         }
-        let mut file = BufWriter::new(File::create(path)?);
+        DocumentWriter::stream_to_writer(BufWriter::new(File::create(path)?))
+    }
+    /// Appends an incremental update to a PDF that's already been written.
+    pub fn update_file(path: impl AsRef<Path>) -> PDFResult<DocumentWriter> {
+        let path = path.as_ref();
+        let existing = DocumentReader::open(path)?;
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        DocumentWriter::append_to_writer(BufWriter::new(file), &existing)
+    }
+}
+impl DocumentWriter< Cursor<Vec<u8>> > {
+    /// Builds the whole PDF in memory. The bytes are given back by `finish_writing`.
+    pub fn in_memory() -> PDFResult< DocumentWriter< Cursor<Vec<u8>> > > {
+        DocumentWriter::stream_to_writer(Cursor::new(Vec::new()))
+    }
+}
+impl <W: Write + Seek> DocumentWriter<W> {
+    /// The PDF will start at the writer's current position.
+    pub fn stream_to_writer(mut file: W) -> PDFResult< DocumentWriter<W> > {
+        let start_position = file.stream_position()?;
         file.write_all(b"%PDF-1.7")?;
 
         let mut id_generator = ObjectIdGenerator::new();
         // The xref table needs to start with this object
         let written_objects = vec![WrittenObject::new(
-            id_generator.next(u16::max_value()), 0, true
+            id_generator.next(u16::MAX), ObjectLocation::Free
         )];
         let pages_root_id = id_generator.next(0);
 
         Ok(DocumentWriter {
             file,
+            start_position,
             id_generator,
             written_objects,
+            xref_mode: XrefMode::Table,
+            object_stream: ObjectStreamBuilder::new(),
             pages_root_id,
             pages: Vec::new(),
+            base_catalog: Dictionary::new(),
+            base_pages_root: Dictionary::new(),
+            base_document_info: Dictionary::new(),
+            base_document_id: None,
+            previous_xref_start: None,
+            first_new_object_num: 0,
+            catalog_options: CatalogOptions::new(),
+            page_labels: BTreeMap::new(),
+            font_subsets: BTreeMap::new(),
+            lent_font_ids: BTreeSet::new(),
+            borrowed_font_ids: BTreeSet::new(),
+            icc_profiles: HashMap::new(),
+            image_streams: HashMap::new(),
+            duplicate_images: DuplicateImages::default(),
+            compression: CompressionOptions::new(),
         })
     }
+    /// Adds an incremental update onto the end of `existing`, which `file` has to hold.
+    /// The original bytes are left alone. Everything written (including the new page tree,
+    /// outline and xref section) comes after them. The existing `/Info` is kept, and only the fields
+    /// that `finish_writing` is given get replaced.
+    pub fn append_to_writer(mut file: W, existing: &DocumentReader) -> PDFResult< DocumentWriter<W> > {
+        let end_position = file.seek(SeekFrom::End(0))?;
+        let start_position = end_position.checked_sub(existing.file_size())
+            .ok_or_else(|| PDFError::MalformedPDF("The writer doesn't hold the PDF".to_string()))?;
 
-    pub fn add_image(&mut self, image: PDFImage) -> PDFResult<ImageRef> {
-        let image_id = self.id_generator.next(0);
-        let image_ref = crate::pdf_image::ref_from_image(image_id, &image);
-        let image_stream = crate::pdf_image::make_image_stream(image);
-        self.write_object_with_ref(image_id, image_stream)?;
-        Ok(image_ref)
+        let catalog = existing.catalog()?;
+        let pages_root_id = catalog.get(&Name::pages()).and_then(Object::as_ref_id)
+            .ok_or_else(|| PDFError::MalformedPDF("The catalog is missing /Pages".to_string()))?;
+        let pages_root = existing.resolve_dictionary(&pages_root_id.into())?;
+        let document_id = match existing.trailer().get(&Name::id()) {
+            Some(document_id) => existing.resolve(document_id)?,
+            None => Object::Null,
+        };
+        // Keep using whatever kind of xref the document already has
+        let xref_mode = if existing.uses_xref_streams() { XrefMode::Stream } else { XrefMode::Table };
+
+        let mut writer = DocumentWriter {
+            file,
+            start_position,
+            id_generator: ObjectIdGenerator::starting_at(existing.xref_size()),
+            written_objects: Vec::new(),
+            xref_mode,
+            object_stream: ObjectStreamBuilder::new(),
+            pages_root_id,
+            pages: Vec::new(),
+            base_catalog: catalog,
+            base_pages_root: crate::reader::inheritable_attributes(&pages_root),
+            base_document_info: existing.document_info()?.unwrap_or_default(),
+            base_document_id: document_id.as_array()
+                .and_then(|document_id| document_id.first())
+                .and_then(Object::as_string_bytes),
+            previous_xref_start: Some(existing.last_xref_offset()),
+            first_new_object_num: existing.xref_size(),
+            catalog_options: CatalogOptions::new(),
+            page_labels: BTreeMap::new(),
+            font_subsets: BTreeMap::new(),
+            lent_font_ids: BTreeSet::new(),
+            borrowed_font_ids: BTreeSet::new(),
+            icc_profiles: HashMap::new(),
+            image_streams: HashMap::new(),
+            duplicate_images: DuplicateImages::default(),
+            compression: CompressionOptions::new(),
+        };
+        for (page_id, mut page_dictionary) in existing.pages()? {
+            let page_ref = crate::page::ref_from_dictionary(page_id, &page_dictionary);
+            // The new page tree is flat, so any pages that were deeper need to point at the root.
+            // Their inherited attributes are already filled in by the reader.
+            let parent_id = page_dictionary.get(&Name::parent()).and_then(Object::as_ref_id);
+            if parent_id != Some(pages_root_id) {
+                page_dictionary.insert(Name::parent(), pages_root_id);
+                writer.write_object_with_ref(page_id, page_dictionary)?;
+            }
+            writer.pages.push(page_ref);
+        }
+        Ok(writer)
+    }
+    /// This needs to be set before anything gets added to the document.
+    pub fn with_xref_mode(mut self, xref_mode: XrefMode) -> DocumentWriter<W> {
+        self.xref_mode = xref_mode;
+        self
+    }
+    /// How the content streams, fonts, colour profiles and cross-reference streams get compressed.
+    /// Images are compressed before they're added, so they use the options they were made with.
+    pub fn with_compression(mut self, compression: CompressionOptions) -> DocumentWriter<W> {
+        self.compression = compression;
+        self
+    }
+    /// The viewer preferences, page layout and page mode for the document catalog
+    pub fn with_catalog_options(mut self, catalog_options: CatalogOptions) -> DocumentWriter<W> {
+        self.catalog_options = catalog_options;
+        self
+    }
+
+    /// An image that's the same as one that was already added (including its soft mask and colour
+    /// profile) isn't written again. It uses the same XObject, but it can still be turned differently.
+    pub fn add_image(&mut self, mut image: PDFImage) -> PDFResult<ImageRef> {
+        let (width, height, orientation) = crate::pdf_image::upright_size(&image);
+        let soft_mask_id = match crate::pdf_image::take_soft_mask(&mut image) {
+            Some(soft_mask) => {
+                Some(self.add_image_stream(crate::pdf_image::make_image_stream(soft_mask, None, None))?)
+            },
+            None => None,
+        };
+        let icc_profile_id = match crate::pdf_image::take_icc_profile(&mut image) {
+            Some(icc_profile) => Some(self.add_icc_profile(icc_profile)?),
+            None => None,
+        };
+        let image_stream = crate::pdf_image::make_image_stream(image, soft_mask_id, icc_profile_id);
+        let image_id = self.add_image_stream(image_stream)?;
+        Ok(ImageRef::new(image_id, width, height, orientation))
+    }
+    /// The font only gets written when the document is finished, since it only includes the glyphs
+    /// that the pages used.
+    pub fn add_font(&mut self, font: PDFFont) -> FontRef {
+        let font_id = self.id_generator.next(0);
+        // Vertical text needs its own font, which is only written if it gets used
+        let vertical_font_id = self.id_generator.next(0);
+        let font = Arc::new(font);
+        self.font_subsets.insert(font_id, Arc::new(Mutex::new(FontSubset::new(font.clone(), vertical_font_id))));
+        FontRef::new(font_id, vertical_font_id, font)
+    }
+    /// Gives the glyphs of the text their CIDs ahead of time. Fragment writers that share a font use
+    /// its glyphs in whatever order their threads get to them, so reserving all of the text first
+    /// keeps the document the same each time it's made.
+    pub fn reserve_glyphs(&mut self, font_ref: &FontRef, text: &str) -> PDFResult<()> {
+        let font_subset = self.font_subsets.get(&font_ref.id).ok_or(PDFError::FontNotInDocument)?;
+        crate::font::lock_subset(font_subset).use_text(text);
+        Ok(())
     }
     pub fn add_page(&mut self, page: PDFPage) -> PDFResult<PageRef> {
         let page_id = self.id_generator.next(0);
-        let page_ref = crate::page::ref_from_page(page_id, &page);
-        let content_stream_ref = self.write_object_ref(page.make_content_stream()?)?;
-        let page_dictionary = crate::page::make_page_dictionary(
-            self.pages_root_id, page, content_stream_ref);
-        self.write_object_with_ref(page_id, page_dictionary)?;
-        self.pages.push(page_ref.clone());
+        let page_ref = self.write_page(page_id, page)?;
+        self.pages.push(page_ref);
+        Ok(page_ref)
+    }
+    /// Puts the page in before the page that's currently at `page_index`
+    pub fn insert_page(&mut self, page_index: usize, page: PDFPage) -> PDFResult<PageRef> {
+        if page_index > self.pages.len() {
+            return Err(PDFError::PageIndexOutOfRange(page_index));
+        }
+        let page_id = self.id_generator.next(0);
+        let page_ref = self.write_page(page_id, page)?;
+        self.pages.insert(page_index, page_ref);
+        Ok(page_ref)
+    }
+    /// The new page takes over the old page's object, so anything pointing to it will still work
+    pub fn replace_page(&mut self, page_index: usize, page: PDFPage) -> PDFResult<PageRef> {
+        let old_page_ref = *self.pages.get(page_index)
+            .ok_or(PDFError::PageIndexOutOfRange(page_index))?;
+        let page_ref = self.write_page(old_page_ref.id, page)?;
+        self.pages[page_index] = page_ref;
         Ok(page_ref)
     }
+    /// Starts a fragment (see `append_fragment`) that uses this document's fonts and compression. The
+    /// fonts only get written by this document, so the fragment has to be appended to it. The
+    /// fragment's objects are numbered after this document's, so they can't be mixed up with the fonts.
+    pub fn fragment_writer<F: Write + Seek>(&mut self, file: F) -> PDFResult< DocumentWriter<F> > {
+        let mut fragment_writer = DocumentWriter::stream_to_writer(file)?
+            .with_compression(self.compression.clone());
+        fragment_writer.id_generator = ObjectIdGenerator::starting_at(self.id_generator.next_object_num());
+        fragment_writer.pages_root_id = fragment_writer.id_generator.next(0);
+        for (&font_id, font_subset) in &self.font_subsets {
+            self.lent_font_ids.insert(font_id);
+            self.lent_font_ids.insert(crate::font::lock_subset(font_subset).reserved_vertical_font_id());
+            fragment_writer.borrowed_font_ids.insert(font_id);
+            fragment_writer.font_subsets.insert(font_id, font_subset.clone());
+        }
+        Ok(fragment_writer)
+    }
+    /// Copies the pages of a fragment onto the end of this document. A fragment is a PDF that was
+    /// written separately (usually a range of pages written by another `DocumentWriter` on its own
+    /// thread). Everything the pages use gets renumbered to follow on from this document's objects.
+    /// Only the pages come across, so the fragment's outline, page labels and info are left behind.
+    /// Images and ICC profiles that this document already has are used instead of copying them again.
+    pub fn append_fragment(&mut self, fragment: &DocumentReader) -> PDFResult< Vec<PageRef> > {
+        let mut new_ids = BTreeMap::new();
+        let mut page_refs = Vec::new();
+        for (page_id, mut page_dictionary) in fragment.pages()? {
+            let new_page_id = self.id_generator.next(0);
+            new_ids.insert(page_id, Some(new_page_id));
+            // The page goes into this document's page tree instead of the fragment's
+            page_dictionary.remove(&Name::parent());
+            let mut page_dictionary = self.renumber_dictionary(fragment, &page_dictionary, &mut new_ids)?;
+            page_dictionary.insert(Name::parent(), self.pages_root_id);
+            let page_ref = crate::page::ref_from_dictionary(new_page_id, &page_dictionary);
+            self.write_object_with_ref(new_page_id, page_dictionary)?;
+            self.pages.push(page_ref);
+            page_refs.push(page_ref);
+        }
+        Ok(page_refs)
+    }
+    /// Every page in the document so far, in order
+    pub fn pages(&self) -> &[PageRef] { &self.pages }
+    /// The images that were added more than once, and how much writing them again would have taken
+    pub fn duplicate_images(&self) -> DuplicateImages { self.duplicate_images }
+    /// Labels the pages starting from `page_index` until the next label range starts.
+    /// Any pages before the first range get numbered normally.
+    pub fn add_page_label(&mut self, page_index: usize, page_label: PageLabel) {
+        self.page_labels.insert(page_index, page_label);
+    }
 
+    /// Gives back the underlying writer once everything has been flushed to it.
     pub fn finish_writing(mut self, outline_tree: Vec<OutlineItem>, document_info: DocumentInfo)
-    -> PDFResult<()> {
-        let mut pages = Dictionary::new();
+    -> PDFResult<W> {
+        let mut pages = std::mem::take(&mut self.base_pages_root);
         pages.insert(Name::type_name(), Name::pages());
         pages.insert(Name::count(), self.pages.len());
         let kids: Vec<Object> = self.pages.iter()
@@ -106,7 +391,7 @@ impl DocumentWriter {
         let outline_dictionary_ref = {
             let outline_root_id = self.id_generator.next(0);
             let outline_ids = self.write_outline_tree(outline_root_id, outline_tree)?;
-            if outline_ids.len() == 0 {
+            if outline_ids.is_empty() {
                 None
             } else {
                 let mut outline_dictionary = Dictionary::new();
@@ -117,46 +402,221 @@ impl DocumentWriter {
                 Some(outline_root_id)
             }
         };
+        let font_subsets = std::mem::take(&mut self.font_subsets);
+        for (font_id, font_subset) in font_subsets {
+            if !self.borrowed_font_ids.contains(&font_id) {
+                self.write_font(font_id, &crate::font::lock_subset(&font_subset))?;
+            }
+        }
         let document_catalog_ref = {
-            let mut catalog = Dictionary::new();
+            let mut catalog = std::mem::take(&mut self.base_catalog);
             catalog.insert(Name::type_name(), Name::catalog());
             catalog.insert(Name::pages(), self.pages_root_id);
-            catalog.insert(Name::outlines(), outline_dictionary_ref);
+            // An update without an outline keeps the one that's already there
+            if outline_dictionary_ref.is_some() || catalog.get(&Name::outlines()).is_none() {
+                catalog.insert(Name::outlines(), outline_dictionary_ref);
+            }
+            crate::catalog::add_to_catalog(&self.catalog_options, &mut catalog);
+            if !self.page_labels.is_empty() {
+                let page_labels = std::mem::take(&mut self.page_labels);
+                catalog.insert(Name::page_labels(), crate::page_labels::make_page_labels_tree(page_labels));
+            }
             self.write_object_ref(catalog)?
         };
         let document_info_ref = {
-            let info_dictionary = document_info.into_dictionary();
+            let mut info_dictionary = std::mem::take(&mut self.base_document_info);
+            document_info.add_to_dictionary(&mut info_dictionary);
             self.write_object_ref(info_dictionary)?
         };
-        let xref_table_start = self.file_position()?;
-        self.write_xref_table()?;
-        self.write_trailer(document_catalog_ref, document_info_ref)?;
-        write!(&mut self.file, "\nstartxref\n{}\n%%EOF", xref_table_start)?;
+        self.write_object_stream()?;
+
+        let mut trailer = Dictionary::new();
+        trailer.insert(Name::root(), document_catalog_ref);
+        trailer.insert(Name::info(), document_info_ref);
+        if let Some(previous_xref_start) = self.previous_xref_start {
+            trailer.insert(Name::prev(), previous_xref_start);
+        }
+        if let Some(base_document_id) = self.base_document_id.take() {
+            // The second part has to be different for each update
+            let update_id = crate::utils::content_hash(&[&base_document_id, &self.file_position()?.to_be_bytes()]);
+            trailer.insert(Name::id(), vec![
+                Object::HexStr(base_document_id),
+                Object::HexStr(update_id.to_be_bytes().to_vec()),
+            ]);
+        }
+        let xref_start = match self.xref_mode {
+            XrefMode::Table => self.write_xref_table(trailer)?,
+            XrefMode::Stream => self.write_xref_stream(trailer)?,
+        };
+        write!(&mut self.file, "\nstartxref\n{}\n%%EOF", xref_start)?;
         self.file.flush()?;
-        Ok(())
+        Ok(self.file)
     }
 
+    /// The number of bytes written for this PDF so far.
     pub fn file_position(&mut self) -> PDFResult<u64> {
-        let current_position = self.file.seek(SeekFrom::Current(0))?;
-        Ok(current_position)
+        let current_position = self.file.stream_position()?;
+        Ok(current_position - self.start_position)
     }
 }
-impl DocumentWriter {
+impl <W: Write + Seek> DocumentWriter<W> {
     fn write_object_ref<T: Into<Object>>(&mut self, object: T) -> PDFResult<ObjectId> {
         let new_id = self.id_generator.next(0);
         self.write_object_with_ref(new_id, object)?;
         Ok(new_id)
     }
     fn write_object_with_ref<T: Into<Object>>(&mut self, id: ObjectId, object: T) -> PDFResult<()> {
+        // Some readers let an older compressed entry win over a newer one, so anything that replaces
+        // an existing object is always written out uncompressed
+        let is_new_object = id.object_num() >= self.first_new_object_num;
+        match (self.xref_mode, object.into()) {
+            (XrefMode::Stream, object) if is_new_object && !matches!(object, Object::Stream(_)) => {
+                self.object_stream.push(id, object)?;
+                if self.object_stream.is_full() {
+                    self.write_object_stream()?;
+                }
+                Ok(())
+            },
+            (_, object) => self.write_object_to_file(id, object),
+        }
+    }
+    fn write_page(&mut self, page_id: ObjectId, page: PDFPage) -> PDFResult<PageRef> {
+        let page_ref = crate::page::ref_from_page(page_id, &page);
+        let content_stream = crate::page::make_content_stream(&page, &self.font_subsets, self.compression.flate_level())?;
+        let content_stream_ref = self.write_object_ref(content_stream)?;
+        let page_dictionary = crate::page::make_page_dictionary(
+            self.pages_root_id, page, content_stream_ref);
+        self.write_object_with_ref(page_id, page_dictionary)?;
+        Ok(page_ref)
+    }
+    fn write_font(&mut self, font_id: ObjectId, font_subset: &FontSubset) -> PDFResult<()> {
+        let font_file_ref = self.write_object_ref(font_subset.make_font_file(self.compression.flate_level())?)?;
+        let font_descriptor_ref = self.write_object_ref(font_subset.make_font_descriptor(font_file_ref))?;
+        let cid_font_ref = self.write_object_ref(font_subset.make_cid_font(font_descriptor_ref))?;
+        let to_unicode_ref = self.write_object_ref(font_subset.make_to_unicode_cmap(self.compression.flate_level())?)?;
+        if let Some(vertical_font_id) = font_subset.vertical_font_id() {
+            self.write_object_with_ref(vertical_font_id,
+                font_subset.make_vertical_type0_font(cid_font_ref, to_unicode_ref))?;
+        }
+        self.write_object_with_ref(font_id, font_subset.make_type0_font(cid_font_ref, to_unicode_ref))
+    }
+    /// Gives back the ID of the same stream if it was already written, instead of writing it again
+    fn add_image_stream(&mut self, image_stream: Stream) -> PDFResult<ObjectId> {
+        let mut dictionary_bytes = Vec::new();
+        image_stream.dictionary().write_to(&mut dictionary_bytes)?;
+        let image_digest = crate::utils::content_digest(&[&dictionary_bytes, image_stream.contents()]);
+        if let Some(&image_id) = self.image_streams.get(&image_digest) {
+            self.duplicate_images.count += 1;
+            self.duplicate_images.bytes_saved += (dictionary_bytes.len() + image_stream.contents().len()) as u64;
+            return Ok(image_id);
+        }
+        let image_id = self.id_generator.next(0);
+        self.write_object_with_ref(image_id, image_stream)?;
+        self.image_streams.insert(image_digest, image_id);
+        Ok(image_id)
+    }
+    /// Only the first image with a profile writes it. The others use the same ICCBased stream.
+    fn add_icc_profile(&mut self, icc_profile: Vec<u8>) -> PDFResult<ObjectId> {
+        let profile_digest = crate::utils::content_digest(&[&icc_profile]);
+        if let Some(&icc_profile_id) = self.icc_profiles.get(&profile_digest) {
+            return Ok(icc_profile_id);
+        }
+        let icc_profile_id = self.id_generator.next(0);
+        self.write_object_with_ref(icc_profile_id, crate::icc::make_profile_stream(&icc_profile, self.compression.flate_level())?)?;
+        self.icc_profiles.insert(profile_digest, icc_profile_id);
+        Ok(icc_profile_id)
+    }
+    /// Copies the fragment's object the first time it's used, and gives back its new ID.
+    /// The new IDs are `None` for streams that are still being copied.
+    fn copy_fragment_object(&mut self, fragment: &DocumentReader, id: ObjectId,
+    new_ids: &mut BTreeMap< ObjectId, Option<ObjectId> >) -> PDFResult<ObjectId> {
+        match new_ids.get(&id) {
+            Some(&Some(new_id)) => return Ok(new_id),
+            Some(None) => return Err(PDFError::MalformedPDF(format!("Stream {:?} points back to itself", id))),
+            None => (),
+        }
+        let object = fragment.get_object(id)?;
+        if let Object::Stream(stream) = &object {
+            // Images and profiles can only get their IDs once everything they point to has been copied,
+            // since that's what they're compared by
+            new_ids.insert(id, None);
+            let dictionary = self.renumber_dictionary(fragment, stream.dictionary(), new_ids)?;
+            let new_id = if dictionary.get(&Name::subtype()).and_then(Object::as_name) == Some(&Name::image()) {
+                self.add_image_stream(Stream::new(dictionary, stream.contents().to_vec()))?
+            } else if crate::icc::is_profile_stream(&dictionary) {
+                self.add_icc_profile(fragment.decode_stream(stream)?)?
+            } else {
+                let new_id = self.id_generator.next(0);
+                self.write_object_with_ref(new_id, Stream::new(dictionary, stream.contents().to_vec()))?;
+                new_id
+            };
+            new_ids.insert(id, Some(new_id));
+            return Ok(new_id);
+        }
+        // The ID is taken before copying, so anything that points back to this object won't copy it again
+        let new_id = self.id_generator.next(0);
+        new_ids.insert(id, Some(new_id));
+        let object = self.renumber_object(fragment, &object, new_ids)?;
+        self.write_object_with_ref(new_id, object)?;
+        Ok(new_id)
+    }
+    /// Copies anything the object points to, changing the references to the new IDs
+    fn renumber_object(&mut self, fragment: &DocumentReader, object: &Object,
+    new_ids: &mut BTreeMap< ObjectId, Option<ObjectId> >) -> PDFResult<Object> {
+        let renumbered_object = match object {
+            Object::Ref(id) if self.lent_font_ids.contains(id) => Object::Ref(*id),
+            Object::Ref(id) => Object::Ref(self.copy_fragment_object(fragment, *id, new_ids)?),
+            Object::Array(array) => {
+                let renumbered_array = array.iter()
+                    .map(|object| self.renumber_object(fragment, object, new_ids))
+                    .collect::<PDFResult<_>>()?;
+                Object::Array(renumbered_array)
+            },
+            Object::Dictionary(dictionary) => {
+                Object::Dictionary(self.renumber_dictionary(fragment, dictionary, new_ids)?)
+            },
+            // The stream's bytes don't have any references in them, so they're copied as they are
+            Object::Stream(stream) => {
+                let dictionary = self.renumber_dictionary(fragment, stream.dictionary(), new_ids)?;
+                Object::Stream(Stream::new(dictionary, stream.contents().to_vec()))
+            },
+            object => object.clone(),
+        };
+        Ok(renumbered_object)
+    }
+    fn renumber_dictionary(&mut self, fragment: &DocumentReader, dictionary: &Dictionary,
+    new_ids: &mut BTreeMap< ObjectId, Option<ObjectId> >) -> PDFResult<Dictionary> {
+        let mut renumbered_dictionary = Dictionary::new();
+        for (name, object) in dictionary.iter() {
+            renumbered_dictionary.insert(name.clone(), self.renumber_object(fragment, object, new_ids)?);
+        }
+        Ok(renumbered_dictionary)
+    }
+    fn write_object_to_file(&mut self, id: ObjectId, object: Object) -> PDFResult<()> {
         // Start with a new line to guarantee no symantic collisions
-        self.file.write(b"\n")?;
+        self.file.write_all(b"\n")?;
         let object_start = self.file_position()?;
         id.write_to(&mut self.file)?;
         self.file.write_all(b" obj\n")?;
-        object.into().write_to(&mut self.file)?;
+        object.write_to(&mut self.file)?;
         self.file.write_all(b"\nendobj")?;
 
-        self.written_objects.push(WrittenObject::new(id, object_start, false));
+        self.written_objects.push(WrittenObject::new(id, ObjectLocation::Offset(object_start)));
+        Ok(())
+    }
+    /// Writes out any objects that are waiting to be packed into an object stream
+    fn write_object_stream(&mut self) -> PDFResult<()> {
+        if self.object_stream.is_empty() {
+            return Ok(());
+        }
+        let (stream, packed_ids) = self.object_stream.take_stream(self.compression.flate_level())?;
+        let stream_id = self.id_generator.next(0);
+        self.write_object_to_file(stream_id, stream.into())?;
+        for (index, id) in packed_ids.into_iter().enumerate() {
+            self.written_objects.push(WrittenObject::new(
+                id, ObjectLocation::InObjectStream { stream_id, index }
+            ));
+        }
         Ok(())
     }
 
@@ -165,7 +625,7 @@ impl DocumentWriter {
         let outline_ids: Vec<ObjectId> = std::iter::repeat_with(|| self.id_generator.next(0))
             .take(outline_tree.len())
             .collect();
-        if outline_ids.len() != 0 {
+        if !outline_ids.is_empty() {
             let max_index = outline_ids.len() - 1;
             for (i, outline_item) in outline_tree.into_iter().enumerate() {
                 let mut item_dictionary = Dictionary::new();
@@ -186,7 +646,7 @@ impl DocumentWriter {
 
                 let item_id = outline_ids[i];
                 let child_ids = self.write_outline_tree(item_id, outline_item.children)?;
-                if child_ids.len() > 0 {
+                if !child_ids.is_empty() {
                     item_dictionary.insert(Name::first(), child_ids[0]);
                     item_dictionary.insert(Name::last(), child_ids[child_ids.len() - 1]);
                     // Make sure that all the children are closed (negative length of children)
@@ -198,41 +658,35 @@ impl DocumentWriter {
         Ok(outline_ids)
     }
 
-    fn write_xref_table(&mut self) -> PDFResult<()> {
-        self.file.write_all(b"\nxref\n")?;
-
-        self.written_objects.sort_by(|object1, object2| object1.id.cmp(&object2.id));
-        let mut adjacent_object_lists: Vec< Vec<&WrittenObject> > = Vec::new();
-        for written_object in &self.written_objects {
-            if let Some(last_object_list) = adjacent_object_lists.last_mut() {
-                let last_object = last_object_list.last().unwrap();
-                if last_object.object_num() == (written_object.object_num() - 1) {
-                    last_object_list.push(written_object);
-                    continue;
-                }
-            }
-            adjacent_object_lists.push(vec![written_object]);
-        }
-
-        for adjacent_objects in adjacent_object_lists {
-            let start_object_num = adjacent_objects[0].object_num();
-            write!(&mut self.file, "{} {}\n", start_object_num, adjacent_objects.len())?;
-            for written_object in adjacent_objects {
-                written_object.write_xref_line(&mut self.file)?;
-            }
-        }
-        Ok(())
-    }
+    /// Gives back where the xref table starts
+    fn write_xref_table(&mut self, mut trailer: Dictionary) -> PDFResult<u64> {
+        // startxref has to point right at the xref keyword
+        self.file.write_all(b"\n")?;
+        let xref_start = self.file_position()?;
+        crate::xref::write_xref_table(&mut self.file, &mut self.written_objects)?;
 
-    fn write_trailer(&mut self, root_id: ObjectId, info_id: ObjectId) -> PDFResult<()> {
         self.file.write_all(b"\ntrailer\n")?;
-
-        let mut trailer = Dictionary::new();
-        trailer.insert(Name::size(), self.written_objects.len());
-        trailer.insert(Name::root(), root_id);
-        trailer.insert(Name::info(), info_id);
+        trailer.insert(Name::size(), crate::xref::xref_size(&self.written_objects));
         trailer.write_to(&mut self.file)?;
-        Ok(())
+        Ok(xref_start)
+    }
+    /// Gives back where the xref stream object starts
+    fn write_xref_stream(&mut self, mut trailer: Dictionary) -> PDFResult<u64> {
+        let xref_stream_id = self.id_generator.next(0);
+        self.file.write_all(b"\n")?;
+        let xref_start = self.file_position()?;
+        // The xref stream has to have an entry for itself
+        self.written_objects.push(WrittenObject::new(
+            xref_stream_id, ObjectLocation::Offset(xref_start)
+        ));
+        trailer.insert(Name::size(), crate::xref::xref_size(&self.written_objects));
+        let xref_stream = crate::xref::make_xref_stream(&mut self.written_objects, trailer, self.compression.flate_level())?;
+
+        xref_stream_id.write_to(&mut self.file)?;
+        self.file.write_all(b" obj\n")?;
+        xref_stream.write_to(&mut self.file)?;
+        self.file.write_all(b"\nendobj")?;
+        Ok(xref_start)
     }
 }
 
@@ -240,19 +694,60 @@ impl DocumentWriter {
 pub struct ImageRef {
     id: ObjectId,
     ref_name: Name,
+    /// The size of the image once it's upright
     width: u32,
     height: u32,
+    orientation: Orientation,
 }
 impl ImageRef {
     pub fn dimensions(&self) -> (u32, u32) { (self.width, self.height) }
 }
 impl ImageRef {
-    fn new(id: ObjectId, width: u32, height: u32) -> ImageRef {
+    fn new(id: ObjectId, width: u32, height: u32, orientation: Orientation) -> ImageRef {
         let ref_name = Name::new(format!("Image{}", id.object_num()));
-        ImageRef { id, ref_name, width, height }
+        ImageRef { id, ref_name, width, height, orientation }
     }
 }
 
+/// Image streams that weren't written again since they were already in the document. Soft masks
+/// are their own streams, so they're counted on their own.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct DuplicateImages {
+    count: usize,
+    bytes_saved: u64,
+}
+impl DuplicateImages {
+    pub fn count(&self) -> usize { self.count }
+    /// The size of the streams that would have been written
+    pub fn bytes_saved(&self) -> u64 { self.bytes_saved }
+}
+/// For adding up the duplicates from each fragment
+impl AddAssign for DuplicateImages {
+    fn add_assign(&mut self, other: DuplicateImages) {
+        self.count += other.count;
+        self.bytes_saved += other.bytes_saved;
+    }
+}
+
+#[derive(Clone)]
+pub struct FontRef {
+    id: ObjectId,
+    ref_name: Name,
+    vertical_id: ObjectId,
+    vertical_ref_name: Name,
+    font: Arc<PDFFont>,
+}
+impl FontRef {
+    /// For measuring text before it gets added to a page
+    pub fn font(&self) -> &PDFFont { &self.font }
+}
+impl FontRef {
+    fn new(id: ObjectId, vertical_id: ObjectId, font: Arc<PDFFont>) -> FontRef {
+        let ref_name = Name::new(format!("Font{}", id.object_num()));
+        let vertical_ref_name = Name::new(format!("Font{}", vertical_id.object_num()));
+        FontRef { id, ref_name, vertical_id, vertical_ref_name, font }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub struct PageRef {
@@ -283,6 +778,7 @@ impl OutlineItem {
     }
 }
 
+#[derive(Default)]
 pub struct DocumentInfo {
     title: Option<String>,
     author: Option<String>,
@@ -303,33 +799,13 @@ impl DocumentInfo {
         self
     }
 
-    fn into_dictionary(self) -> Dictionary {
-        let mut info_dictionary = Dictionary::new();
-        info_dictionary.insert(Name::title(), self.title);
-        info_dictionary.insert(Name::author(), self.author);
-        info_dictionary
-    }
-}
-
-struct WrittenObject {
-    id: ObjectId,
-    byte_offset: u64,
-    is_free: bool,
-}
-impl WrittenObject {
-    fn new(id: ObjectId, byte_offset: u64, is_free: bool) -> WrittenObject {
-        WrittenObject { id, byte_offset, is_free }
-    }
-    fn object_num(&self) -> u32 { self.id.object_num() }
-
-    fn write_xref_line<W: Write>(&self, writer: &mut W) -> PDFResult<()> {
-        let byte_index_string = format!("{:010}", self.byte_offset);
-        if byte_index_string.len() > 10 {
-            return Err(PDFError::ByteIndexTooLarge);
+    /// Only the fields that were set get replaced
+    fn add_to_dictionary(self, info_dictionary: &mut Dictionary) {
+        if let Some(title) = self.title {
+            info_dictionary.insert(Name::title(), title);
+        }
+        if let Some(author) = self.author {
+            info_dictionary.insert(Name::author(), author);
         }
-        let gen_number = self.id.gen_string();
-        let object_type = if self.is_free { "f" } else { "n" };
-        write!(writer, "{} {} {}\r\n", byte_index_string, gen_number, object_type)?;
-        Ok(())
     }
 }
@@ -15,6 +15,8 @@ pub enum Object {
     Real(f64),
     Name(Name),
     Str(String),
+    /// Binary data (like a palette) that gets written as hex
+    HexStr(Vec<u8>),
     Array(Vec<Object>),
     Dictionary(Dictionary),
     Stream(Stream),
@@ -24,6 +26,7 @@ impl From<bool> for Object { fn from(boolean: bool) -> Self { Self::Bool(boolean
 impl From<u8> for Object { fn from(int: u8) -> Self { Self::Int(int as i64) } }
 impl From<u16> for Object { fn from(int: u16) -> Self { Self::Int(int as i64) } }
 impl From<u32> for Object { fn from(int: u32) -> Self { Self::Int(int as i64) } }
+impl From<u64> for Object { fn from(int: u64) -> Self { Self::Int(int as i64) } }
 impl From<usize> for Object { fn from(int: usize) -> Self { Self::Int(int as i64) } }
 impl From<i8> for Object { fn from(int: i8) -> Self { Self::Int(int as i64) } }
 impl From<i16> for Object { fn from(int: i16) -> Self { Self::Int(int as i64) } }
@@ -53,6 +56,68 @@ impl <T: Into<Object>> From< Option<T> > for Object {
 }
 
 impl Object {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Self::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+    /// Integers are also numbers
+    pub fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Int(int) => Some(*int as f64),
+            Self::Real(real) => Some(*real),
+            _ => None,
+        }
+    }
+    pub fn as_name(&self) -> Option<&Name> {
+        match self {
+            Self::Name(name) => Some(name),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Str(string) => Some(string),
+            _ => None,
+        }
+    }
+    /// The raw bytes of either kind of string. Text strings are encoded the same way the reader decodes them.
+    pub fn as_string_bytes(&self) -> Option< Vec<u8> > {
+        match self {
+            Self::Str(string) if string.chars().all(|c| (c as u32) <= 0xFF) => {
+                Some(string.chars().map(|c| c as u8).collect())
+            },
+            Self::Str(string) => Some(crate::utils::to_utf16(string)),
+            Self::HexStr(bytes) => Some(bytes.clone()),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Object]> {
+        match self {
+            Self::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+    pub fn as_dictionary(&self) -> Option<&Dictionary> {
+        match self {
+            Self::Dictionary(dictionary) => Some(dictionary),
+            _ => None,
+        }
+    }
+    pub fn as_stream(&self) -> Option<&Stream> {
+        match self {
+            Self::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+    pub fn as_ref_id(&self) -> Option<ObjectId> {
+        match self {
+            Self::Ref(id) => Some(*id),
+            _ => None,
+        }
+    }
+
     pub fn write_to<W: Write>(&self, writer: &mut W) -> PDFResult<()> {
         match self {
             Self::Null => writer.write_all(b"null")?,
@@ -63,34 +128,41 @@ impl Object {
             Self::Name(name) => name.write_to(writer)?,
             Self::Str(string) => {
                 let mut write_string_bytes = |string_bytes: &[u8]| -> PDFResult<()> {
-                    writer.write(b"(")?;
+                    writer.write_all(b"(")?;
                     for &byte in string_bytes {
                         // Escape 0x28 `(`, 0x29 `)`, 0x5C `\` with 0x5C `\` in the PDF text
                         match byte {
                             b'(' | b')' | b'\\' => {
-                                writer.write(b"\\")?;
-                                writer.write(&[byte])?;
+                                writer.write_all(b"\\")?;
+                                writer.write_all(&[byte])?;
                             },
-                            _ => { writer.write(&[byte])?; },
+                            _ => { writer.write_all(&[byte])?; },
                         };
                     }
-                    writer.write(b")")?;
+                    writer.write_all(b")")?;
                     Ok(())
                 };
                 if string.is_ascii() {
                     write_string_bytes(string.as_bytes())?
                 } else {
-                    let utf16_bytes = crate::utils::to_utf16(&string);
+                    let utf16_bytes = crate::utils::to_utf16(string);
                     write_string_bytes(&utf16_bytes)?;
                 }
             },
+            Self::HexStr(bytes) => {
+                writer.write_all(b"<")?;
+                for byte in bytes {
+                    write!(writer, "{:02X}", byte)?;
+                }
+                writer.write_all(b">")?;
+            },
             Self::Array(array) => {
-                writer.write(b"[")?;
+                writer.write_all(b"[")?;
                 for object in array {
                     object.write_to(writer)?;
-                    writer.write(b" ")?;
+                    writer.write_all(b" ")?;
                 }
-                writer.write(b"]")?;
+                writer.write_all(b"]")?;
             },
             Self::Dictionary(dictionary) => dictionary.write_to(writer)?,
             Self::Stream(stream) => stream.write_to(writer)?,
@@ -106,9 +178,11 @@ impl Object {
 #[derive(Debug, Copy, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub struct ObjectId(u32, u16);
 impl ObjectId {
+    pub fn new(object_num: u32, gen_num: u16) -> ObjectId { ObjectId(object_num, gen_num) }
     /// Makes the generation number into a string. Will fail if it's more than 5 digits.
     pub fn gen_string(&self) -> String { format!("{:05}", self.1) }
     pub fn object_num(&self) -> u32 { self.0 }
+    pub fn gen_num(&self) -> u16 { self.1 }
     pub fn write_to<W: Write>(&self, writer: &mut W) -> PDFResult<()> {
         write!(writer, "{} {}", self.0, self.1)?;
         Ok(())
@@ -121,7 +195,13 @@ impl ObjectIdGenerator {
     pub fn new() -> ObjectIdGenerator {
         ObjectIdGenerator { next_id: 0 }
     }
+    /// For adding objects after the ones that are already in a document
+    pub fn starting_at(next_id: u32) -> ObjectIdGenerator {
+        ObjectIdGenerator { next_id }
+    }
 
+    /// The number that the next object will get
+    pub fn next_object_num(&self) -> u32 { self.next_id }
     pub fn next(&mut self, generation_num: u16) -> ObjectId {
         let given_id = self.next_id;
         self.next_id += 1;
@@ -133,52 +213,111 @@ impl ObjectIdGenerator {
 #[derive(Debug, Clone, Eq, Ord, PartialEq, PartialOrd)]
 pub struct Name(String);
 impl Name {
+    pub fn alternate() -> Name { Name::new("Alternate") }
+    pub fn ascent() -> Name { Name::new("Ascent") }
     pub fn author() -> Name { Name::new("Author") }
+    pub fn base_font() -> Name { Name::new("BaseFont") }
     pub fn bits_per_component() -> Name { Name::new("BitsPerComponent") }
+    pub fn cap_height() -> Name { Name::new("CapHeight") }
     pub fn catalog() -> Name { Name::new("Catalog") }
+    pub fn ccitt_fax_decode() -> Name { Name::new("CCITTFaxDecode") }
+    pub fn cid_system_info() -> Name { Name::new("CIDSystemInfo") }
+    pub fn cid_to_gid_map() -> Name { Name::new("CIDToGIDMap") }
     pub fn color_space() -> Name { Name::new("ColorSpace") }
+    pub fn color_transform() -> Name { Name::new("ColorTransform") }
+    pub fn colors() -> Name { Name::new("Colors") }
+    pub fn columns() -> Name { Name::new("Columns") }
     pub fn contents() -> Name { Name::new("Contents") }
     pub fn count() -> Name { Name::new("Count") }
     pub fn dct_decode() -> Name { Name::new("DCTDecode") }
+    pub fn decode() -> Name { Name::new("Decode") }
+    pub fn decode_parms() -> Name { Name::new("DecodeParms") }
+    pub fn descendant_fonts() -> Name { Name::new("DescendantFonts") }
+    pub fn descent() -> Name { Name::new("Descent") }
     pub fn dest() -> Name { Name::new("Dest") }
+    pub fn device_cmyk() -> Name { Name::new("DeviceCMYK") }
     pub fn device_gray() -> Name { Name::new("DeviceGray") }
     pub fn device_rgb() -> Name { Name::new("DeviceRGB") }
+    pub fn direction() -> Name { Name::new("Direction") }
+    pub fn display_doc_title() -> Name { Name::new("DisplayDocTitle") }
+    pub fn dw() -> Name { Name::new("DW") }
+    pub fn encoding() -> Name { Name::new("Encoding") }
     pub fn filter() -> Name { Name::new("Filter") }
     pub fn first() -> Name { Name::new("First") }
+    pub fn fit_window() -> Name { Name::new("FitWindow") }
+    pub fn flags() -> Name { Name::new("Flags") }
     pub fn flate_decode() -> Name { Name::new("FlateDecode") }
     pub fn font() -> Name { Name::new("Font") }
+    pub fn font_b_box() -> Name { Name::new("FontBBox") }
+    pub fn font_descriptor() -> Name { Name::new("FontDescriptor") }
+    pub fn font_file2() -> Name { Name::new("FontFile2") }
+    pub fn font_file3() -> Name { Name::new("FontFile3") }
+    pub fn font_name() -> Name { Name::new("FontName") }
+    pub fn hide_toolbar() -> Name { Name::new("HideToolbar") }
+    pub fn id() -> Name { Name::new("ID") }
+    pub fn icc_based() -> Name { Name::new("ICCBased") }
     pub fn image() -> Name { Name::new("Image") }
+    pub fn index() -> Name { Name::new("Index") }
+    pub fn indexed() -> Name { Name::new("Indexed") }
     pub fn info() -> Name { Name::new("Info") }
     pub fn height() -> Name { Name::new("Height") }
+    pub fn italic_angle() -> Name { Name::new("ItalicAngle") }
+    pub fn jbig2_decode() -> Name { Name::new("JBIG2Decode") }
+    pub fn k() -> Name { Name::new("K") }
     pub fn kids() -> Name { Name::new("Kids") }
     pub fn last() -> Name { Name::new("Last") }
     pub fn length() -> Name { Name::new("Length") }
+    pub fn length1() -> Name { Name::new("Length1") }
     pub fn media_box() -> Name { Name::new("MediaBox") }
+    pub fn n() -> Name { Name::new("N") }
     pub fn next() -> Name { Name::new("Next") }
+    pub fn nums() -> Name { Name::new("Nums") }
+    pub fn obj_stm() -> Name { Name::new("ObjStm") }
+    pub fn ordering() -> Name { Name::new("Ordering") }
     pub fn outlines() -> Name { Name::new("Outlines") }
+    pub fn p() -> Name { Name::new("P") }
     pub fn page() -> Name { Name::new("Page") }
+    pub fn page_label() -> Name { Name::new("PageLabel") }
+    pub fn page_labels() -> Name { Name::new("PageLabels") }
+    pub fn page_layout() -> Name { Name::new("PageLayout") }
+    pub fn page_mode() -> Name { Name::new("PageMode") }
     pub fn pages() -> Name { Name::new("Pages") }
     pub fn parent() -> Name { Name::new("Parent") }
+    pub fn predictor() -> Name { Name::new("Predictor") }
     pub fn prev() -> Name { Name::new("Prev") }
+    pub fn registry() -> Name { Name::new("Registry") }
     pub fn resources() -> Name { Name::new("Resources") }
     pub fn root() -> Name { Name::new("Root") }
+    pub fn rows() -> Name { Name::new("Rows") }
+    pub fn s() -> Name { Name::new("S") }
+    pub fn s_mask() -> Name { Name::new("SMask") }
     pub fn size() -> Name { Name::new("Size") }
+    pub fn st() -> Name { Name::new("St") }
+    pub fn stem_v() -> Name { Name::new("StemV") }
     pub fn subtype() -> Name { Name::new("Subtype") }
+    pub fn supplement() -> Name { Name::new("Supplement") }
     pub fn title() -> Name { Name::new("Title") }
+    pub fn to_unicode() -> Name { Name::new("ToUnicode") }
     pub fn type_name() -> Name { Name::new("Type") }
     pub fn type1() -> Name { Name::new("Type1") }
+    pub fn viewer_preferences() -> Name { Name::new("ViewerPreferences") }
+    pub fn w() -> Name { Name::new("W") }
+    pub fn w2() -> Name { Name::new("W2") }
     pub fn width() -> Name { Name::new("Width") }
     pub fn xobject() -> Name { Name::new("XObject") }
+    pub fn xref() -> Name { Name::new("XRef") }
+    pub fn xref_stm() -> Name { Name::new("XRefStm") }
 }
 impl Name {
     pub fn new(string: impl ToString) -> Name { Name(string.to_string()) }
+    pub fn as_str(&self) -> &str { &self.0 }
 
     fn write_to<W: Write>(&self, writer: &mut W) -> PDFResult<()> {
         let mut converted = vec![b'/'];
         for byte in self.0.bytes() {
             if byte == b'#' {
                 converted.write_all(b"#23")?;
-            } else if (0x21 <= byte && byte <= 0x7E) && !DELIMITER_CHARS.contains(&byte) {
+            } else if (0x21..=0x7E).contains(&byte) && !DELIMITER_CHARS.contains(&byte) {
                 converted.push(byte);
             } else {
                 write!(&mut converted, "#{:X}", byte)?;
@@ -190,22 +329,24 @@ impl Name {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Dictionary(BTreeMap<Name, Object>);
 impl Dictionary {
     pub fn new() -> Dictionary { Dictionary(BTreeMap::new()) }
 
     pub fn is_empty(&self) -> bool { self.0.is_empty() }
     pub fn iter(&self) -> impl Iterator<Item = (&Name, &Object)> { self.0.iter() }
+    pub fn get(&self, name: &Name) -> Option<&Object> { self.0.get(name) }
     pub fn insert<T: Into<Object>>(&mut self, name: Name, value: T) {
         self.0.insert(name, value.into());
     }
+    pub fn remove(&mut self, name: &Name) -> Option<Object> { self.0.remove(name) }
 
     pub fn write_to<W: Write>(&self, writer: &mut W) -> PDFResult<()> {
         writer.write_all(b"<<")?;
         for (name, object) in self.iter() {
             name.write_to(writer)?;
-            writer.write(b" ")?;
+            writer.write_all(b" ")?;
             object.write_to(writer)?;
         }
         writer.write_all(b">>")?;
@@ -221,6 +362,10 @@ impl Stream {
         Stream(dictionary, contents)
     }
 
+    pub fn dictionary(&self) -> &Dictionary { &self.0 }
+    /// The raw (still encoded) bytes of the stream
+    pub fn contents(&self) -> &[u8] { &self.1 }
+
     pub fn write_to<W: Write>(&self, writer: &mut W) -> PDFResult<()> {
         self.0.write_to(writer)?;
         writer.write_all(b"stream\n")?;
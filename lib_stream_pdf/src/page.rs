@@ -1,23 +1,53 @@
 use std::{
+    collections::{BTreeMap},
     io::{Write},
 };
 use crate::{
-    PDFResult,
-    Name, Dictionary, Stream, Object, ObjectId, ImageRef, PageRef,
+    PDFError, PDFResult,
+    Name, Dictionary, Stream, Object, ObjectId, FontRef, ImageRef, PageRef,
     Justify,
+    common_types::{Orientation},
+    compression::{FlateLevel},
+    font::{SharedFontSubset},
+    paragraph::{Line, Paragraph, TextBox},
+    text_layer::{TextLayer},
+    vertical_text::{VerticalRun},
 };
 
+enum Instruction {
+    Operator(String, Vec<Object>),
+    /// The glyph IDs get changed to the ones in the font subset when the page is added to a document
+    ShowGlyphs {
+        font_id: ObjectId,
+        is_vertical: bool,
+        glyphs: Vec<(u16, char)>,
+        /// Extra space after each glyph (in 1/1000ths of the font size). Empty if there isn't any.
+        extra_spacing: Vec<f64>,
+    },
+}
+
+/// Extra space that gets added between glyphs to justify a line
+#[derive(Copy, Clone, Default)]
+struct TextSpacing {
+    /// After every glyph
+    glyph_spacing: f64,
+    /// After every space, on top of the glyph spacing
+    word_spacing: f64,
+}
+
 pub struct PDFPage {
     width: f64,
     height: f64,
     xobject_dictionary: Dictionary,
-    instructions: Vec<(String, Vec<Object>)>,
+    font_dictionary: Dictionary,
+    instructions: Vec<Instruction>,
 }
 impl PDFPage {
     pub fn new(width: f64, height: f64) -> PDFPage {
         PDFPage {
             width, height,
             xobject_dictionary: Dictionary::new(),
+            font_dictionary: Dictionary::new(),
             instructions: Vec::new(),
         }
     }
@@ -25,6 +55,157 @@ impl PDFPage {
     /// Justify will use the left as the start.
     pub fn add_image(&mut self, image_ref: ImageRef, start_x_percent: f64, end_x_percent: f64,
     justify: Justify) {
+        let (x, y, scale_width, scale_height) = self.place_image(&image_ref, start_x_percent, end_x_percent,
+            justify);
+        // Make a new graphics frame so that we can easily change the view matrix
+        self.add_instruction("q", Vec::new());
+        // Translate it first
+        self.add_instruction("cm", vec![
+            1.into(), 0.into(), 0.into(), 1.into(), x.into(), y.into()
+        ]);
+        // Scale the image to fit the page
+        self.add_instruction("cm", vec![
+            scale_width.into(), 0.into(), 0.into(), scale_height.into(), 0.into(), 0.into()
+        ]);
+        // Flip or turn it upright
+        if image_ref.orientation != Orientation::Normal {
+            let matrix = image_ref.orientation.image_matrix();
+            self.add_instruction("cm", matrix.iter().map(|&number| number.into()).collect());
+        }
+        self.add_instruction("Do", vec![image_ref.ref_name.clone().into()]);
+        // Pop off the graphics frame we created
+        self.add_instruction("Q", Vec::new());
+
+        self.xobject_dictionary.insert(image_ref.ref_name, image_ref.id);
+    }
+    /// Puts invisible text over an image (added with the same arguments) so that it can be searched
+    /// and copied. Each word gets stretched to fit where it was found in the image.
+    pub fn add_text_layer(&mut self, image_ref: &ImageRef, start_x_percent: f64, end_x_percent: f64,
+    justify: Justify, text_layer: &TextLayer, font_ref: &FontRef) {
+        let (x, y, scale_width, scale_height) = self.place_image(image_ref, start_x_percent, end_x_percent,
+            justify);
+        let font = font_ref.font();
+        self.add_instruction("q", Vec::new());
+        // Render mode 3 doesn't fill or stroke the text
+        self.add_instruction("Tr", vec![3.into()]);
+        for word in text_layer.words() {
+            let (left, top, right, bottom) = word.bounding_box();
+            // The image's Y goes down from the top
+            let (left, right) = (x + left * scale_width, x + right * scale_width);
+            let (top, bottom) = (y + (1.0 - top) * scale_height, y + (1.0 - bottom) * scale_height);
+            self.add_instruction("q", Vec::new());
+            if word.is_vertical() {
+                let font_size = right - left;
+                let text_height = font.vertical_text_height(word.text(), font_size);
+                let scale = if text_height > 0.0 { (top - bottom) / text_height } else { 1.0 };
+                self.add_instruction("cm", vec![
+                    1.into(), 0.into(), 0.into(), scale.into(), ((left + right) / 2.0).into(), top.into()
+                ]);
+                self.add_vertical_text(font_ref, font_size, 0.0, 0.0, word.text());
+            } else {
+                // Fits the font's ascent and descent into the box
+                let em_height = font.ascent(1.0) - font.descent(1.0);
+                let font_size = if em_height > 0.0 { (top - bottom) / em_height } else { top - bottom };
+                let text_width = font.text_width(word.text(), font_size);
+                let scale = if text_width > 0.0 { (right - left) / text_width } else { 1.0 };
+                self.add_instruction("cm", vec![
+                    scale.into(), 0.into(), 0.into(), 1.into(), left.into(), bottom.into()
+                ]);
+                self.add_text(font_ref, font_size, 0.0, -font.descent(font_size), word.text());
+            }
+            self.add_instruction("Q", Vec::new());
+        }
+        self.add_instruction("Q", Vec::new());
+    }
+
+    /// Draws a single line of text with its baseline starting at (x, y)
+    pub fn add_text(&mut self, font_ref: &FontRef, font_size: f64, x: f64, y: f64, text: &str) {
+        self.add_spaced_text(font_ref, font_size, x, y, text, TextSpacing::default());
+    }
+    /// Draws a single line of text from top to bottom, centered on `x` and starting at `y`.
+    /// Latin text and punctuation without a vertical form are turned on their side, and short
+    /// numbers are written horizontally (tate-chu-yoko).
+    pub fn add_vertical_text(&mut self, font_ref: &FontRef, font_size: f64, x: f64, y: f64, text: &str) {
+        self.add_spaced_vertical_text(font_ref, font_size, x, y, text, TextSpacing::default());
+    }
+    /// Lays out as many lines of the paragraph as fit in the box. Whatever didn't fit is given back
+    /// so that it can go in another box.
+    pub fn add_paragraph(&mut self, mut paragraph: Paragraph, text_box: TextBox) -> Option<Paragraph> {
+        let is_vertical = paragraph.is_vertical();
+        let (line_length, box_depth) = if is_vertical {
+            (text_box.height, text_box.width)
+        } else {
+            (text_box.width, text_box.height)
+        };
+        // How far the lines have gone into the box (down for horizontal text, left for vertical text)
+        let mut line_position = 0.0;
+        for line in paragraph.layout_lines(line_length) {
+            if line_position + line.ruby_size + line.font_size > box_depth {
+                return Some(paragraph.split_off(line.start_index));
+            }
+            // The ruby goes in front of the line (above it, or to the right of it)
+            line_position += line.ruby_size;
+            let (start_offset, text_spacing) = justify_line(&paragraph, &line, line_length);
+            if is_vertical {
+                let x = text_box.x + text_box.width - line_position - line.font_size / 2.0;
+                let mut y = text_box.y + text_box.height - start_offset;
+                for segment in &line.segments {
+                    let (font_ref, font_size) = paragraph.span_style(segment.span_index);
+                    let ruby_padding = paragraph.ruby_padding(segment.span_index);
+                    let base_spacing = TextSpacing {
+                        glyph_spacing: text_spacing.glyph_spacing + ruby_padding,
+                        ..text_spacing
+                    };
+                    let base_height = self.add_spaced_vertical_text(font_ref, font_size, x, y - ruby_padding / 2.0,
+                        &segment.text, base_spacing);
+                    if let Some((ruby, ruby_size)) = paragraph.span_ruby(segment.span_index) {
+                        // Centered on the base text, without the spacing after its last character
+                        let center_y = y - (base_height - text_spacing.glyph_spacing) / 2.0;
+                        let ruby_height = font_ref.font().vertical_text_height(ruby, ruby_size);
+                        let ruby_x = x + font_size / 2.0 + ruby_size / 2.0;
+                        self.add_vertical_text(font_ref, ruby_size, ruby_x, center_y + ruby_height / 2.0, ruby);
+                    }
+                    y -= base_height;
+                }
+            } else {
+                let ascent = line.segments.iter()
+                    .map(|segment| {
+                        let (font_ref, font_size) = paragraph.span_style(segment.span_index);
+                        font_ref.font().ascent(font_size)
+                    })
+                    .fold(0.0, f64::max);
+                let baseline = text_box.y + text_box.height - line_position - ascent;
+                let mut x = text_box.x + start_offset;
+                for segment in &line.segments {
+                    let (font_ref, font_size) = paragraph.span_style(segment.span_index);
+                    let ruby_padding = paragraph.ruby_padding(segment.span_index);
+                    let base_spacing = TextSpacing {
+                        glyph_spacing: text_spacing.glyph_spacing + ruby_padding,
+                        ..text_spacing
+                    };
+                    let base_width = self.add_spaced_text(font_ref, font_size, x + ruby_padding / 2.0, baseline,
+                        &segment.text, base_spacing);
+                    if let Some((ruby, ruby_size)) = paragraph.span_ruby(segment.span_index) {
+                        // Centered on the base text, without the spacing after its last character
+                        let center_x = x + (base_width - text_spacing.glyph_spacing) / 2.0;
+                        let font = font_ref.font();
+                        let ruby_width = font.text_width(ruby, ruby_size);
+                        // Sits right on top of the base text
+                        let ruby_baseline = baseline + font.ascent(font_size) - font.descent(ruby_size);
+                        self.add_text(font_ref, ruby_size, center_x - ruby_width / 2.0, ruby_baseline, ruby);
+                    }
+                    x += base_width;
+                }
+            }
+            line_position += line.font_size * paragraph.line_spacing();
+        }
+        None
+    }
+}
+impl PDFPage {
+    /// Gives back the (x, y) of the bottom left of the image, and its (width, height) on the page
+    fn place_image(&self, image_ref: &ImageRef, start_x_percent: f64, end_x_percent: f64, justify: Justify)
+    -> (f64, f64, f64, f64) {
         let start_x = self.width * start_x_percent;
         let end_x = self.width * end_x_percent;
         let image_width_on_page = end_x - start_x;
@@ -55,50 +236,202 @@ impl PDFPage {
             // It's already centered and space between is the same as center here
             _ => (),
         }
-        // Make a new graphics frame so that we can easily change the view matrix
-        self.add_instruction("q", Vec::new());
-        // Translate it first
-        self.add_instruction("cm", vec![
-            1.into(), 0.into(), 0.into(), 1.into(), x.into(), y.into()
-        ]);
-        // Scale the image to fit the page
-        self.add_instruction("cm", vec![
-            scale_width.into(), 0.into(), 0.into(), scale_height.into(), 0.into(), 0.into()
-        ]);
-        self.add_instruction("Do", vec![image_ref.ref_name.clone().into()]);
-        // Pop off the graphics frame we created
-        self.add_instruction("Q", Vec::new());
-
-        self.xobject_dictionary.insert(image_ref.ref_name, image_ref.id);
+        (x, y, scale_width, scale_height)
+    }
+    fn add_instruction(&mut self, operator: impl ToString, arguments: Vec<Object>) {
+        self.instructions.push(Instruction::Operator(operator.to_string(), arguments));
     }
+    /// Gives back how far the text went, including the extra spacing
+    fn add_spaced_text(&mut self, font_ref: &FontRef, font_size: f64, x: f64, y: f64, text: &str,
+    text_spacing: TextSpacing) -> f64 {
+        self.add_instruction("BT", Vec::new());
+        self.add_instruction("Tf", vec![font_ref.ref_name.clone().into(), font_size.into()]);
+        self.add_instruction("Td", vec![x.into(), y.into()]);
+        let spacing_count = self.add_glyphs(font_ref, font_size, text, text_spacing);
+        self.add_instruction("ET", Vec::new());
 
-    pub fn make_content_stream(&self) -> PDFResult<Stream> {
-        let mut encoded_instructions: Vec<u8> = Vec::new();
-        for (operator, arguments) in &self.instructions {
-            for argument in arguments {
-                argument.write_to(&mut encoded_instructions)?;
-                encoded_instructions.push(b' ');
-            }
-            encoded_instructions.write_all(operator.as_bytes())?;
-            encoded_instructions.push(b'\n');
+        self.font_dictionary.insert(font_ref.ref_name.clone(), font_ref.id);
+        font_ref.font().text_width(text, font_size) + spacing_count
+    }
+    /// Gives back how far down the text went, including the extra spacing
+    /// Spaces aren't spread out any more than other characters in vertical text
+    fn add_spaced_vertical_text(&mut self, font_ref: &FontRef, font_size: f64, x: f64, y: f64, text: &str,
+    text_spacing: TextSpacing) -> f64 {
+        let glyph_spacing = text_spacing.glyph_spacing;
+        let column_spacing = TextSpacing { glyph_spacing, word_spacing: 0.0 };
+        let font = font_ref.font();
+        // Moves the baseline over so that the em box is centered on the line
+        let baseline_offset = (font.ascent(font_size) + font.descent(font_size)) / 2.0;
+        let mut current_y = y;
+        self.add_instruction("BT", Vec::new());
+        for vertical_run in crate::vertical_text::split_vertical_runs(font, text) {
+            let run_height = vertical_run.height(font, font_size);
+            let spacing_count = match vertical_run {
+                VerticalRun::Upright(glyphs) => {
+                    self.add_instruction("Tf", vec![font_ref.vertical_ref_name.clone().into(), font_size.into()]);
+                    self.add_instruction("Tm", vec![
+                        1.into(), 0.into(), 0.into(), 1.into(), x.into(), current_y.into()
+                    ]);
+                    let extra_spacing = make_extra_spacing(&glyphs, font_size, column_spacing);
+                    let spacing_count = glyph_spacing * glyphs.len() as f64;
+                    self.instructions.push(Instruction::ShowGlyphs {
+                        font_id: font_ref.id, is_vertical: true, glyphs, extra_spacing,
+                    });
+                    self.font_dictionary.insert(font_ref.vertical_ref_name.clone(), font_ref.vertical_id);
+                    spacing_count
+                },
+                VerticalRun::Sideways(text) => {
+                    self.add_instruction("Tf", vec![font_ref.ref_name.clone().into(), font_size.into()]);
+                    // Turned 90° clockwise so the tops of the glyphs face right
+                    self.add_instruction("Tm", vec![
+                        0.into(), (-1).into(), 1.into(), 0.into(), (x - baseline_offset).into(), current_y.into()
+                    ]);
+                    self.font_dictionary.insert(font_ref.ref_name.clone(), font_ref.id);
+                    self.add_glyphs(font_ref, font_size, &text, column_spacing)
+                },
+                VerticalRun::Horizontal(text) => {
+                    self.add_instruction("Tf", vec![font_ref.ref_name.clone().into(), font_size.into()]);
+                    // Squeeze it in if it's wider than a single character
+                    let text_width = font.text_width(&text, font_size);
+                    let scale = (font_size / text_width).min(1.0);
+                    let baseline_y = current_y - font_size / 2.0 - baseline_offset;
+                    self.add_instruction("Tm", vec![
+                        scale.into(), 0.into(), 0.into(), 1.into(),
+                        (x - text_width * scale / 2.0).into(), baseline_y.into()
+                    ]);
+                    self.add_glyphs(font_ref, font_size, &text, TextSpacing::default());
+                    self.font_dictionary.insert(font_ref.ref_name.clone(), font_ref.id);
+                    // It's treated like a single character
+                    glyph_spacing
+                },
+            };
+            current_y -= run_height + spacing_count;
         }
+        self.add_instruction("ET", Vec::new());
+        y - current_y
+    }
+    /// Shows the text with the current font (`Tj` or `TJ` if there's extra spacing).
+    /// Gives back how much extra spacing was added in total.
+    fn add_glyphs(&mut self, font_ref: &FontRef, font_size: f64, text: &str, text_spacing: TextSpacing)
+    -> f64 {
+        let glyphs: Vec<(u16, char)> = text.chars()
+            .map(|character| (font_ref.font.glyph_id(character), character))
+            .collect();
+        let extra_spacing = make_extra_spacing(&glyphs, font_size, text_spacing);
+        let space_count = glyphs.iter().filter(|&&(_, character)| character == ' ').count();
+        let spacing_count = text_spacing.glyph_spacing * glyphs.len() as f64 +
+            text_spacing.word_spacing * space_count as f64;
+        self.instructions.push(Instruction::ShowGlyphs {
+            font_id: font_ref.id, is_vertical: false, glyphs, extra_spacing,
+        });
+        spacing_count
+    }
+}
 
-        let compressed_content = crate::utils::flate_compress(&encoded_instructions, None)?;
-        let mut stream_dictionary = Dictionary::new();
-        stream_dictionary.insert(Name::filter(), Name::flate_decode());
-        Ok(Stream::new(stream_dictionary, compressed_content))
-        // Ok(Stream::new(Dictionary::new(), encoded_instructions))
+/// Converts the spacing to the text space units that `TJ` uses
+fn make_extra_spacing(glyphs: &[(u16, char)], font_size: f64, text_spacing: TextSpacing) -> Vec<f64> {
+    let TextSpacing { glyph_spacing, word_spacing } = text_spacing;
+    if glyph_spacing == 0.0 && word_spacing == 0.0 {
+        return Vec::new();
     }
+    glyphs.iter()
+        .map(|&(_, character)| {
+            let spacing = if character == ' ' { glyph_spacing + word_spacing } else { glyph_spacing };
+            spacing * 1000.0 / font_size
+        })
+        .collect()
 }
-impl PDFPage {
-    fn add_instruction(&mut self, operator: impl ToString, arguments: Vec<Object>) {
-        self.instructions.push( (operator.to_string(), arguments) );
+
+/// Gives back how far along the line it starts, and the spacing to spread it out
+fn justify_line(paragraph: &Paragraph, line: &Line, line_length: f64) -> (f64, TextSpacing) {
+    let leftover_length = (line_length - line.length).max(0.0);
+    let no_spacing = TextSpacing::default();
+    match paragraph.justify() {
+        Justify::Start => (0.0, no_spacing),
+        Justify::End => (leftover_length, no_spacing),
+        Justify::Center => (leftover_length / 2.0, no_spacing),
+        // The last line would get stretched out too far, so it stays at the start
+        Justify::SpaceBetween if line.is_last_line => (0.0, no_spacing),
+        // Spread words apart, or every character if there aren't any spaces (like Japanese)
+        Justify::SpaceBetween if line.space_count > 0 && !paragraph.is_vertical() => {
+            let word_spacing = leftover_length / line.space_count as f64;
+            (0.0, TextSpacing { glyph_spacing: 0.0, word_spacing })
+        },
+        Justify::SpaceBetween if line.character_count > 1 => {
+            let glyph_spacing = leftover_length / (line.character_count - 1) as f64;
+            (0.0, TextSpacing { glyph_spacing, word_spacing: 0.0 })
+        },
+        Justify::SpaceBetween => (0.0, no_spacing),
     }
 }
 
 pub fn ref_from_page(id: ObjectId, page: &PDFPage) -> PageRef {
     PageRef::new(id, page.height)
 }
+/// For pages that were read from a PDF. The height comes from the media box.
+pub fn ref_from_dictionary(id: ObjectId, page_dictionary: &Dictionary) -> PageRef {
+    let height = page_dictionary.get(&Name::media_box())
+        .and_then(Object::as_array)
+        .and_then(|media_box| Some(media_box.get(3)?.as_number()? - media_box.get(1)?.as_number()?))
+        .unwrap_or(0.0);
+    PageRef::new(id, height)
+}
+/// Any glyphs that get shown are added to the font subsets
+pub fn make_content_stream(page: &PDFPage, font_subsets: &BTreeMap<ObjectId, SharedFontSubset>,
+flate_level: FlateLevel) -> PDFResult<Stream> {
+    let mut encoded_instructions: Vec<u8> = Vec::new();
+    for instruction in &page.instructions {
+        match instruction {
+            Instruction::Operator(operator, arguments) => {
+                for argument in arguments {
+                    argument.write_to(&mut encoded_instructions)?;
+                    encoded_instructions.push(b' ');
+                }
+                encoded_instructions.write_all(operator.as_bytes())?;
+            },
+            Instruction::ShowGlyphs { font_id, is_vertical, glyphs, extra_spacing } => {
+                let mut font_subset = font_subsets.get(font_id)
+                    .map(crate::font::lock_subset)
+                    .ok_or(PDFError::FontNotInDocument)?;
+                if *is_vertical {
+                    font_subset.set_used_vertically();
+                }
+                let mut cids = glyphs.iter()
+                    .map(|&(glyph_id, character)| font_subset.use_glyph(glyph_id, character.encode_utf8(&mut [0; 4])));
+                if extra_spacing.is_empty() {
+                    // Identity-H and Identity-V use 2 bytes for each CID
+                    encoded_instructions.push(b'<');
+                    for cid in cids {
+                        write!(&mut encoded_instructions, "{:04X}", cid)?;
+                    }
+                    encoded_instructions.write_all(b"> Tj")?;
+                } else {
+                    // Positive numbers move the next glyph left for horizontal text, but down (further along
+                    //  the column) for vertical text, since they're taken away from the negative y advance
+                    let direction = if *is_vertical { 1.0 } else { -1.0 };
+                    encoded_instructions.write_all(b"[<")?;
+                    let glyph_count = glyphs.len();
+                    for (i, (cid, &spacing)) in cids.by_ref().zip(extra_spacing).enumerate() {
+                        write!(&mut encoded_instructions, "{:04X}", cid)?;
+                        // Spacing after the last glyph wouldn't do anything
+                        if spacing != 0.0 && i + 1 < glyph_count {
+                            encoded_instructions.write_all(b">")?;
+                            Object::from(spacing * direction).write_to(&mut encoded_instructions)?;
+                            encoded_instructions.write_all(b"<")?;
+                        }
+                    }
+                    encoded_instructions.write_all(b">] TJ")?;
+                }
+            },
+        }
+        encoded_instructions.push(b'\n');
+    }
+
+    let compressed_content = crate::utils::flate_compress(&encoded_instructions, None, flate_level)?;
+    let mut stream_dictionary = Dictionary::new();
+    stream_dictionary.insert(Name::filter(), Name::flate_decode());
+    Ok(Stream::new(stream_dictionary, compressed_content))
+}
 pub fn make_page_dictionary(parent_id: ObjectId, page: PDFPage, content_stream_ref: ObjectId)
 -> Dictionary {
     let mut page_dictionary = Dictionary::new();
@@ -111,6 +444,9 @@ pub fn make_page_dictionary(parent_id: ObjectId, page: PDFPage, content_stream_r
     if !page.xobject_dictionary.is_empty() {
         resource_dictionary.insert(Name::xobject(), page.xobject_dictionary);
     }
+    if !page.font_dictionary.is_empty() {
+        resource_dictionary.insert(Name::font(), page.font_dictionary);
+    }
     if !resource_dictionary.is_empty() {
         page_dictionary.insert(Name::resources(), resource_dictionary);
     }
@@ -0,0 +1,93 @@
+use std::{
+    collections::{BTreeMap},
+    num::{NonZeroU32},
+};
+use crate::{
+    objects::{Dictionary, Name, Object},
+};
+
+/// How the number part of a page label is written.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageLabelStyle {
+    /// 1, 2, 3
+    Decimal,
+    /// I, II, III
+    UpperRoman,
+    /// i, ii, iii
+    LowerRoman,
+    /// A to Z, then AA to ZZ
+    UpperAlpha,
+    /// a to z, then aa to zz
+    LowerAlpha,
+}
+impl PageLabelStyle {
+    fn pdf_name(&self) -> Name {
+        match self {
+            Self::Decimal => Name::new("D"),
+            Self::UpperRoman => Name::new("R"),
+            Self::LowerRoman => Name::new("r"),
+            Self::UpperAlpha => Name::new("A"),
+            Self::LowerAlpha => Name::new("a"),
+        }
+    }
+}
+
+/// The labels for a range of pages. The range goes until the next label starts.
+#[derive(Clone, Default)]
+pub struct PageLabel {
+    /// Without a style, the pages will only have the prefix as their label
+    style: Option<PageLabelStyle>,
+    prefix: Option<String>,
+    /// The number of the first page in the range (1 if it isn't set)
+    start_number: Option<NonZeroU32>,
+}
+impl PageLabel {
+    pub fn new() -> PageLabel {
+        PageLabel::default()
+    }
+    pub fn with_style(mut self, style: PageLabelStyle) -> PageLabel {
+        self.style = Some(style);
+        self
+    }
+    /// Goes in front of the number (like "Ch3-")
+    pub fn with_prefix(mut self, prefix: impl ToString) -> PageLabel {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+    /// Page labels count from 1, so they can't start at 0
+    pub fn with_start_number(mut self, start_number: NonZeroU32) -> PageLabel {
+        self.start_number = Some(start_number);
+        self
+    }
+
+    fn into_dictionary(self) -> Dictionary {
+        let mut label_dictionary = Dictionary::new();
+        label_dictionary.insert(Name::type_name(), Name::page_label());
+        if let Some(style) = self.style {
+            label_dictionary.insert(Name::s(), style.pdf_name());
+        }
+        if let Some(prefix) = self.prefix {
+            label_dictionary.insert(Name::p(), prefix);
+        }
+        if let Some(start_number) = self.start_number {
+            label_dictionary.insert(Name::st(), start_number.get());
+        }
+        label_dictionary
+    }
+}
+
+/// Makes the number tree for `/PageLabels`, keyed by the index of the first page in each range
+pub fn make_page_labels_tree(mut page_labels: BTreeMap<usize, PageLabel>) -> Dictionary {
+    // The first page always needs a label, so keep the normal numbering up until the first range
+    page_labels.entry(0)
+        .or_insert_with(|| PageLabel::new().with_style(PageLabelStyle::Decimal));
+
+    let mut nums: Vec<Object> = Vec::with_capacity(page_labels.len() * 2);
+    for (page_index, page_label) in page_labels {
+        nums.push(page_index.into());
+        nums.push(page_label.into_dictionary().into());
+    }
+    let mut number_tree = Dictionary::new();
+    number_tree.insert(Name::nums(), nums);
+    number_tree
+}
@@ -0,0 +1,350 @@
+use crate::{
+    FontRef, Justify,
+    vertical_text::{VerticalRun},
+};
+
+/// Characters that can't start a line (closing brackets, small kana, and most punctuation)
+const NO_LINE_START: &[char] = &[
+    '、', '。', '，', '．', '・', '：', '；', '？', '！', '‼', '⁇', '⁈', '⁉',
+    'ー', '‐', '゠', '–', '〜', '～', '…', '‥', '々', '〻', 'ゝ', 'ゞ', 'ヽ', 'ヾ',
+    '）', '」', '』', '】', '〕', '〉', '》', '〙', '〗', '］', '｝', '’', '”', '»', '〟',
+    'ぁ', 'ぃ', 'ぅ', 'ぇ', 'ぉ', 'っ', 'ゃ', 'ゅ', 'ょ', 'ゎ', 'ゕ', 'ゖ',
+    'ァ', 'ィ', 'ゥ', 'ェ', 'ォ', 'ッ', 'ャ', 'ュ', 'ョ', 'ヮ', 'ヵ', 'ヶ',
+    'ㇰ', 'ㇱ', 'ㇲ', 'ㇳ', 'ㇴ', 'ㇵ', 'ㇶ', 'ㇷ', 'ㇸ', 'ㇹ', 'ㇺ', 'ㇻ', 'ㇼ', 'ㇽ', 'ㇾ', 'ㇿ',
+    ')', ']', '}', ',', '.', ':', ';', '!', '?', '%', '\'', '"',
+];
+/// Characters that can't end a line (opening brackets)
+const NO_LINE_END: &[char] = &[
+    '（', '「', '『', '【', '〔', '〈', '《', '〘', '〖', '［', '｛', '‘', '“', '«', '〝',
+    '(', '[', '{',
+];
+/// Ruby is drawn at this much of the size of its base text
+const RUBY_SIZE_RATIO: f64 = 0.5;
+
+/// Text that uses the same font and size
+pub struct TextSpan {
+    font_ref: FontRef,
+    font_size: f64,
+    text: String,
+    /// Drawn in smaller text above the base text (or to the right of it in vertical text)
+    ruby: Option<String>,
+}
+
+/// Text that gets broken up into lines to fit in a box. Lines break at spaces or between CJK
+/// characters, but never leave punctuation at the start of a line or an opening bracket at the end.
+pub struct Paragraph {
+    spans: Vec<TextSpan>,
+    justify: Justify,
+    /// Multiplied by the biggest font size on each line
+    line_spacing: f64,
+    /// Lines go from top to bottom, and the lines go from right to left
+    is_vertical: bool,
+}
+impl Paragraph {
+    /// Each line gets justified on its own. With `SpaceBetween`, the last line (and any line
+    /// before a `\n`) stays at the start.
+    pub fn new(justify: Justify) -> Paragraph {
+        Paragraph {
+            spans: Vec::new(),
+            justify,
+            line_spacing: 1.5,
+            is_vertical: false,
+        }
+    }
+    pub fn with_line_spacing(mut self, line_spacing: f64) -> Paragraph {
+        self.line_spacing = line_spacing;
+        self
+    }
+    /// Writes the text in vertical lines (tategaki), starting from the right side of the box
+    pub fn with_vertical(mut self, is_vertical: bool) -> Paragraph {
+        self.is_vertical = is_vertical;
+        self
+    }
+    /// Any `\n` in the text starts a new line
+    pub fn add_text(&mut self, font_ref: &FontRef, font_size: f64, text: impl ToString) {
+        self.spans.push(TextSpan {
+            font_ref: font_ref.clone(),
+            font_size,
+            text: text.to_string(),
+            ruby: None,
+        });
+    }
+    /// Adds text with ruby (like furigana) that's centered over it. The base text is never split
+    /// onto different lines, and it gets spread out if the ruby is longer than it.
+    pub fn add_ruby_text(&mut self, font_ref: &FontRef, font_size: f64, text: impl ToString,
+    ruby: impl ToString) {
+        self.spans.push(TextSpan {
+            font_ref: font_ref.clone(),
+            font_size,
+            text: text.to_string(),
+            ruby: Some(ruby.to_string()),
+        });
+    }
+
+    pub fn justify(&self) -> Justify { self.justify }
+    pub fn line_spacing(&self) -> f64 { self.line_spacing }
+    pub fn is_vertical(&self) -> bool { self.is_vertical }
+    pub fn span_style(&self, span_index: usize) -> (&FontRef, f64) {
+        let span = &self.spans[span_index];
+        (&span.font_ref, span.font_size)
+    }
+    /// The ruby text for the span, and its font size
+    pub fn span_ruby(&self, span_index: usize) -> Option<(&str, f64)> {
+        let span = &self.spans[span_index];
+        span.ruby.as_deref().map(|ruby| (ruby, span.font_size * RUBY_SIZE_RATIO))
+    }
+    /// The extra space given to each base character so that the base text is as long as its ruby
+    pub fn ruby_padding(&self, span_index: usize) -> f64 {
+        let span = &self.spans[span_index];
+        match span.ruby.as_deref() {
+            Some(ruby) => {
+                let font = span.font_ref.font();
+                let ruby_size = span.font_size * RUBY_SIZE_RATIO;
+                let (base_length, ruby_length) = if self.is_vertical {
+                    (font.vertical_text_height(&span.text, span.font_size), font.vertical_text_height(ruby, ruby_size))
+                } else {
+                    (font.text_width(&span.text, span.font_size), font.text_width(ruby, ruby_size))
+                };
+                let base_count = span.text.chars().count().max(1);
+                (ruby_length - base_length).max(0.0) / base_count as f64
+            },
+            None => 0.0,
+        }
+    }
+
+    /// Breaks the text up into lines that are at most `line_length` long
+    pub fn layout_lines(&self, line_length: f64) -> Vec<Line> {
+        let characters = self.layout_characters();
+        let mut lines = Vec::new();
+        let mut line_start = 0;
+        while line_start < characters.len() {
+            let mut length = 0.0;
+            let mut last_break = None;
+            let mut line_end = characters.len();
+            let mut next_line_start = characters.len();
+            let mut is_last_line = true;
+            for i in line_start..characters.len() {
+                let character = &characters[i];
+                if character.character == '\n' {
+                    line_end = i;
+                    next_line_start = i + 1;
+                    break;
+                }
+                // Spaces can hang off the end of the line
+                if length + character.advance > line_length && character.character != ' ' && i > line_start {
+                    // Whatever is past the last place we could break goes onto the next line.
+                    // If there's nowhere to break (like a really long word), it has to break here,
+                    // unless that would split up text with ruby. Ruby that's too long for a whole
+                    // line gets to run past the end of it.
+                    let break_index = last_break.unwrap_or_else(|| {
+                        let ruby_start = self.ruby_group_start(&characters, i);
+                        if ruby_start > line_start { ruby_start } else { self.ruby_group_end(&characters, i) }
+                    });
+                    line_end = break_index;
+                    next_line_start = break_index;
+                    is_last_line = false;
+                    break;
+                }
+                length += character.advance;
+                let is_breakable = characters.get(i + 1).is_some_and(|next| {
+                    can_break_between(character.character, next.character) &&
+                        self.ruby_group_start(&characters, i + 1) == i + 1
+                });
+                if is_breakable {
+                    last_break = Some(i + 1);
+                }
+            }
+            // The line break is included so that empty lines still know their font size
+            lines.push(self.make_line(&characters[line_start..next_line_start.max(line_end)], line_start,
+                is_last_line));
+            line_start = next_line_start;
+            // A line that was broken at a space doesn't need to start with it
+            while !is_last_line && characters.get(line_start).is_some_and(|next| next.character == ' ') {
+                line_start += 1;
+            }
+        }
+        lines
+    }
+    /// Takes out everything from the line starting at `character_index` onwards.
+    /// Text with ruby is never split, so all of it goes with the new paragraph.
+    pub fn split_off(&mut self, character_index: usize) -> Paragraph {
+        let mut remaining_spans = Vec::new();
+        let mut span_start = 0;
+        for span in self.spans.iter_mut() {
+            let span_length = span.text.chars().count();
+            if span_start + span_length > character_index {
+                let split_at = if span.ruby.is_some() { 0 } else { character_index.saturating_sub(span_start) };
+                let byte_index = span.text.char_indices().nth(split_at)
+                    .map_or(span.text.len(), |(byte_index, _)| byte_index);
+                remaining_spans.push(TextSpan {
+                    font_ref: span.font_ref.clone(),
+                    font_size: span.font_size,
+                    text: span.text.split_off(byte_index),
+                    ruby: span.ruby.clone(),
+                });
+            }
+            span_start += span_length;
+        }
+        self.spans.retain(|span| !span.text.is_empty());
+        Paragraph {
+            spans: remaining_spans,
+            justify: self.justify,
+            line_spacing: self.line_spacing,
+            is_vertical: self.is_vertical,
+        }
+    }
+}
+impl Paragraph {
+    /// Every character with how far it moves along the line
+    fn layout_characters(&self) -> Vec<LayoutCharacter> {
+        let mut characters = Vec::new();
+        for (span_index, span) in self.spans.iter().enumerate() {
+            let font = span.font_ref.font();
+            let font_size = span.font_size;
+            if self.is_vertical {
+                // Vertical text can be turned or grouped, which changes how much space it takes up
+                for (line_index, line) in span.text.split('\n').enumerate() {
+                    if line_index > 0 {
+                        characters.push(LayoutCharacter { character: '\n', span_index, advance: 0.0 });
+                    }
+                    for vertical_run in crate::vertical_text::split_vertical_runs(font, line) {
+                        let advances: Vec<(char, f64)> = match &vertical_run {
+                            VerticalRun::Upright(glyphs) => glyphs.iter()
+                                .map(|&(glyph_id, character)| {
+                                    (character, font.advance_height(glyph_id) * font_size / 1000.0)
+                                })
+                                .collect(),
+                            VerticalRun::Sideways(text) => text.chars()
+                                .map(|character| (character, font.text_width(&character.to_string(), font_size)))
+                                .collect(),
+                            // The whole number takes up a single character's space
+                            VerticalRun::Horizontal(text) => {
+                                let advance = font_size / text.chars().count() as f64;
+                                text.chars().map(|character| (character, advance)).collect()
+                            },
+                        };
+                        let ruby_padding = self.ruby_padding(span_index);
+                        characters.extend(advances.into_iter().map(|(character, advance)| {
+                            LayoutCharacter { character, span_index, advance: advance + ruby_padding }
+                        }));
+                    }
+                }
+            } else {
+                let ruby_padding = self.ruby_padding(span_index);
+                characters.extend(span.text.chars().map(|character| LayoutCharacter {
+                    character,
+                    span_index,
+                    advance: font.text_width(&character.to_string(), font_size) + ruby_padding,
+                }));
+            }
+        }
+        characters
+    }
+    /// Where the text with ruby that `index` is in starts (or `index` if it doesn't have ruby)
+    fn ruby_group_start(&self, characters: &[LayoutCharacter], index: usize) -> usize {
+        let span_index = characters[index].span_index;
+        if self.spans[span_index].ruby.is_none() {
+            return index;
+        }
+        characters[..index].iter()
+            .rposition(|character| character.span_index != span_index)
+            .map_or(0, |before_index| before_index + 1)
+    }
+    /// Where the text with ruby that `index` is in ends (or just after `index` if it doesn't have ruby)
+    fn ruby_group_end(&self, characters: &[LayoutCharacter], index: usize) -> usize {
+        let span_index = characters[index].span_index;
+        if self.spans[span_index].ruby.is_none() {
+            return index + 1;
+        }
+        characters[index..].iter()
+            .position(|character| character.span_index != span_index || character.character == '\n')
+            .map_or(characters.len(), |after_index| index + after_index)
+    }
+
+    fn make_line(&self, characters: &[LayoutCharacter], start_index: usize, is_last_line: bool) -> Line {
+        let font_size = characters.iter()
+            .map(|character| self.spans[character.span_index].font_size)
+            .fold(0.0, f64::max);
+        // Trailing spaces don't count towards the length
+        let trimmed_length = characters.iter()
+            .rposition(|character| character.character != ' ' && character.character != '\n')
+            .map_or(0, |last_index| last_index + 1);
+        let characters = &characters[..trimmed_length];
+        let ruby_size = characters.iter()
+            .filter_map(|character| self.span_ruby(character.span_index))
+            .map(|(_, ruby_size)| ruby_size)
+            .fold(0.0, f64::max);
+
+        let mut segments: Vec<LineSegment> = Vec::new();
+        for character in characters {
+            match segments.last_mut() {
+                Some(segment) if segment.span_index == character.span_index => segment.text.push(character.character),
+                _ => segments.push(LineSegment {
+                    span_index: character.span_index,
+                    text: character.character.to_string(),
+                }),
+            }
+        }
+        Line {
+            segments,
+            length: characters.iter().map(|character| character.advance).sum(),
+            character_count: characters.len(),
+            space_count: characters.iter().filter(|character| character.character == ' ').count(),
+            font_size,
+            ruby_size,
+            start_index,
+            is_last_line,
+        }
+    }
+}
+
+struct LayoutCharacter {
+    character: char,
+    span_index: usize,
+    advance: f64,
+}
+
+/// Part of a line that's all from the same span
+pub struct LineSegment {
+    pub span_index: usize,
+    pub text: String,
+}
+
+pub struct Line {
+    pub segments: Vec<LineSegment>,
+    /// How long the line is, without any trailing spaces
+    pub length: f64,
+    pub character_count: usize,
+    pub space_count: usize,
+    /// The biggest font size on the line
+    pub font_size: f64,
+    /// The biggest ruby font size on the line (0 without any ruby). This much extra space is
+    /// given to the line for its ruby.
+    pub ruby_size: f64,
+    /// Where the line starts in the paragraph's text (in characters)
+    pub start_index: usize,
+    /// The last line in the paragraph or before a line break
+    pub is_last_line: bool,
+}
+
+/// Where a paragraph gets laid out on the page, from the bottom left like everything else
+#[derive(Copy, Clone)]
+pub struct TextBox {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+impl TextBox {
+    pub fn new(x: f64, y: f64, width: f64, height: f64) -> TextBox {
+        TextBox { x, y, width, height }
+    }
+}
+
+fn can_break_between(before: char, after: char) -> bool {
+    // Spaces stay at the end of the line
+    if after == ' ' || NO_LINE_END.contains(&before) || NO_LINE_START.contains(&after) {
+        return false;
+    }
+    before == ' ' || crate::utils::is_wide(before) || crate::utils::is_wide(after)
+}
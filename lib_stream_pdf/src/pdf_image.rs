@@ -1,153 +1,730 @@
 use std::{
+    collections::{HashMap},
+    convert::{TryFrom},
     path::{Path},
     fs,
     io::{Cursor},
 };
-use image::{ColorType, DynamicImage, ImageFormat, ImageOutputFormat, GenericImageView};
+use fax::{Color, VecWriter, encoder::{Encoder}};
+use image::{
+    ColorType, DynamicImage, GrayImage, GenericImageView, ImageError, ImageFormat,
+    error::{EncodingError},
+    io::{Reader as ImageReader},
+};
 use crate::{
-    PDFError, PDFResult, ImageRef,
-    Name, Dictionary, Stream, ObjectId,
+    PDFError, PDFResult,
+    Name, Dictionary, Stream, Object, ObjectId,
+    common_types::{Orientation},
+    compression::{ChromaSubsampling, CompressionOptions, FlateLevel},
+    icc::{ColourProfiles},
+    image_encoding::{EncodingPolicy, ImageEncoding},
+    jbig2::{SymbolMatching},
+    jpeg::{AdobeTransform, JpegHeader},
+    png::{PngColourType},
 };
 
+/// Indexes have to fit in a byte
+const MAX_PALETTE_SIZE: usize = 256;
+/// Gray images only get smaller with a palette if the indexes use 4 bits or less
+const MAX_GRAY_PALETTE_SIZE: usize = 16;
+
 pub struct PDFImage {
     image_bytes: Vec<u8>,
     width: u32,
     height: u32,
     image_type: ImageType,
     colour_type: ColourType,
+    /// 1 for bilevel images, 8 for everything else
+    bits_per_component: u8,
+    /// The alpha channel, since PDF images can't have one of their own
+    soft_mask: Option<Box<PDFImage>>,
+    /// Only for JPEGs that are used directly
+    adobe_transform: Option<AdobeTransform>,
+    /// JPEGs are turned upright by the page instead of re-encoding them
+    orientation: Orientation,
+    encoding: ImageEncoding,
+    /// The ICC profile of the colours (the base colours for indexed images)
+    icc_profile: Option<Vec<u8>>,
 }
 impl PDFImage {
     pub fn from_path(image_path: impl AsRef<Path>, lossless: bool) -> PDFResult<PDFImage> {
         let image_path = image_path.as_ref();
         let image_bytes = fs::read(image_path)?;
-        match Self::from_bytes(image_bytes, lossless) {
-            Err(PDFError::BadImageColourType(message)) => {
-                Err(PDFError::BadImageColourType(
-                    format!("{} path={}", message, image_path.display())
-                ))
-            },
-            other => other,
-        }
+        add_path_to_error(Self::from_bytes(image_bytes, lossless), image_path)
     }
+    pub fn from_path_with_policy(image_path: impl AsRef<Path>, policy: &EncodingPolicy)
+    -> PDFResult<PDFImage> {
+        let image_path = image_path.as_ref();
+        let image_bytes = fs::read(image_path)?;
+        add_path_to_error(Self::from_bytes_with_policy(image_bytes, policy), image_path)
+    }
+    /// JPEGs are used directly, so only their header gets read. Everything else gets decoded.
+    /// When lossless, 8 bit gray and RGB PNGs keep their compressed data if it isn't bigger than
+    /// encoding them again.
+    /// Any ICC profile is kept.
     pub fn from_bytes(image_bytes: Vec<u8>, lossless: bool) -> PDFResult<PDFImage> {
-        let image = image::load_from_memory(&image_bytes)?;
-        let width = image.width();
-        let height = image.height();
+        Self::from_bytes_with_compression(image_bytes, lossless, &CompressionOptions::new())
+    }
+    /// Like `from_bytes`, but any image that gets encoded again uses the compression options
+    pub fn from_bytes_with_compression(image_bytes: Vec<u8>, lossless: bool, compression: &CompressionOptions)
+    -> PDFResult<PDFImage> {
+        let icc_profile = crate::icc::read_icc_profile(&image_bytes);
+        let mut pdf_image = match crate::jpeg::read_header(&image_bytes) {
+            Some(header) if header.can_pass_through() => Self::from_jpeg(image_bytes, header)?,
+            _ => {
+                let image = image::load_from_memory(&image_bytes)?;
+                let pdf_image = Self::from_image_with_compression(image, lossless, compression)?;
+                // PNGs can keep their compressed data, unless they could be made bilevel, grayscale or
+                // indexed, or compressed better
+                let passthrough = if lossless { Self::from_png(&image_bytes) } else { None };
+                match passthrough {
+                    Some(passthrough) if pdf_image.soft_mask.is_none()
+                    && passthrough.image_bytes.len() <= pdf_image.image_bytes.len() => passthrough,
+                    _ => pdf_image,
+                }
+            },
+        };
+        pdf_image.set_icc_profile(icc_profile);
+        Ok(pdf_image)
+    }
+    /// Like `from_image_with_policy`, but the JPEG or PNG data can also be used as it is. Images are
+    /// only decoded for their colours to be converted if their profile isn't already the standard one.
+    pub fn from_bytes_with_policy(image_bytes: Vec<u8>, policy: &EncodingPolicy) -> PDFResult<PDFImage> {
+        let icc_profile = crate::icc::read_icc_profile(&image_bytes);
+        let jpeg_header = crate::jpeg::read_header(&image_bytes);
+        if policy.colour_profiles() == ColourProfiles::ConvertToStandard {
+            if let Some(icc_profile) = icc_profile.as_ref().filter(|icc_profile| !crate::icc::is_standard(icc_profile)) {
+                let image = match &jpeg_header {
+                    Some(header) if header.components() == 4 =>
+                        crate::icc::convert_cmyk_jpeg_to_standard(&image_bytes, icc_profile)?,
+                    _ => crate::icc::convert_to_standard(image::load_from_memory(&image_bytes)?, icc_profile)?,
+                };
+                let mut pdf_image = Self::from_image_with_policy(image, policy)?;
+                // The decoded image isn't upright, so the page has to turn it like the JPEG
+                if let Some(header) = jpeg_header {
+                    pdf_image.orientation = header.orientation();
+                }
+                pdf_image.set_standard_icc_profile();
+                return Ok(pdf_image);
+            }
+        }
 
-        // Check if this is already a JPG image that we can use directly
-        // We can unwrap it because we already know that we can read in the image
-        match image::guess_format(&image_bytes)? {
-            ImageFormat::Jpeg => {
-                let colour_type = ColourType::from_image_colour_type(image.color())
-                    .ok_or_else(|| PDFError::BadImageColourType(
-                        format!("colour={:?} width={} height={}", image.color(), width, height)
-                    ))?;
-                Ok(PDFImage {
-                    image_bytes,
-                    width,
-                    height,
-                    image_type: ImageType::Jpg,
-                    colour_type,
-                })
+        let image = image::load_from_memory(&image_bytes)?;
+        let passthrough = match jpeg_header {
+            Some(header) if header.can_pass_through() => Self::from_jpeg(image_bytes.clone(), header).ok(),
+            _ => Self::from_png(&image_bytes),
+        };
+        let mut pdf_image = Self::from_image_with_policy(image, policy)?;
+        let mut pdf_image = match passthrough {
+            Some(passthrough) if passthrough.image_bytes.len() <= pdf_image.image_bytes.len() => passthrough,
+            Some(passthrough) => {
+                // The decoded image isn't upright, so the page has to turn it like the JPEG
+                pdf_image.orientation = passthrough.orientation;
+                pdf_image
             },
-            _ => Self::from_image(image, lossless),
+            None => pdf_image,
+        };
+        match policy.colour_profiles() {
+            ColourProfiles::Keep => pdf_image.set_icc_profile(icc_profile),
+            // Images without a profile are taken to already be standard
+            ColourProfiles::ConvertToStandard => pdf_image.set_standard_icc_profile(),
         }
+        Ok(pdf_image)
     }
+    /// Reads the size of the upright image from the header of a JPEG or PNG, without decoding it
+    pub fn read_dimensions(image_bytes: &[u8]) -> PDFResult<(u32, u32)> {
+        if let Some(header) = crate::jpeg::read_header(image_bytes) {
+            if header.orientation().swaps_dimensions() {
+                return Ok((header.height(), header.width()));
+            }
+            return Ok((header.width(), header.height()));
+        }
+        if let Some(header) = crate::png::read_header(image_bytes) {
+            return Ok((header.width(), header.height()));
+        }
+        let reader = ImageReader::new(Cursor::new(image_bytes)).with_guessed_format()?;
+        Ok(reader.into_dimensions()?)
+    }
+    /// Any transparency is kept as a soft mask
     pub fn from_image(image: DynamicImage, lossless: bool) -> PDFResult<PDFImage> {
-        let try_to_convert_to_grayscale = |image, old_colour_type| {
-            // Sample the image first to see if it can be turned to grayscale
-            // We will only grayscale non-colour images
-            //  This should save some space for all of the images that are just black and white
-            if image_can_be_grayscale(&image) {
-                (DynamicImage::ImageLuma8(image.to_luma8()), ColourType::Gray)
-            } else {
-                (image, old_colour_type)
+        Self::from_image_with_compression(image, lossless, &CompressionOptions::new())
+    }
+    pub fn from_image_with_compression(image: DynamicImage, lossless: bool, compression: &CompressionOptions)
+    -> PDFResult<PDFImage> {
+        check_not_empty(&image)?;
+        let soft_mask = make_soft_mask(&image, compression.flate_level())?;
+        let mut pdf_image = Self::from_opaque_image(image, lossless, compression)?;
+        pdf_image.soft_mask = soft_mask.map(Box::new);
+        Ok(pdf_image)
+    }
+    /// Tries every encoding that the policy allows and keeps the smallest one
+    pub fn from_image_with_policy(image: DynamicImage, policy: &EncodingPolicy) -> PDFResult<PDFImage> {
+        check_not_empty(&image)?;
+        let soft_mask = make_soft_mask(&image, policy.compression().flate_level())?;
+        let mut pdf_image = Self::from_opaque_image_with_policy(image, policy)?;
+        pdf_image.soft_mask = soft_mask.map(Box::new);
+        Ok(pdf_image)
+    }
+    pub fn encoding(&self) -> ImageEncoding { self.encoding }
+
+    /// The profile is dropped if it doesn't have as many components as the colours, like when an
+    /// RGB image was made grayscale.
+    fn set_icc_profile(&mut self, icc_profile: Option<Vec<u8>>) {
+        let components = self.colour_type.base().components();
+        self.icc_profile = icc_profile
+            .filter(|icc_profile| crate::icc::profile_components(icc_profile) == Some(components));
+    }
+    fn set_standard_icc_profile(&mut self) {
+        self.set_icc_profile(crate::icc::standard_profile(self.colour_type.base().components()));
+    }
+
+    fn from_opaque_image_with_policy(image: DynamicImage, policy: &EncodingPolicy) -> PDFResult<PDFImage> {
+        let (image, colour_type) = make_pdf_colours(image);
+        let compression = policy.compression();
+        // Lossless is always good enough
+        let mut candidates = vec![Self::from_opaque_image(image.clone(), true, compression)?];
+        if policy.allows_lossy() {
+            let is_good_enough = |changed_samples: &[u8]| {
+                crate::image_encoding::psnr(image.as_bytes(), changed_samples) >= policy.min_psnr()
+            };
+
+            let jpeg_bytes = encode_jpeg(&image, compression)?;
+            if is_good_enough(image::load_from_memory(&jpeg_bytes)?.as_bytes()) {
+                let (width, height) = image.dimensions();
+                candidates.push(PDFImage {
+                    image_bytes: jpeg_bytes, width, height,
+                    image_type: ImageType::Jpg,
+                    colour_type: colour_type.clone(),
+                    bits_per_component: 8,
+                    soft_mask: None,
+                    adobe_transform: None,
+                    orientation: Orientation::Normal,
+                    encoding: ImageEncoding::Jpeg,
+                    icc_profile: None,
+                });
+            }
+            // Images that are only black and white were already found by the lossless encoding. Nearly
+            // bilevel ones are left to the PSNR to judge.
+            if colour_type == ColourType::Gray && !image_is_bilevel(&image) {
+                let threshold = 128;
+                let bilevel_samples: Vec<u8> = image.as_bytes().iter()
+                    .map(|&sample| if sample >= threshold { u8::MAX } else { 0 })
+                    .collect();
+                if is_good_enough(&bilevel_samples) {
+                    candidates.push(Self::from_image_bilevel_with_compression(image.clone(), threshold, compression)?);
+                }
             }
+            let tolerance = policy.quantise_tolerance();
+            if let Some(palette) = find_palette(&image, tolerance, MAX_PALETTE_SIZE) {
+                if is_good_enough(&palette.samples()) {
+                    candidates.push(Self::from_image_quantised_with_compression(image.clone(), tolerance, compression)?);
+                }
+            }
+        }
+        Ok(candidates.into_iter()
+            .min_by_key(|candidate| candidate.image_bytes.len())
+            .unwrap())
+    }
+    fn from_jpeg(image_bytes: Vec<u8>, header: JpegHeader) -> PDFResult<PDFImage> {
+        let (width, height) = (header.width(), header.height());
+        let colour_type = ColourType::from_jpeg_components(header.components())
+            .ok_or_else(|| PDFError::BadImageColourType(
+                format!("components={} width={} height={}", header.components(), width, height)
+            ))?;
+        // The metadata isn't needed once the header has been read
+        let image_bytes = crate::jpeg::strip_metadata(&image_bytes).unwrap_or(image_bytes);
+        Ok(PDFImage {
+            image_bytes,
+            width,
+            height,
+            image_type: ImageType::Jpg,
+            colour_type,
+            bits_per_component: 8,
+            soft_mask: None,
+            adobe_transform: header.adobe_transform(),
+            orientation: header.orientation(),
+            encoding: ImageEncoding::Passthrough,
+            icc_profile: None,
+        })
+    }
+    /// Uses the IDAT data of 8 bit gray and RGB PNGs with the PNG predictors, instead of compressing
+    /// it again. Only the chunks get read.
+    fn from_png(png_bytes: &[u8]) -> Option<PDFImage> {
+        let header = crate::png::read_header(png_bytes)?;
+        if header.bit_depth() != 8 || header.interlaced() {
+            return None;
+        }
+        let colour_type = match header.colour_type() {
+            PngColourType::Gray => ColourType::Gray,
+            PngColourType::Rgb => ColourType::Rgb,
+            _ => return None,
         };
+        Some(PDFImage {
+            image_bytes: crate::png::read_image_data(png_bytes)?,
+            width: header.width(),
+            height: header.height(),
+            image_type: ImageType::FlatePredictor,
+            colour_type,
+            bits_per_component: 8,
+            soft_mask: None,
+            adobe_transform: None,
+            orientation: Orientation::Normal,
+            encoding: ImageEncoding::Passthrough,
+            icc_profile: None,
+        })
+    }
+    fn from_opaque_image(image: DynamicImage, lossless: bool, compression: &CompressionOptions)
+    -> PDFResult<PDFImage> {
+        let (image, colour_type) = make_pdf_colours(image);
 
-        // We need to make sure that the PDF can handle the image
-        //  We can make it not use any weird color channels to accomplish this
-        let (image, colour_type) = match image.color() {
-            ColorType::L8 => (image, ColourType::Gray),
-            ColorType::Rgb8 => try_to_convert_to_grayscale(image, ColourType::RGB),
-
-            ColorType::La8 => (
-                DynamicImage::ImageLuma8(image.to_luma8()),
-                ColourType::Gray
-            ),
-
-            _ => try_to_convert_to_grayscale(
-                DynamicImage::ImageRgb8(image.to_rgb8()),
-                ColourType::RGB
-            ),
-        };
+        // Black and white line art only needs a single bit for each pixel
+        if lossless && colour_type == ColourType::Gray && image_is_bilevel(&image) {
+            return Self::encode_bilevel(image, 128, compression.flate_level());
+        }
+        // Images with only a few colours can point into a palette with fewer bits
+        if lossless {
+            let max_palette_size = match colour_type {
+                ColourType::Gray => MAX_GRAY_PALETTE_SIZE,
+                _ => MAX_PALETTE_SIZE,
+            };
+            if let Some(palette) = find_palette(&image, 0, max_palette_size) {
+                return Self::from_palette(&image, colour_type, palette, compression.flate_level());
+            }
+        }
 
         let width = image.width();
         let height = image.height();
         // Make a rough estimate for the compressed image size so it's not quite so inefficient
-        let rough_size = (width * height) as usize;
+        let rough_size = width as usize * height as usize;
 
-        let (image_bytes, image_type) = if lossless {
-            let image_bytes = crate::utils::flate_compress(image.as_bytes(), Some(rough_size))?;
-            (image_bytes, ImageType::FlateLossless)
+        let (image_bytes, image_type, encoding) = if lossless {
+            let image_bytes = crate::utils::flate_compress(image.as_bytes(), Some(rough_size), compression.flate_level())?;
+            (image_bytes, ImageType::FlateLossless, ImageEncoding::Flate)
         } else {
             // Convert a JPG for virtually lossless
             //  But still have a decent file size reduction
-            let mut image_bytes = Cursor::new(Vec::with_capacity(rough_size));
-            image.write_to(&mut image_bytes, ImageOutputFormat::Jpeg(90))?;
-            (image_bytes.into_inner(), ImageType::Jpg)
+            (encode_jpeg(&image, compression)?, ImageType::Jpg, ImageEncoding::Jpeg)
         };
-        Ok(PDFImage { image_bytes, width, height, image_type, colour_type })
+        Ok(PDFImage { image_bytes, width, height, image_type, colour_type, bits_per_component: 8, soft_mask: None,
+            adobe_transform: None, orientation: Orientation::Normal, encoding, icc_profile: None })
+    }
+    /// Makes an indexed image out of one that's nearly made of a few colours (like a cleaned scan
+    /// with some noise). Colours within the tolerance of a more common colour are changed to it.
+    /// Images that have too many colours for a palette, or a colour that would end up further off
+    /// than the tolerance, are made like a lossless `from_image`.
+    pub fn from_image_quantised(image: DynamicImage, tolerance: u8) -> PDFResult<PDFImage> {
+        Self::from_image_quantised_with_compression(image, tolerance, &CompressionOptions::new())
+    }
+    pub fn from_image_quantised_with_compression(image: DynamicImage, tolerance: u8,
+    compression: &CompressionOptions) -> PDFResult<PDFImage> {
+        check_not_empty(&image)?;
+        let soft_mask = make_soft_mask(&image, compression.flate_level())?;
+        let (image, colour_type) = make_pdf_colours(image);
+        let mut pdf_image = match find_palette(&image, tolerance, MAX_PALETTE_SIZE) {
+            Some(palette) => Self::from_palette(&image, colour_type, palette, compression.flate_level())?,
+            None => Self::from_opaque_image(image, true, compression)?,
+        };
+        pdf_image.soft_mask = soft_mask.map(Box::new);
+        Ok(pdf_image)
+    }
+    /// Makes a black and white image, where anything darker than the threshold is black.
+    /// It's compressed with lossless JBIG2 (with or without symbols), CCITT Group 4 or Flate,
+    /// whichever is smallest.
+    pub fn from_image_bilevel(image: DynamicImage, threshold: u8) -> PDFResult<PDFImage> {
+        Self::from_image_bilevel_with_compression(image, threshold, &CompressionOptions::new())
+    }
+    pub fn from_image_bilevel_with_compression(image: DynamicImage, threshold: u8, compression: &CompressionOptions)
+    -> PDFResult<PDFImage> {
+        check_not_empty(&image)?;
+        Self::encode_bilevel(image, threshold, compression.flate_level())
+    }
+    fn encode_bilevel(image: DynamicImage, threshold: u8, flate_level: FlateLevel) -> PDFResult<PDFImage> {
+        let image = image.into_luma8();
+        let (width, height) = image.dimensions();
+
+        let flate_bytes = crate::utils::flate_compress(&pack_bilevel_rows(&image, threshold),
+            Some(width as usize * height as usize / 64), flate_level)?;
+        let mut encodings = vec![
+            (flate_bytes, ImageType::FlateLossless),
+            (crate::jbig2::encode_image(&image, threshold, SymbolMatching::Off)?, ImageType::JBIG2),
+            (crate::jbig2::encode_image(&image, threshold, SymbolMatching::Exact)?, ImageType::JBIG2),
+        ];
+        if let Some(ccitt_bytes) = encode_ccitt_group_4(&image, threshold) {
+            encodings.push((ccitt_bytes, ImageType::CCITTGroup4));
+        }
+        let (image_bytes, image_type) = encodings.into_iter()
+            .min_by_key(|(image_bytes, _)| image_bytes.len())
+            .unwrap();
+        Ok(PDFImage {
+            image_bytes, width, height, image_type,
+            colour_type: ColourType::Gray,
+            bits_per_component: 1,
+            soft_mask: None,
+            adobe_transform: None,
+            orientation: Orientation::Normal,
+            encoding: ImageEncoding::Bilevel,
+            icc_profile: None,
+        })
+    }
+    fn from_palette(image: &DynamicImage, base_colour_type: ColourType, palette: Palette, flate_level: FlateLevel)
+    -> PDFResult<PDFImage> {
+        let (width, height) = image.dimensions();
+        let bits_per_component = match palette.colour_count {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        };
+        let packed_indexes = pack_indexes(&palette.indexes, width, bits_per_component);
+        let image_bytes = crate::utils::flate_compress(&packed_indexes, Some(packed_indexes.len() / 4), flate_level)?;
+        Ok(PDFImage {
+            image_bytes, width, height,
+            image_type: ImageType::FlateLossless,
+            colour_type: ColourType::Indexed { base: Box::new(base_colour_type), palette: palette.colours },
+            bits_per_component,
+            soft_mask: None,
+            adobe_transform: None,
+            orientation: Orientation::Normal,
+            encoding: ImageEncoding::Indexed,
+            icc_profile: None,
+        })
+    }
+    /// Makes a black and white image that's always compressed with JBIG2. Repeated shapes (like
+    /// lettering) can be stored once as symbols, which is only lossy with `SymbolMatching::Similar`.
+    pub fn from_image_jbig2(image: DynamicImage, threshold: u8, symbol_matching: SymbolMatching)
+    -> PDFResult<PDFImage> {
+        check_not_empty(&image)?;
+        let image = image.into_luma8();
+        let (width, height) = image.dimensions();
+        Ok(PDFImage {
+            image_bytes: crate::jbig2::encode_image(&image, threshold, symbol_matching)?,
+            width, height,
+            image_type: ImageType::JBIG2,
+            colour_type: ColourType::Gray,
+            bits_per_component: 1,
+            soft_mask: None,
+            adobe_transform: None,
+            orientation: Orientation::Normal,
+            encoding: ImageEncoding::Bilevel,
+            icc_profile: None,
+        })
     }
 }
 
-pub fn ref_from_image(id: ObjectId, image: &PDFImage) -> ImageRef {
-    ImageRef::new(id, image.width, image.height)
+/// The (width, height) that the page uses, and how the image gets turned upright
+pub fn upright_size(image: &PDFImage) -> (u32, u32, Orientation) {
+    if image.orientation.swaps_dimensions() {
+        (image.height, image.width, image.orientation)
+    } else {
+        (image.width, image.height, image.orientation)
+    }
+}
+/// The soft mask needs to be written as its own image before the image that uses it
+pub fn take_soft_mask(image: &mut PDFImage) -> Option<PDFImage> {
+    image.soft_mask.take().map(|soft_mask| *soft_mask)
+}
+/// How many bytes the image is holding onto until it's written
+pub fn memory_size(image: &PDFImage) -> u64 {
+    let soft_mask_size = image.soft_mask.as_ref().map(|soft_mask| memory_size(soft_mask)).unwrap_or(0);
+    let icc_profile_size = image.icc_profile.as_ref().map(Vec::len).unwrap_or(0);
+    (image.image_bytes.len() + icc_profile_size) as u64 + soft_mask_size
+}
+/// The ICC profile gets written as its own stream, which can be shared between images
+pub fn take_icc_profile(image: &mut PDFImage) -> Option<Vec<u8>> {
+    image.icc_profile.take()
 }
-pub fn make_image_stream(image: PDFImage) -> Stream {
+pub fn make_image_stream(image: PDFImage, soft_mask_id: Option<ObjectId>, icc_profile_id: Option<ObjectId>) -> Stream {
     let mut image_dictionary = Dictionary::new();
     image_dictionary.insert(Name::type_name(), Name::xobject());
     image_dictionary.insert(Name::subtype(), Name::image());
     image_dictionary.insert(Name::width(), image.width);
     image_dictionary.insert(Name::height(), image.height);
-    image_dictionary.insert(Name::color_space(), image.colour_type.pdf_colour_space());
-    image_dictionary.insert(Name::bits_per_component(), 8);
+    image_dictionary.insert(Name::color_space(), image.colour_type.pdf_colour_space(icc_profile_id));
+    image_dictionary.insert(Name::bits_per_component(), image.bits_per_component);
     image_dictionary.insert(Name::filter(), image.image_type.pdf_filter());
+    if let ImageType::CCITTGroup4 = image.image_type {
+        let mut decode_parms = Dictionary::new();
+        // Pure two-dimensional encoding (Group 4)
+        decode_parms.insert(Name::k(), -1);
+        decode_parms.insert(Name::columns(), image.width);
+        decode_parms.insert(Name::rows(), image.height);
+        image_dictionary.insert(Name::decode_parms(), decode_parms);
+    }
+    if let ImageType::FlatePredictor = image.image_type {
+        let mut decode_parms = Dictionary::new();
+        // The PNG filter type is chosen for each row
+        decode_parms.insert(Name::predictor(), 15);
+        decode_parms.insert(Name::colors(), image.colour_type.components());
+        decode_parms.insert(Name::bits_per_component(), image.bits_per_component);
+        decode_parms.insert(Name::columns(), image.width);
+        image_dictionary.insert(Name::decode_parms(), decode_parms);
+    }
+    if image.colour_type == ColourType::Cmyk {
+        if let Some(adobe_transform) = image.adobe_transform {
+            // Adobe writes CMYK JPEGs with every component inverted
+            image_dictionary.insert(Name::decode(), vec![1, 0, 1, 0, 1, 0, 1, 0]);
+            if adobe_transform == AdobeTransform::Ycck {
+                let mut decode_parms = Dictionary::new();
+                decode_parms.insert(Name::color_transform(), 1);
+                image_dictionary.insert(Name::decode_parms(), decode_parms);
+            }
+        }
+    }
+    if let Some(soft_mask_id) = soft_mask_id {
+        image_dictionary.insert(Name::s_mask(), soft_mask_id);
+    }
     Stream::new(image_dictionary, image.image_bytes)
 }
 
+/// Makes a gray image out of the alpha channel, unless the image is completely opaque
+fn make_soft_mask(image: &DynamicImage, flate_level: FlateLevel) -> PDFResult<Option<PDFImage>> {
+    if !image.color().has_alpha() {
+        return Ok(None);
+    }
+    let alpha: Vec<u8> = image.to_rgba8().pixels()
+        .map(|pixel| pixel[3])
+        .collect();
+    if alpha.iter().all(|&alpha| alpha == u8::MAX) {
+        return Ok(None);
+    }
+    let (width, height) = image.dimensions();
+    let image_bytes = crate::utils::flate_compress(&alpha, Some(alpha.len() / 4), flate_level)?;
+    Ok(Some(PDFImage {
+        image_bytes, width, height,
+        image_type: ImageType::FlateLossless,
+        colour_type: ColourType::Gray,
+        bits_per_component: 8,
+        soft_mask: None,
+        adobe_transform: None,
+        orientation: Orientation::Normal,
+        encoding: ImageEncoding::Flate,
+        icc_profile: None,
+    }))
+}
+
 enum ImageType {
     FlateLossless,
+    /// Flate with PNG predictors, straight from a PNG
+    FlatePredictor,
     Jpg,
+    /// Only for bilevel images
+    CCITTGroup4,
+    /// Only for bilevel images
+    JBIG2,
 }
 impl ImageType {
     fn pdf_filter(&self) -> Name {
         match self {
-            Self::FlateLossless => Name::flate_decode(),
+            Self::FlateLossless | Self::FlatePredictor => Name::flate_decode(),
             Self::Jpg => Name::dct_decode(),
+            Self::CCITTGroup4 => Name::ccitt_fax_decode(),
+            Self::JBIG2 => Name::jbig2_decode(),
         }
     }
 }
+#[derive(Clone, Eq, PartialEq)]
 enum ColourType {
     Gray,
-    RGB,
+    Rgb,
+    /// Only for JPEGs that are used directly
+    Cmyk,
+    /// Each pixel is an index into the palette, which has the colours of the base one after another
+    Indexed { base: Box<ColourType>, palette: Vec<u8> },
 }
 impl ColourType {
-    fn from_image_colour_type(color_type: ColorType) -> Option<ColourType> {
-        match color_type {
-            ColorType::L8 => Some(Self::Gray),
-            ColorType::Rgb8 => Some(Self::RGB),
+    fn from_jpeg_components(components: u8) -> Option<ColourType> {
+        match components {
+            1 => Some(Self::Gray),
+            3 => Some(Self::Rgb),
+            4 => Some(Self::Cmyk),
             _ => None,
         }
     }
-    fn pdf_colour_space(&self) -> Name {
+    fn components(&self) -> u8 {
         match self {
-            Self::Gray => Name::device_gray(),
-            Self::RGB => Name::device_rgb(),
+            Self::Gray => 1,
+            Self::Rgb => 3,
+            Self::Cmyk => 4,
+            Self::Indexed { .. } => 1,
+        }
+    }
+    /// The colours that indexes point to
+    fn base(&self) -> &ColourType {
+        match self {
+            Self::Indexed { base, .. } => base,
+            _ => self,
+        }
+    }
+    /// The device colour space is used when there isn't an ICC profile
+    fn pdf_colour_space(&self, icc_profile_id: Option<ObjectId>) -> Object {
+        match (self, icc_profile_id) {
+            (Self::Indexed { base, palette }, _) => {
+                let highest_index = (palette.len() / base.components() as usize).saturating_sub(1);
+                vec![
+                    Name::indexed().into(),
+                    base.pdf_colour_space(icc_profile_id),
+                    highest_index.into(),
+                    Object::HexStr(palette.clone()),
+                ].into()
+            },
+            (_, Some(icc_profile_id)) => vec![Object::from(Name::icc_based()), icc_profile_id.into()].into(),
+            (Self::Gray, None) => Name::device_gray().into(),
+            (Self::Rgb, None) => Name::device_rgb().into(),
+            (Self::Cmyk, None) => Name::device_cmyk().into(),
         }
     }
 }
 
+/// The colours of an indexed image, and the index of every pixel
+struct Palette {
+    /// The components of each colour one after another
+    colours: Vec<u8>,
+    colour_count: usize,
+    indexes: Vec<u8>,
+}
+impl Palette {
+    /// The samples of the image with every pixel changed to its palette colour
+    fn samples(&self) -> Vec<u8> {
+        let channels = self.colours.len() / self.colour_count;
+        self.indexes.iter()
+            .flat_map(|&index| {
+                let start = index as usize * channels;
+                self.colours[start..start + channels].iter().copied()
+            })
+            .collect()
+    }
+}
+
+pub fn add_path_to_error(result: PDFResult<PDFImage>, image_path: &Path) -> PDFResult<PDFImage> {
+    match result {
+        Err(PDFError::BadImageColourType(message)) => {
+            Err(PDFError::BadImageColourType(
+                format!("{} path={}", message, image_path.display())
+            ))
+        },
+        other => other,
+    }
+}
+
+/// The image has to be L8 or Rgb8
+fn encode_jpeg(image: &DynamicImage, compression: &CompressionOptions) -> PDFResult<Vec<u8>> {
+    let to_image_error = |error: Box<dyn std::error::Error + Send + Sync>| {
+        ImageError::Encoding(EncodingError::new(ImageFormat::Jpeg.into(), error))
+    };
+    // JPEGs can't be any bigger than this
+    let width = u16::try_from(image.width()).map_err(|error| to_image_error(error.into()))?;
+    let height = u16::try_from(image.height()).map_err(|error| to_image_error(error.into()))?;
+    let colour_type = match image.color() {
+        ColorType::L8 => jpeg_encoder::ColorType::Luma,
+        _ => jpeg_encoder::ColorType::Rgb,
+    };
+
+    // Make a rough estimate for the compressed image size so it's not quite so inefficient
+    let mut image_bytes = Vec::with_capacity((image.width() * image.height()) as usize);
+    let mut encoder = jpeg_encoder::Encoder::new(&mut image_bytes, compression.jpeg_quality());
+    encoder.set_sampling_factor(match compression.chroma_subsampling() {
+        ChromaSubsampling::Full => jpeg_encoder::SamplingFactor::R_4_4_4,
+        ChromaSubsampling::Half => jpeg_encoder::SamplingFactor::R_4_2_2,
+        ChromaSubsampling::Quarter => jpeg_encoder::SamplingFactor::R_4_2_0,
+    });
+    encoder.encode(image.as_bytes(), width, height, colour_type)
+        .map_err(|error| to_image_error(error.into()))?;
+    Ok(image_bytes)
+}
+
+/// Turns an image into colours that a PDF can use, making it grayscale if it doesn't have any colour
+fn make_pdf_colours(image: DynamicImage) -> (DynamicImage, ColourType) {
+    let try_to_convert_to_grayscale = |image, old_colour_type| {
+        // Sample the image first to see if it can be turned to grayscale
+        // We will only grayscale non-colour images
+        //  This should save some space for all of the images that are just black and white
+        if image_can_be_grayscale(&image) {
+            (DynamicImage::ImageLuma8(image.to_luma8()), ColourType::Gray)
+        } else {
+            (image, old_colour_type)
+        }
+    };
+
+    // We need to make sure that the PDF can handle the image
+    //  We can make it not use any weird color channels to accomplish this
+    match image.color() {
+        ColorType::L8 => (image, ColourType::Gray),
+        ColorType::Rgb8 => try_to_convert_to_grayscale(image, ColourType::Rgb),
+
+        ColorType::La8 => (
+            DynamicImage::ImageLuma8(image.to_luma8()),
+            ColourType::Gray
+        ),
+
+        _ => try_to_convert_to_grayscale(
+            DynamicImage::ImageRgb8(image.to_rgb8()),
+            ColourType::Rgb
+        ),
+    }
+}
+
+/// The most common colours go into the palette first, and any colour within the tolerance of one
+/// of them gets its index. Gives back nothing if the palette would be too big.
+/// The image has to be L8 or Rgb8.
+fn find_palette(image: &DynamicImage, tolerance: u8, max_palette_size: usize) -> Option<Palette> {
+    let channels = image.color().channel_count() as usize;
+    let pixels = image.as_bytes().chunks_exact(channels);
+    let mut colour_counts: HashMap<&[u8], u32> = HashMap::new();
+    for pixel in pixels.clone() {
+        *colour_counts.entry(pixel).or_insert(0) += 1;
+        // Every colour needs its own index without a tolerance
+        if tolerance == 0 && colour_counts.len() > max_palette_size {
+            return None;
+        }
+    }
+    let mut colours: Vec<(&[u8], u32)> = colour_counts.into_iter().collect();
+    colours.sort_by(|(colour1, count1), (colour2, count2)| count2.cmp(count1).then(colour1.cmp(colour2)));
+
+    let is_close = |colour1: &[u8], colour2: &[u8]| colour1.iter().zip(colour2)
+        .all(|(&component1, &component2)| component1.abs_diff(component2) <= tolerance);
+    let mut palette: Vec<&[u8]> = Vec::new();
+    for &(colour, _) in colours.iter() {
+        if !palette.iter().any(|&palette_colour| is_close(palette_colour, colour)) {
+            if palette.len() == max_palette_size {
+                return None;
+            }
+            palette.push(colour);
+        }
+    }
+    // Images without any pixels don't have any colours
+    if palette.is_empty() {
+        return None;
+    }
+    // Keep the output the same for the same image
+    palette.sort();
+
+    let distance = |colour1: &[u8], colour2: &[u8]| -> u32 { colour1.iter().zip(colour2)
+        .map(|(&component1, &component2)| (component1.abs_diff(component2) as u32).pow(2))
+        .sum() };
+    // The closest colour overall can still have one component that's too far off, and then the
+    // image is better off lossless
+    let colour_indexes: HashMap<&[u8], u8> = colours.iter()
+        .map(|&(colour, _)| {
+            let closest_index = (0..palette.len())
+                .min_by_key(|&i| distance(palette[i], colour))
+                .unwrap();
+            if is_close(palette[closest_index], colour) { Some((colour, closest_index as u8)) } else { None }
+        })
+        .collect::<Option<_>>()?;
+    Some(Palette {
+        colours: palette.concat(),
+        colour_count: palette.len(),
+        indexes: pixels.map(|pixel| colour_indexes[pixel]).collect(),
+    })
+}
+
+fn check_not_empty(image: &DynamicImage) -> PDFResult<()> {
+    if image.width() == 0 || image.height() == 0 {
+        return Err(PDFError::EmptyImage);
+    }
+    Ok(())
+}
+
 // Sample the image at intervals (instead of looking at every single pixel)
 // This will speed things up and won't sacrifice accuracy (if it's not a super weird image)
 fn image_can_be_grayscale(image: &DynamicImage) -> bool {
@@ -171,3 +748,48 @@ fn image_can_be_grayscale(image: &DynamicImage) -> bool {
     }
     true
 }
+
+/// Every pixel has to be exactly black or white (not just a sample like for grayscale), so nothing
+/// changes when it's made bilevel
+fn image_is_bilevel(image: &DynamicImage) -> bool {
+    image.as_bytes().iter().all(|&pixel| pixel == 0 || pixel == u8::MAX)
+}
+
+/// 8 pixels to a byte with every row starting on a new byte. 1 is white and 0 is black.
+fn pack_bilevel_rows(image: &GrayImage, threshold: u8) -> Vec<u8> {
+    let row_length = (image.width() as usize).div_ceil(8);
+    let mut packed_bytes = vec![0; row_length * image.height() as usize];
+    for (y, row) in image.rows().enumerate() {
+        let packed_row = &mut packed_bytes[y * row_length..(y + 1) * row_length];
+        for (x, pixel) in row.enumerate() {
+            if pixel[0] >= threshold {
+                packed_row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    packed_bytes
+}
+
+/// Packs the indexes with the most significant bits first, with every row starting on a new byte
+fn pack_indexes(indexes: &[u8], width: u32, bits_per_index: u8) -> Vec<u8> {
+    let indexes_per_byte = 8 / bits_per_index as usize;
+    indexes.chunks(width as usize)
+        .flat_map(|row| row.chunks(indexes_per_byte).map(|byte_indexes| {
+            byte_indexes.iter().enumerate().fold(0, |byte, (i, &index)| {
+                byte | index << (8 - bits_per_index as usize * (i + 1))
+            })
+        }))
+        .collect()
+}
+
+/// Gives back nothing if the image is too wide for the encoder
+fn encode_ccitt_group_4(image: &GrayImage, threshold: u8) -> Option<Vec<u8>> {
+    let width = u16::try_from(image.width()).ok()?;
+    let mut encoder = Encoder::new(VecWriter::new());
+    for row in image.rows() {
+        let pixels = row.map(|pixel| if pixel[0] >= threshold { Color::White } else { Color::Black });
+        // The VecWriter can't fail
+        encoder.encode_line(pixels, width).ok()?;
+    }
+    Some(encoder.finish().ok()?.finish())
+}
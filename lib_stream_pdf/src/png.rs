@@ -0,0 +1,109 @@
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PngColourType {
+    Gray,
+    Rgb,
+    Palette,
+    GrayAlpha,
+    Rgba,
+}
+impl PngColourType {
+    fn from_byte(byte: u8) -> Option<PngColourType> {
+        match byte {
+            0 => Some(Self::Gray),
+            2 => Some(Self::Rgb),
+            3 => Some(Self::Palette),
+            4 => Some(Self::GrayAlpha),
+            6 => Some(Self::Rgba),
+            _ => None,
+        }
+    }
+}
+
+/// The parts of a PNG's IHDR chunk that we need
+pub struct PngHeader {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    colour_type: PngColourType,
+    interlaced: bool,
+}
+impl PngHeader {
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+    pub fn bit_depth(&self) -> u8 { self.bit_depth }
+    pub fn colour_type(&self) -> PngColourType { self.colour_type }
+    pub fn interlaced(&self) -> bool { self.interlaced }
+}
+
+/// The IHDR always has to be the first chunk. Gives back nothing if it's not a PNG.
+pub fn read_header(png_bytes: &[u8]) -> Option<PngHeader> {
+    let (chunk_type, data) = Chunks::new(png_bytes)?.next()?;
+    if chunk_type != b"IHDR" || data.len() < 13 {
+        return None;
+    }
+    Some(PngHeader {
+        width: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+        height: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        bit_depth: data[8],
+        colour_type: PngColourType::from_byte(data[9])?,
+        interlaced: data[12] != 0,
+    })
+}
+
+/// Puts all of the IDAT chunks together, which is a zlib stream of the filtered rows. Gives back
+/// nothing if there's a tRNS chunk, since that transparency would be lost.
+pub fn read_image_data(png_bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut image_data = Vec::new();
+    for (chunk_type, data) in Chunks::new(png_bytes)? {
+        match chunk_type {
+            b"IDAT" => image_data.extend_from_slice(data),
+            b"tRNS" => return None,
+            b"IEND" => break,
+            _ => (),
+        }
+    }
+    if image_data.is_empty() {
+        None
+    } else {
+        Some(image_data)
+    }
+}
+
+/// The iCCP chunk has the profile's name, the compression method and the zlib compressed profile
+pub fn read_icc_profile(png_bytes: &[u8]) -> Option<Vec<u8>> {
+    let (_, data) = Chunks::new(png_bytes)?
+        .take_while(|&(chunk_type, _)| chunk_type != b"IDAT")
+        .find(|&(chunk_type, _)| chunk_type == b"iCCP")?;
+    let name_end = data.iter().position(|&byte| byte == 0)?;
+    crate::utils::flate_decompress(data.get(name_end + 2..)?).ok()
+}
+
+/// Goes through the (type, data) of each chunk
+struct Chunks<'a> {
+    png_bytes: &'a [u8],
+    position: usize,
+}
+impl <'a> Chunks<'a> {
+    fn new(png_bytes: &'a [u8]) -> Option<Chunks<'a>> {
+        if png_bytes.starts_with(&SIGNATURE) {
+            Some(Chunks { png_bytes, position: SIGNATURE.len() })
+        } else {
+            None
+        }
+    }
+}
+impl <'a> Iterator for Chunks<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.png_bytes;
+        let length = bytes.get(self.position..self.position + 4)?;
+        let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+        let chunk_type = bytes.get(self.position + 4..self.position + 8)?;
+        let data = bytes.get(self.position + 8..self.position + 8 + length)?;
+        // Skip over the CRC at the end
+        self.position += 12 + length;
+        Some((chunk_type, data))
+    }
+}
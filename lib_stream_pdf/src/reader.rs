@@ -0,0 +1,569 @@
+mod parser;
+
+use std::{
+    cell::{RefCell},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::{TryFrom},
+    fs,
+    ops::{Range},
+    path::{Path},
+    rc::{Rc},
+};
+use crate::{
+    PDFError, PDFResult,
+    objects::{Dictionary, Name, Object, ObjectId, Stream},
+};
+use self::parser::{Parser};
+
+/// How far from the end of the file we'll look for `startxref`
+const STARTXREF_SEARCH_LENGTH: usize = 1024;
+/// Page attributes that are inherited from the parent `/Pages` nodes
+const INHERITABLE_PAGE_ATTRIBUTES: &[&str] = &["Resources", "MediaBox", "CropBox", "Rotate"];
+/// The widest field that an xref stream entry can have (it has to fit in a `u64`)
+const MAX_XREF_FIELD_WIDTH: usize = 8;
+/// Predictor rows can't have more colours than this in each pixel
+const MAX_PREDICTOR_COLOURS: usize = 32;
+
+/// Reads the objects out of an existing PDF.
+pub struct DocumentReader {
+    bytes: Vec<u8>,
+    version: String,
+    xref: BTreeMap<u32, XrefEntry>,
+    trailer: Dictionary,
+    /// Where each cross-reference section starts, from the newest to the oldest
+    xref_offsets: Vec<u64>,
+    /// The decoded objects of any object streams that we've had to look into
+    object_streams: RefCell< HashMap< u32, Rc< Vec<Object> > > >,
+    /// The objects that are being loaded right now
+    loading_ids: RefCell< BTreeSet<ObjectId> >,
+}
+impl DocumentReader {
+    pub fn open(path: impl AsRef<Path>) -> PDFResult<DocumentReader> {
+        DocumentReader::from_bytes(fs::read(path)?)
+    }
+    pub fn from_bytes(mut bytes: Vec<u8>) -> PDFResult<DocumentReader> {
+        // There can be junk before the header. Every byte offset is from the header though.
+        let header_start = find_forwards(&bytes[..bytes.len().min(1024)], b"%PDF-")
+            .ok_or_else(|| PDFError::MalformedPDF("Missing the %PDF- header".to_string()))?;
+        bytes.drain(..header_start);
+        let version = bytes[5..].iter()
+            .take_while(|&&byte| byte.is_ascii_digit() || byte == b'.')
+            .map(|&byte| byte as char)
+            .collect();
+
+        let mut reader = DocumentReader {
+            bytes,
+            version,
+            xref: BTreeMap::new(),
+            trailer: Dictionary::new(),
+            xref_offsets: Vec::new(),
+            object_streams: RefCell::new(HashMap::new()),
+            loading_ids: RefCell::new(BTreeSet::new()),
+        };
+        let xref_start = reader.find_startxref()?;
+        reader.read_xref_sections(xref_start)?;
+        Ok(reader)
+    }
+
+    /// The version from the header (like `1.7`)
+    pub fn version(&self) -> &str { &self.version }
+    /// The newest trailer, which has the `/Root` and `/Info` of the document
+    pub fn trailer(&self) -> &Dictionary { &self.trailer }
+    /// The `startxref` offset of the newest cross-reference section
+    pub fn last_xref_offset(&self) -> u64 { self.xref_offsets[0] }
+    /// Whether the newest cross-reference section is a stream instead of a table
+    pub fn uses_xref_streams(&self) -> bool {
+        self.trailer.get(&Name::type_name()).and_then(Object::as_name) == Some(&Name::xref())
+    }
+    /// The size of the PDF in bytes, not counting anything before the header
+    pub fn file_size(&self) -> u64 { self.bytes.len() as u64 }
+    /// One past the largest object number in use
+    pub fn xref_size(&self) -> u32 {
+        let trailer_size = self.trailer.get(&Name::size())
+            .and_then(Object::as_int)
+            .unwrap_or(0) as u32;
+        let largest_entry = self.xref.keys().next_back().map_or(0, |&object_num| object_num + 1);
+        trailer_size.max(largest_entry)
+    }
+
+    /// Every object that's in use by the document
+    pub fn object_ids(&self) -> Vec<ObjectId> {
+        self.xref.iter().filter_map(|(&object_num, entry)| match entry {
+            XrefEntry::Free => None,
+            XrefEntry::Offset { gen_num, .. } => Some(ObjectId::new(object_num, *gen_num)),
+            XrefEntry::InObjectStream { .. } => Some(ObjectId::new(object_num, 0)),
+        }).collect()
+    }
+
+    /// A missing object is the same as `null`
+    pub fn get_object(&self, id: ObjectId) -> PDFResult<Object> {
+        // A broken xref can send a stream's `/Length` or object stream back into itself
+        if !self.loading_ids.borrow_mut().insert(id) {
+            return Err(PDFError::MalformedPDF(format!("Object {:?} needs itself to be loaded", id)));
+        }
+        let object = self.load_object(id);
+        self.loading_ids.borrow_mut().remove(&id);
+        object
+    }
+    /// Follows the reference (if it is one) to the actual object
+    pub fn resolve(&self, object: &Object) -> PDFResult<Object> {
+        let mut object = object.clone();
+        // Guard against references that point at each other
+        let mut seen_ids = BTreeSet::new();
+        while let Object::Ref(id) = object {
+            if !seen_ids.insert(id) {
+                return Err(PDFError::MalformedPDF(format!("Reference loop at {:?}", id)));
+            }
+            object = self.get_object(id)?;
+        }
+        Ok(object)
+    }
+    pub fn resolve_dictionary(&self, object: &Object) -> PDFResult<Dictionary> {
+        match self.resolve(object)? {
+            Object::Dictionary(dictionary) => Ok(dictionary),
+            Object::Stream(stream) => Ok(stream.dictionary().clone()),
+            other => Err(PDFError::MalformedPDF(format!("Expected a dictionary, got {:?}", other))),
+        }
+    }
+
+    pub fn catalog(&self) -> PDFResult<Dictionary> {
+        let root = self.trailer.get(&Name::root())
+            .ok_or_else(|| PDFError::MalformedPDF("The trailer is missing /Root".to_string()))?;
+        self.resolve_dictionary(root)
+    }
+    pub fn document_info(&self) -> PDFResult< Option<Dictionary> > {
+        match self.trailer.get(&Name::info()) {
+            Some(info) => Ok(Some(self.resolve_dictionary(info)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Walks the page tree in order. Each page dictionary gets the attributes it inherits from
+    /// its parents filled in.
+    pub fn pages(&self) -> PDFResult< Vec<(ObjectId, Dictionary)> > {
+        let catalog = self.catalog()?;
+        let pages_root = catalog.get(&Name::pages())
+            .ok_or_else(|| PDFError::MalformedPDF("The catalog is missing /Pages".to_string()))?;
+        let mut pages = Vec::new();
+        let mut seen_ids = BTreeSet::new();
+        self.walk_page_tree(pages_root, &Dictionary::new(), &mut seen_ids, &mut pages)?;
+        Ok(pages)
+    }
+
+    /// Undoes the stream's filters. Only `/FlateDecode` (with or without a predictor) is supported.
+    pub fn decode_stream(&self, stream: &Stream) -> PDFResult< Vec<u8> > {
+        let filters = match stream.dictionary().get(&Name::filter()) {
+            Some(filter) => self.resolve(filter)?,
+            None => return Ok(stream.contents().to_vec()),
+        };
+        let filters = match filters {
+            Object::Array(filters) => filters,
+            Object::Null => Vec::new(),
+            filter => vec![filter],
+        };
+        let decode_parameters = match stream.dictionary().get(&Name::decode_parms()) {
+            Some(parameters) => match self.resolve(parameters)? {
+                Object::Array(parameters) => parameters,
+                parameters => vec![parameters],
+            },
+            None => Vec::new(),
+        };
+
+        let mut contents = stream.contents().to_vec();
+        for (i, filter) in filters.iter().enumerate() {
+            let parameters = match decode_parameters.get(i) {
+                Some(parameters) => self.resolve(parameters)?.as_dictionary().cloned(),
+                None => None,
+            };
+            match self.resolve(filter)?.as_name().map(Name::as_str) {
+                Some("FlateDecode") => {
+                    contents = crate::utils::flate_decompress(&contents)?;
+                    if let Some(parameters) = parameters {
+                        contents = undo_predictor(contents, &parameters)?;
+                    }
+                },
+                other => return Err(PDFError::UnsupportedFilter(format!("{:?}", other))),
+            }
+        }
+        Ok(contents)
+    }
+}
+impl DocumentReader {
+    fn load_object(&self, id: ObjectId) -> PDFResult<Object> {
+        match self.xref.get(&id.object_num()) {
+            Some(&XrefEntry::Offset { byte_offset, gen_num }) if gen_num == id.gen_num() => {
+                let (found_id, object) = self.read_object_at(byte_offset)?;
+                if found_id != id {
+                    return Err(PDFError::MalformedPDF(
+                        format!("Expected object {:?} at {} but found {:?}", id, byte_offset, found_id)
+                    ));
+                }
+                Ok(object)
+            },
+            Some(&XrefEntry::InObjectStream { stream_num, index }) if id.gen_num() == 0 => {
+                let objects = self.object_stream(stream_num)?;
+                Ok(objects.get(index).cloned().unwrap_or(Object::Null))
+            },
+            _ => Ok(Object::Null),
+        }
+    }
+    fn find_startxref(&self) -> PDFResult<u64> {
+        let search_start = self.bytes.len().saturating_sub(STARTXREF_SEARCH_LENGTH);
+        let startxref_position = find_backwards(&self.bytes[search_start..], b"startxref")
+            .map(|position| search_start + position)
+            .ok_or_else(|| PDFError::MalformedPDF("Missing startxref".to_string()))?;
+        let mut parser = Parser::new(&self.bytes, startxref_position);
+        parser.expect_keyword(b"startxref")?;
+        Ok(parser.parse_int()? as u64)
+    }
+
+    /// Goes through every cross-reference section by following the `/Prev` offsets.
+    /// Newer sections win over older ones.
+    fn read_xref_sections(&mut self, newest_start: u64) -> PDFResult<()> {
+        let mut next_start = Some(newest_start);
+        while let Some(xref_start) = next_start.take() {
+            if self.xref_offsets.contains(&xref_start) {
+                return Err(PDFError::MalformedPDF("The xref sections loop".to_string()));
+            }
+            self.xref_offsets.push(xref_start);
+
+            let (entries, trailer) = self.read_xref_section(xref_start)?;
+            // A hybrid file has an xref stream with extra entries for the table
+            if let Some(xref_stream_start) = trailer.get(&Name::xref_stm()).and_then(Object::as_int) {
+                let (stream_entries, _) = self.read_xref_section(xref_stream_start as u64)?;
+                self.add_xref_entries(stream_entries);
+            }
+            self.add_xref_entries(entries);
+
+            next_start = trailer.get(&Name::prev()).and_then(Object::as_int).map(|prev| prev as u64);
+            if self.trailer.is_empty() {
+                self.trailer = trailer;
+            }
+        }
+        Ok(())
+    }
+    fn add_xref_entries(&mut self, entries: Vec<(u32, XrefEntry)>) {
+        for (object_num, entry) in entries {
+            self.xref.entry(object_num).or_insert(entry);
+        }
+    }
+
+    fn read_xref_section(&self, xref_start: u64) -> PDFResult<(Vec<(u32, XrefEntry)>, Dictionary)> {
+        let mut parser = Parser::new(&self.bytes, xref_start as usize);
+        if parser.take_keyword(b"xref") {
+            self.read_xref_table(&mut parser)
+        } else {
+            let (_, xref_stream) = self.read_object_at(xref_start)?;
+            match xref_stream {
+                Object::Stream(xref_stream) => self.read_xref_stream(&xref_stream),
+                _ => Err(PDFError::MalformedPDF(
+                    format!("Expected an xref table or stream at {}", xref_start)
+                )),
+            }
+        }
+    }
+    fn read_xref_table(&self, parser: &mut Parser) -> PDFResult<(Vec<(u32, XrefEntry)>, Dictionary)> {
+        let mut entries = Vec::new();
+        while !parser.take_keyword(b"trailer") {
+            let start_object_num = parser.parse_int()?;
+            let object_count = parser.parse_int()?;
+            for object_num in object_num_range(start_object_num, object_count)? {
+                let byte_offset = parser.parse_int()?;
+                let gen_num = parser.parse_int()?;
+                let entry = if parser.take_keyword(b"n") {
+                    match (u64::try_from(byte_offset), u16::try_from(gen_num)) {
+                        (Ok(byte_offset), Ok(gen_num)) => XrefEntry::Offset { byte_offset, gen_num },
+                        _ => return Err(PDFError::MalformedPDF(
+                            format!("Bad xref entry for object {}", object_num)
+                        )),
+                    }
+                } else {
+                    parser.expect_keyword(b"f")?;
+                    XrefEntry::Free
+                };
+                entries.push( (object_num, entry) );
+            }
+        }
+        let trailer = match parser.parse_object()? {
+            Object::Dictionary(trailer) => trailer,
+            _ => return Err(PDFError::MalformedPDF("The trailer isn't a dictionary".to_string())),
+        };
+        Ok( (entries, trailer) )
+    }
+    fn read_xref_stream(&self, xref_stream: &Stream) -> PDFResult<(Vec<(u32, XrefEntry)>, Dictionary)> {
+        let dictionary = xref_stream.dictionary();
+        let field_widths: Vec<usize> = dictionary.get(&Name::w())
+            .and_then(Object::as_array)
+            .ok_or_else(|| PDFError::MalformedPDF("The xref stream is missing /W".to_string()))?
+            .iter()
+            .map(|width| width.as_int().unwrap_or(0))
+            .map(|width| usize::try_from(width).ok().filter(|&width| width <= MAX_XREF_FIELD_WIDTH)
+                .ok_or_else(|| PDFError::MalformedPDF(format!("Bad xref stream field width {}", width))))
+            .collect::<PDFResult<_>>()?;
+        if field_widths.len() != 3 {
+            return Err(PDFError::MalformedPDF("The xref stream /W needs 3 widths".to_string()));
+        }
+        let index: Vec<i64> = match dictionary.get(&Name::index()).and_then(Object::as_array) {
+            Some(index) => index.iter().filter_map(Object::as_int).collect(),
+            None => vec![0, dictionary.get(&Name::size()).and_then(Object::as_int).unwrap_or(0)],
+        };
+
+        let entry_data = self.decode_stream(xref_stream)?;
+        let entry_width: usize = field_widths.iter().sum();
+        let mut entry_chunks = entry_data.chunks_exact(entry_width.max(1));
+        let mut entries = Vec::new();
+        for subsection in index.chunks_exact(2) {
+            for object_num in object_num_range(subsection[0], subsection[1])? {
+                let entry_bytes = entry_chunks.next()
+                    .ok_or_else(|| PDFError::MalformedPDF("The xref stream is too short".to_string()))?;
+                let (type_bytes, field_bytes) = entry_bytes.split_at(field_widths[0]);
+                let (field_2_bytes, field_3_bytes) = field_bytes.split_at(field_widths[1]);
+                // The type defaults to 1 when it isn't in the stream
+                let object_type = if type_bytes.is_empty() { 1 } else { read_be_int(type_bytes) };
+                let field_2 = read_be_int(field_2_bytes);
+                let field_3 = read_be_int(field_3_bytes);
+                let entry = match object_type {
+                    0 => XrefEntry::Free,
+                    1 => XrefEntry::Offset { byte_offset: field_2, gen_num: field_3 as u16 },
+                    2 => XrefEntry::InObjectStream {
+                        stream_num: field_2 as u32,
+                        index: field_3 as usize,
+                    },
+                    // Unknown types are to be treated as null objects
+                    _ => continue,
+                };
+                entries.push( (object_num, entry) );
+            }
+        }
+        Ok( (entries, dictionary.clone()) )
+    }
+
+    fn read_object_at(&self, byte_offset: u64) -> PDFResult<(ObjectId, Object)> {
+        let mut parser = Parser::new(&self.bytes, byte_offset as usize);
+        let id = parser.parse_object_header()?;
+        let object = parser.parse_object()?;
+        let object = match object {
+            Object::Dictionary(dictionary) if parser.take_keyword(b"stream") => {
+                parser.skip_stream_eol();
+                let contents_start = parser.position();
+                let contents_end = self.find_stream_end(&dictionary, contents_start)?;
+                let contents = self.bytes[contents_start..contents_end].to_vec();
+                Object::Stream(Stream::new(dictionary, contents))
+            },
+            object => object,
+        };
+        Ok( (id, object) )
+    }
+    /// Uses the `/Length` if it looks right, otherwise looks for `endstream`
+    fn find_stream_end(&self, dictionary: &Dictionary, contents_start: usize) -> PDFResult<usize> {
+        let length = match dictionary.get(&Name::length()) {
+            // The length can be an indirect object, but it can't be in a stream itself
+            Some(Object::Ref(length_id)) => self.get_object(*length_id)?.as_int(),
+            Some(length) => length.as_int(),
+            None => None,
+        };
+        // A negative or huge length can't be right
+        let contents_end = length
+            .and_then(|length| usize::try_from(length).ok())
+            .and_then(|length| contents_start.checked_add(length));
+        if let Some(contents_end) = contents_end {
+            if contents_end <= self.bytes.len() {
+                let mut parser = Parser::new(&self.bytes, contents_end);
+                if parser.take_keyword(b"endstream") {
+                    return Ok(contents_end);
+                }
+            }
+        }
+        let endstream_position = find_forwards(&self.bytes[contents_start..], b"endstream")
+            .map(|position| contents_start + position)
+            .ok_or_else(|| PDFError::MalformedPDF("Missing endstream".to_string()))?;
+        // The end of line before endstream isn't part of the contents
+        let mut contents_end = endstream_position;
+        if self.bytes[..contents_end].ends_with(b"\r\n") {
+            contents_end -= 2;
+        } else if self.bytes[..contents_end].ends_with(b"\n") || self.bytes[..contents_end].ends_with(b"\r") {
+            contents_end -= 1;
+        }
+        Ok(contents_end.max(contents_start))
+    }
+
+    fn object_stream(&self, stream_num: u32) -> PDFResult< Rc< Vec<Object> > > {
+        if let Some(objects) = self.object_streams.borrow().get(&stream_num) {
+            return Ok(objects.clone());
+        }
+
+        let stream = match self.get_object(ObjectId::new(stream_num, 0))? {
+            Object::Stream(stream) => stream,
+            _ => return Err(PDFError::MalformedPDF(
+                format!("Object {} isn't an object stream", stream_num)
+            )),
+        };
+        let object_count = stream.dictionary().get(&Name::n()).and_then(Object::as_int).unwrap_or(0);
+        let first_offset = stream.dictionary().get(&Name::first()).and_then(Object::as_int).unwrap_or(0);
+        let contents = self.decode_stream(&stream)?;
+
+        let mut parser = Parser::new(&contents, 0);
+        let mut object_offsets = Vec::new();
+        for _ in 0..object_count {
+            let _object_num = parser.parse_int()?;
+            object_offsets.push(parser.parse_int()?);
+        }
+        let mut objects = Vec::new();
+        for object_offset in object_offsets {
+            let position = first_offset.checked_add(object_offset)
+                .and_then(|position| usize::try_from(position).ok())
+                .ok_or_else(|| PDFError::MalformedPDF(
+                    format!("Bad object offset {} in object stream {}", object_offset, stream_num)
+                ))?;
+            parser.set_position(position);
+            objects.push(parser.parse_object()?);
+        }
+
+        let objects = Rc::new(objects);
+        self.object_streams.borrow_mut().insert(stream_num, objects.clone());
+        Ok(objects)
+    }
+
+    fn walk_page_tree(&self, node: &Object, inherited: &Dictionary,
+    seen_ids: &mut BTreeSet<ObjectId>, pages: &mut Vec<(ObjectId, Dictionary)>) -> PDFResult<()> {
+        let node_id = node.as_ref_id()
+            .ok_or_else(|| PDFError::MalformedPDF("Page tree nodes need to be references".to_string()))?;
+        if !seen_ids.insert(node_id) {
+            return Err(PDFError::MalformedPDF(format!("The page tree loops at {:?}", node_id)));
+        }
+        let mut node_dictionary = self.resolve_dictionary(node)?;
+
+        let is_page = node_dictionary.get(&Name::type_name()).and_then(Object::as_name)
+            .map_or(node_dictionary.get(&Name::kids()).is_none(), |type_name| type_name == &Name::page());
+        if is_page {
+            for (name, value) in inherited.iter() {
+                if node_dictionary.get(name).is_none() {
+                    node_dictionary.insert(name.clone(), value.clone());
+                }
+            }
+            pages.push( (node_id, node_dictionary) );
+            return Ok(());
+        }
+
+        let mut child_inherited = inherited.clone();
+        for (name, value) in inheritable_attributes(&node_dictionary).iter() {
+            child_inherited.insert(name.clone(), value.clone());
+        }
+        let kids = match node_dictionary.get(&Name::kids()) {
+            Some(kids) => self.resolve(kids)?,
+            None => Object::Null,
+        };
+        for kid in kids.as_array().unwrap_or(&[]) {
+            self.walk_page_tree(kid, &child_inherited, seen_ids, pages)?;
+        }
+        Ok(())
+    }
+}
+
+enum XrefEntry {
+    Free,
+    Offset { byte_offset: u64, gen_num: u16 },
+    InObjectStream { stream_num: u32, index: usize },
+}
+
+/// The attributes of a `/Pages` node that the pages under it inherit
+pub fn inheritable_attributes(node_dictionary: &Dictionary) -> Dictionary {
+    let mut attributes = Dictionary::new();
+    for &attribute in INHERITABLE_PAGE_ATTRIBUTES {
+        if let Some(value) = node_dictionary.get(&Name::new(attribute)) {
+            attributes.insert(Name::new(attribute), value.clone());
+        }
+    }
+    attributes
+}
+
+/// Undoes the PNG (10+) or TIFF (2) predictor that was applied before compressing
+fn undo_predictor(contents: Vec<u8>, parameters: &Dictionary) -> PDFResult< Vec<u8> > {
+    let get_int = |name: &str, default: i64| {
+        parameters.get(&Name::new(name)).and_then(Object::as_int).unwrap_or(default)
+    };
+    let predictor = get_int("Predictor", 1);
+    if predictor == 1 {
+        return Ok(contents);
+    }
+    let colours = usize::try_from(get_int("Colors", 1)).ok()
+        .filter(|colours| (1..=MAX_PREDICTOR_COLOURS).contains(colours));
+    let bits_per_component = usize::try_from(get_int("BitsPerComponent", 8)).ok()
+        .filter(|bits_per_component| [1, 2, 4, 8, 16].contains(bits_per_component));
+    let columns = usize::try_from(get_int("Columns", 1)).ok().filter(|&columns| columns > 0);
+    let (colours, bits_per_component, columns) = match (colours, bits_per_component, columns) {
+        (Some(colours), Some(bits_per_component), Some(columns)) => (colours, bits_per_component, columns),
+        _ => return Err(PDFError::MalformedPDF(format!("Bad predictor parameters {:?}", parameters))),
+    };
+    let bytes_per_pixel = (colours * bits_per_component).div_ceil(8);
+    let row_length = (colours * bits_per_component).checked_mul(columns)
+        .ok_or_else(|| PDFError::MalformedPDF(format!("Predictor rows of {} columns are too long", columns)))?
+        .div_ceil(8);
+
+    match predictor {
+        2 if bits_per_component == 8 => {
+            let mut contents = contents;
+            for row in contents.chunks_mut(row_length.max(1)) {
+                for i in bytes_per_pixel..row.len() {
+                    row[i] = row[i].wrapping_add(row[i - bytes_per_pixel]);
+                }
+            }
+            Ok(contents)
+        },
+        10..=15 => {
+            let mut decoded = Vec::with_capacity(contents.len());
+            // Rows can't be longer than the contents, however many columns they say they have
+            let mut previous_row = vec![0u8; row_length.min(contents.len())];
+            for encoded_row in contents.chunks(row_length + 1) {
+                let (filter_type, encoded_row) = encoded_row.split_first()
+                    .ok_or_else(|| PDFError::MalformedPDF("Empty predictor row".to_string()))?;
+                let mut row = encoded_row.to_vec();
+                for i in 0..row.len() {
+                    let left = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+                    let up = previous_row[i];
+                    let up_left = if i >= bytes_per_pixel { previous_row[i - bytes_per_pixel] } else { 0 };
+                    let prediction = match filter_type {
+                        0 => 0,
+                        1 => left,
+                        2 => up,
+                        3 => ((left as u16 + up as u16) / 2) as u8,
+                        4 => crate::utils::paeth_predictor(left, up, up_left),
+                        _ => return Err(PDFError::MalformedPDF(
+                            format!("Unknown PNG filter type {}", filter_type)
+                        )),
+                    };
+                    row[i] = row[i].wrapping_add(prediction);
+                }
+                decoded.extend_from_slice(&row);
+                previous_row[..row.len()].copy_from_slice(&row);
+            }
+            Ok(decoded)
+        },
+        _ => Err(PDFError::UnsupportedFilter(format!("Predictor {}", predictor))),
+    }
+}
+
+/// The object numbers of an xref subsection
+fn object_num_range(start_object_num: i64, object_count: i64) -> PDFResult< Range<u32> > {
+    let start = u32::try_from(start_object_num).ok();
+    let end = start_object_num.checked_add(object_count)
+        .filter(|_| object_count >= 0)
+        .and_then(|end| u32::try_from(end).ok());
+    match (start, end) {
+        (Some(start), Some(end)) => Ok(start..end),
+        _ => Err(PDFError::MalformedPDF(
+            format!("Bad xref subsection of {} objects from {}", object_count, start_object_num)
+        )),
+    }
+}
+
+fn read_be_int(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
+
+fn find_forwards(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+fn find_backwards(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|window| window == needle)
+}
@@ -0,0 +1,358 @@
+use crate::{
+    PDFError, PDFResult,
+    objects::{Dictionary, Name, Object, ObjectId},
+};
+
+/// How deep arrays and dictionaries can be nested, so that a malformed file can't overflow the stack
+const MAX_NESTING_DEPTH: usize = 256;
+
+/// Splits up the bytes of a PDF into objects. Streams are handled by the reader since it needs to
+/// look up the stream lengths.
+pub struct Parser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl <'a> Parser<'a> {
+    pub fn new(bytes: &'a [u8], position: usize) -> Parser<'a> {
+        Parser { bytes, position }
+    }
+
+    pub fn position(&self) -> usize { self.position }
+    pub fn set_position(&mut self, position: usize) { self.position = position; }
+
+    /// Skips over any whitespace and comments
+    pub fn skip_whitespace(&mut self) {
+        while let Some(&byte) = self.bytes.get(self.position) {
+            if is_whitespace(byte) {
+                self.position += 1;
+            } else if byte == b'%' {
+                while let Some(&byte) = self.bytes.get(self.position) {
+                    if byte == b'\r' || byte == b'\n' {
+                        break;
+                    }
+                    self.position += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Moves past the keyword if it's next, without moving otherwise.
+    pub fn take_keyword(&mut self, keyword: &[u8]) -> bool {
+        let start = self.position;
+        match self.next_token() {
+            Ok(Token::Keyword(found)) if found == keyword => true,
+            _ => {
+                self.position = start;
+                false
+            },
+        }
+    }
+    pub fn expect_keyword(&mut self, keyword: &[u8]) -> PDFResult<()> {
+        if self.take_keyword(keyword) {
+            Ok(())
+        } else {
+            Err(self.error(format!("Expected {}", String::from_utf8_lossy(keyword))))
+        }
+    }
+
+    pub fn parse_int(&mut self) -> PDFResult<i64> {
+        match self.next_token()? {
+            Token::Int(int) => Ok(int),
+            _ => Err(self.error("Expected an integer")),
+        }
+    }
+
+    /// Parses the `10 0 obj` that starts an indirect object
+    pub fn parse_object_header(&mut self) -> PDFResult<ObjectId> {
+        let object_num = self.parse_int()?;
+        let gen_num = self.parse_int()?;
+        self.expect_keyword(b"obj")?;
+        if object_num < 0 || gen_num < 0 || gen_num > u16::MAX as i64 {
+            return Err(self.error("Bad object number"));
+        }
+        Ok(ObjectId::new(object_num as u32, gen_num as u16))
+    }
+
+    /// Moves past the end of line that has to come after the `stream` keyword
+    pub fn skip_stream_eol(&mut self) {
+        if self.bytes[self.position..].starts_with(b"\r\n") {
+            self.position += 2;
+        } else if self.bytes[self.position..].starts_with(b"\n") ||
+            self.bytes[self.position..].starts_with(b"\r") {
+            self.position += 1;
+        }
+    }
+
+    pub fn parse_object(&mut self) -> PDFResult<Object> {
+        let token = self.next_token()?;
+        self.parse_object_from(token, 0)
+    }
+}
+impl <'a> Parser<'a> {
+    /// The depth is how many arrays and dictionaries the object is inside of
+    fn parse_object_from(&mut self, token: Token, depth: usize) -> PDFResult<Object> {
+        if depth > MAX_NESTING_DEPTH {
+            return Err(self.error("Objects are nested too deeply"));
+        }
+        let object = match token {
+            Token::Int(int) => {
+                // This could be the start of a reference (`10 0 R`)
+                let after_int = self.position;
+                match (self.next_token(), self.next_token()) {
+                    (Ok(Token::Int(gen_num)), Ok(Token::Keyword(keyword)))
+                    if keyword == b"R" && int >= 0 && (0..=u16::MAX as i64).contains(&gen_num) => {
+                        Object::Ref(ObjectId::new(int as u32, gen_num as u16))
+                    },
+                    _ => {
+                        self.position = after_int;
+                        Object::Int(int)
+                    },
+                }
+            },
+            Token::Real(real) => Object::Real(real),
+            Token::Name(name) => Object::Name(name),
+            Token::Str(string_bytes) => Object::Str(decode_text_string(&string_bytes)),
+            // Hex strings are usually binary data (like a palette), so they're kept as bytes
+            Token::HexStr(bytes) => Object::HexStr(bytes),
+            Token::ArrayStart => {
+                let mut array = Vec::new();
+                loop {
+                    match self.next_token()? {
+                        Token::ArrayEnd => break,
+                        token => array.push(self.parse_object_from(token, depth + 1)?),
+                    }
+                }
+                Object::Array(array)
+            },
+            Token::DictionaryStart => {
+                let mut dictionary = Dictionary::new();
+                loop {
+                    match self.next_token()? {
+                        Token::DictionaryEnd => break,
+                        Token::Name(name) => {
+                            let token = self.next_token()?;
+                            let value = self.parse_object_from(token, depth + 1)?;
+                            dictionary.insert(name, value);
+                        },
+                        _ => return Err(self.error("Expected a name for the dictionary key")),
+                    }
+                }
+                Object::Dictionary(dictionary)
+            },
+            Token::Keyword(keyword) => match keyword.as_slice() {
+                b"true" => Object::Bool(true),
+                b"false" => Object::Bool(false),
+                b"null" => Object::Null,
+                _ => return Err(self.error(
+                    format!("Unexpected keyword {}", String::from_utf8_lossy(&keyword))
+                )),
+            },
+            Token::ArrayEnd | Token::DictionaryEnd => {
+                return Err(self.error("Unexpected end of an array or dictionary"));
+            },
+        };
+        Ok(object)
+    }
+
+    fn next_token(&mut self) -> PDFResult<Token> {
+        self.skip_whitespace();
+        let start = self.position;
+        let byte = *self.bytes.get(start).ok_or_else(|| self.error("Unexpected end of file"))?;
+        self.position += 1;
+        let token = match byte {
+            b'[' => Token::ArrayStart,
+            b']' => Token::ArrayEnd,
+            b'<' if self.bytes.get(self.position) == Some(&b'<') => {
+                self.position += 1;
+                Token::DictionaryStart
+            },
+            b'>' if self.bytes.get(self.position) == Some(&b'>') => {
+                self.position += 1;
+                Token::DictionaryEnd
+            },
+            b'<' => Token::HexStr(self.read_hex_string()?),
+            b'(' => Token::Str(self.read_literal_string()?),
+            b'/' => {
+                let name_bytes = self.read_regular_bytes();
+                Token::Name(Name::new(String::from_utf8_lossy(&decode_name(name_bytes))))
+            },
+            b'0'..=b'9' | b'+' | b'-' | b'.' => {
+                self.position = start;
+                let number_bytes = self.read_regular_bytes();
+                let number_string = String::from_utf8_lossy(number_bytes);
+                if let Ok(int) = number_string.parse::<i64>() {
+                    Token::Int(int)
+                } else if let Ok(real) = number_string.parse::<f64>() {
+                    Token::Real(real)
+                } else {
+                    return Err(self.error(format!("Bad number {}", number_string)));
+                }
+            },
+            _ => {
+                self.position = start;
+                let keyword = self.read_regular_bytes();
+                if keyword.is_empty() {
+                    return Err(self.error(format!("Unexpected byte 0x{:02X}", byte)));
+                }
+                Token::Keyword(keyword.to_vec())
+            },
+        };
+        Ok(token)
+    }
+
+    /// Reads until the next whitespace or delimiter
+    fn read_regular_bytes(&mut self) -> &'a [u8] {
+        let start = self.position;
+        while let Some(&byte) = self.bytes.get(self.position) {
+            if is_whitespace(byte) || crate::utils::DELIMITER_CHARS.contains(&byte) {
+                break;
+            }
+            self.position += 1;
+        }
+        &self.bytes[start..self.position]
+    }
+
+    fn read_hex_string(&mut self) -> PDFResult< Vec<u8> > {
+        let mut digits = Vec::new();
+        loop {
+            let byte = *self.bytes.get(self.position)
+                .ok_or_else(|| self.error("Unterminated hex string"))?;
+            self.position += 1;
+            match byte {
+                b'>' => break,
+                _ if is_whitespace(byte) => (),
+                _ => digits.push(hex_value(byte).ok_or_else(|| self.error("Bad hex digit"))?),
+            }
+        }
+        // A missing final digit is assumed to be 0
+        if digits.len() % 2 == 1 {
+            digits.push(0);
+        }
+        Ok(digits.chunks(2).map(|pair| (pair[0] << 4) | pair[1]).collect())
+    }
+
+    fn read_literal_string(&mut self) -> PDFResult< Vec<u8> > {
+        let mut string_bytes = Vec::new();
+        // Balanced parentheses are allowed without escaping them
+        let mut depth = 1;
+        loop {
+            let byte = *self.bytes.get(self.position)
+                .ok_or_else(|| self.error("Unterminated string"))?;
+            self.position += 1;
+            match byte {
+                b'(' => {
+                    depth += 1;
+                    string_bytes.push(byte);
+                },
+                b')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                    string_bytes.push(byte);
+                },
+                b'\\' => {
+                    let escaped = *self.bytes.get(self.position)
+                        .ok_or_else(|| self.error("Unterminated string"))?;
+                    self.position += 1;
+                    match escaped {
+                        b'n' => string_bytes.push(b'\n'),
+                        b'r' => string_bytes.push(b'\r'),
+                        b't' => string_bytes.push(b'\t'),
+                        b'b' => string_bytes.push(0x08),
+                        b'f' => string_bytes.push(0x0C),
+                        b'0'..=b'7' => {
+                            // Up to 3 octal digits
+                            let mut value = (escaped - b'0') as u32;
+                            for _ in 0..2 {
+                                match self.bytes.get(self.position) {
+                                    Some(&digit @ b'0'..=b'7') => {
+                                        value = value * 8 + (digit - b'0') as u32;
+                                        self.position += 1;
+                                    },
+                                    _ => break,
+                                }
+                            }
+                            string_bytes.push(value as u8);
+                        },
+                        // A backslash at the end of a line continues the string
+                        b'\r' => {
+                            if self.bytes.get(self.position) == Some(&b'\n') {
+                                self.position += 1;
+                            }
+                        },
+                        b'\n' => (),
+                        _ => string_bytes.push(escaped),
+                    }
+                },
+                _ => string_bytes.push(byte),
+            }
+        }
+        Ok(string_bytes)
+    }
+
+    fn error(&self, message: impl ToString) -> PDFError {
+        PDFError::MalformedPDF(format!("{} (byte {})", message.to_string(), self.position))
+    }
+}
+
+enum Token {
+    Int(i64),
+    Real(f64),
+    Name(Name),
+    Str(Vec<u8>),
+    HexStr(Vec<u8>),
+    ArrayStart,
+    ArrayEnd,
+    DictionaryStart,
+    DictionaryEnd,
+    Keyword(Vec<u8>),
+}
+
+pub fn is_whitespace(byte: u8) -> bool {
+    matches!(byte, 0x00 | b'\t' | b'\n' | 0x0C | b'\r' | b' ')
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Undoes the `#XX` escapes in a name
+fn decode_name(name_bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(name_bytes.len());
+    let mut i = 0;
+    while i < name_bytes.len() {
+        if name_bytes[i] == b'#' {
+            let high = name_bytes.get(i + 1).and_then(|&byte| hex_value(byte));
+            let low = name_bytes.get(i + 2).and_then(|&byte| hex_value(byte));
+            if let (Some(high), Some(low)) = (high, low) {
+                decoded.push((high << 4) | low);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(name_bytes[i]);
+        i += 1;
+    }
+    decoded
+}
+
+/// Text strings are either UTF-16BE (with a byte order mark) or close enough to Latin-1.
+/// Binary strings (like the file ID) won't survive being turned into a `String`.
+fn decode_text_string(string_bytes: &[u8]) -> String {
+    if string_bytes.starts_with(&[0xFE, 0xFF]) {
+        let utf16: Vec<u16> = string_bytes[2..].chunks(2)
+            .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]))
+            .collect();
+        String::from_utf16_lossy(&utf16)
+    } else {
+        string_bytes.iter().map(|&byte| byte as char).collect()
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    fs,
+    path::{Path},
+};
+use quick_xml::{
+    Reader,
+    events::{BytesStart, Event},
+};
+use crate::{
+    PDFError, PDFResult,
+};
+
+/// (left, top, right, bottom)
+type BoundingBox = (f64, f64, f64, f64);
+
+/// HTML elements that never have an end tag
+const VOID_ELEMENTS: &[&[u8]] = &[
+    b"area", b"base", b"br", b"col", b"embed", b"hr", b"img", b"input", b"link", b"meta", b"source", b"track", b"wbr",
+];
+
+/// A word that was found in an image by OCR
+pub struct TextLayerWord {
+    text: String,
+    /// Fractions of the image size, from the top left
+    bounding_box: BoundingBox,
+}
+impl TextLayerWord {
+    pub fn text(&self) -> &str { &self.text }
+    pub fn bounding_box(&self) -> BoundingBox { self.bounding_box }
+    /// Vertical text (tategaki) has words that are taller than they are wide
+    pub fn is_vertical(&self) -> bool {
+        let (left, top, right, bottom) = self.bounding_box;
+        bottom - top > right - left && self.text.chars().count() > 1
+    }
+}
+
+/// The text that OCR found in an image, so that it can be searched and copied in the PDF
+pub struct TextLayer {
+    words: Vec<TextLayerWord>,
+}
+impl TextLayer {
+    /// Reads either an hOCR or an ALTO file
+    pub fn from_path(path: impl AsRef<Path>) -> PDFResult<TextLayer> {
+        let contents = fs::read_to_string(path)?;
+        if contents.contains("<alto") {
+            TextLayer::from_alto(&contents)
+        } else {
+            TextLayer::from_hocr(&contents)
+        }
+    }
+    /// Uses the `ocrx_word` elements, and the `ocr_page` for the size of the image
+    pub fn from_hocr(hocr: &str) -> PDFResult<TextLayer> {
+        let mut reader = make_reader(hocr);
+        let mut page_size = None;
+        let mut pixel_words = Vec::new();
+        // The bounding box and text of the word we're in, and how deep we are inside of it
+        let mut current_word: Option<(BoundingBox, String, usize)> = None;
+        loop {
+            match reader.read_event().map_err(|e| PDFError::BadTextLayer(e.to_string()))? {
+                Event::Start(element) => {
+                    if VOID_ELEMENTS.contains(&element.local_name().as_ref()) {
+                        continue;
+                    }
+                    let class = read_attribute(&element, "class").unwrap_or_default();
+                    let class = class.split_whitespace().next();
+                    match current_word.as_mut() {
+                        Some((_, _, depth)) if class != Some("ocrx_word") => {
+                            *depth += 1;
+                            continue;
+                        },
+                        _ => (),
+                    }
+                    // A word that wasn't closed ends where the next one starts
+                    if let Some((bounding_box, text, _)) = current_word.take() {
+                        pixel_words.push((bounding_box, text));
+                    }
+                    let bounding_box = read_attribute(&element, "title")
+                        .and_then(|title| read_hocr_bounding_box(&title));
+                    match (class, bounding_box) {
+                        (Some("ocr_page"), Some((_, _, right, bottom))) => { page_size = Some((right, bottom)); },
+                        (Some("ocrx_word"), Some(bounding_box)) => {
+                            current_word = Some((bounding_box, String::new(), 0));
+                        },
+                        _ => (),
+                    }
+                },
+                Event::Text(text) => {
+                    if let Some((_, word_text, _)) = current_word.as_mut() {
+                        let text = text.unescape().map(|text| text.into_owned())
+                            .unwrap_or_else(|_| String::from_utf8_lossy(&text).into_owned());
+                        word_text.push_str(&text);
+                    }
+                },
+                Event::End(_) => {
+                    let is_word_end = match current_word.as_mut() {
+                        Some((_, _, depth)) if *depth > 0 => {
+                            *depth -= 1;
+                            false
+                        },
+                        Some(_) => true,
+                        None => false,
+                    };
+                    if let Some((bounding_box, text, _)) = current_word.take_if(|_| is_word_end) {
+                        pixel_words.push((bounding_box, text));
+                    }
+                },
+                Event::Eof => {
+                    if let Some((bounding_box, text, _)) = current_word.take() {
+                        pixel_words.push((bounding_box, text));
+                    }
+                    break;
+                },
+                _ => (),
+            }
+        }
+        let page_size = page_size
+            .ok_or_else(|| PDFError::BadTextLayer("The hOCR doesn't have an ocr_page with a bbox".to_string()))?;
+        TextLayer::from_pixel_words(pixel_words, page_size)
+    }
+    /// Uses the `String` elements, and the `Page` for the size of the image
+    pub fn from_alto(alto: &str) -> PDFResult<TextLayer> {
+        let mut reader = make_reader(alto);
+        let mut page_size = None;
+        let mut pixel_words = Vec::new();
+        loop {
+            match reader.read_event().map_err(|e| PDFError::BadTextLayer(e.to_string()))? {
+                Event::Start(element) | Event::Empty(element) => {
+                    let read_number = |name| read_attribute(&element, name)
+                        .and_then(|value| value.trim().parse::<f64>().ok());
+                    match element.local_name().as_ref() {
+                        b"Page" => {
+                            if let (Some(width), Some(height)) = (read_number("WIDTH"), read_number("HEIGHT")) {
+                                page_size = Some((width, height));
+                            }
+                        },
+                        b"String" => {
+                            let position = (read_number("HPOS"), read_number("VPOS"),
+                                read_number("WIDTH"), read_number("HEIGHT"));
+                            let text = read_attribute(&element, "CONTENT");
+                            if let ((Some(left), Some(top), Some(width), Some(height)), Some(text)) = (position, text) {
+                                pixel_words.push(( (left, top, left + width, top + height), text ));
+                            }
+                        },
+                        _ => (),
+                    }
+                },
+                Event::Eof => break,
+                _ => (),
+            }
+        }
+        let page_size = page_size
+            .ok_or_else(|| PDFError::BadTextLayer("The ALTO doesn't have a Page with a size".to_string()))?;
+        TextLayer::from_pixel_words(pixel_words, page_size)
+    }
+
+    pub fn words(&self) -> &[TextLayerWord] { &self.words }
+}
+impl TextLayer {
+    /// The page size has to be more than 0 and finite, so that the fractions are too
+    fn from_pixel_words(pixel_words: Vec<(BoundingBox, String)>, (width, height): (f64, f64))
+    -> PDFResult<TextLayer> {
+        if !(width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0) {
+            return Err(PDFError::BadTextLayer(format!("Bad page size {}x{}", width, height)));
+        }
+        let words = pixel_words.into_iter()
+            .map(|((left, top, right, bottom), text)| (
+                (left / width, top / height, right / width, bottom / height),
+                text.trim().to_string(),
+            ))
+            .filter(|((left, top, right, bottom), text)| {
+                !text.is_empty() && [left, top, right, bottom].iter().all(|value| value.is_finite())
+            })
+            .map(|(bounding_box, text)| TextLayerWord { text, bounding_box })
+            .collect();
+        Ok(TextLayer { words })
+    }
+}
+
+/// hOCR is usually XHTML, but don't fail on HTML that doesn't close every tag
+fn make_reader(contents: &str) -> Reader<&[u8]> {
+    let mut reader = Reader::from_str(contents);
+    reader.config_mut().check_end_names = false;
+    reader
+}
+
+fn read_attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element.try_get_attribute(name).ok()
+        .flatten()
+        .and_then(|attribute| attribute.unescape_value().ok())
+        .map(|value| value.into_owned())
+}
+
+/// The title looks like `bbox 10 20 110 45; x_wconf 93`
+fn read_hocr_bounding_box(title: &str) -> Option<BoundingBox> {
+    let bounding_box = title.split(';')
+        .map(str::trim)
+        .find_map(|property| property.strip_prefix("bbox "))?;
+    let numbers: Vec<f64> = bounding_box.split_whitespace()
+        .map(|number| number.parse().ok())
+        .collect::<Option<_>>()?;
+    match numbers[..] {
+        [left, top, right, bottom] => Some((left, top, right, bottom)),
+        _ => None,
+    }
+}
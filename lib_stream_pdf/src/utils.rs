@@ -3,22 +3,80 @@ use std::{
 };
 use flate2::{
     Compression,
-    write::{ZlibEncoder},
+    write::{ZlibDecoder, ZlibEncoder},
+};
+use sha2::{Digest, Sha256};
+use crate::{
+    PDFResult,
+    compression::{FlateLevel},
 };
-use crate::{PDFResult};
 
-pub const DELIMITER_CHARS: &'static [u8] = &[
-    b'(', b')', b'<', b'>', b'[', b']', b'{', b'}', b'/', b'%'
-];
+pub const DELIMITER_CHARS: &[u8] = b"()<>[]{}/%";
 
-pub fn flate_compress(to_compress: &[u8], size_hint: Option<usize>) -> PDFResult< Vec<u8> > {
-    let compress_vec = {
+pub fn flate_compress(to_compress: &[u8], size_hint: Option<usize>, flate_level: FlateLevel)
+-> PDFResult< Vec<u8> > {
+    let mut compress_vec = {
         if let Some(size_hint) = size_hint { Vec::with_capacity(size_hint) }
         else { Vec::new() }
     };
-    let mut encoder = ZlibEncoder::new(compress_vec, Compression::best());
-    encoder.write_all(to_compress)?;
-    Ok(encoder.finish()?)
+    match flate_level {
+        FlateLevel::Zlib(level) => {
+            let mut encoder = ZlibEncoder::new(compress_vec, Compression::new(level));
+            encoder.write_all(to_compress)?;
+            Ok(encoder.finish()?)
+        },
+        FlateLevel::Zopfli => {
+            zopfli::compress(zopfli::Options::default(), zopfli::Format::Zlib, to_compress, &mut compress_vec)?;
+            Ok(compress_vec)
+        },
+    }
+}
+
+pub fn flate_decompress(to_decompress: &[u8]) -> PDFResult< Vec<u8> > {
+    let mut decoder = ZlibDecoder::new(Vec::with_capacity(to_decompress.len() * 2));
+    decoder.write_all(to_decompress)?;
+    Ok(decoder.finish()?)
+}
+
+/// The same parts always give the same hash, for finding objects that were already written.
+/// It's 64-bit FNV-1a, so it doesn't change between Rust versions like `DefaultHasher` can.
+pub fn content_hash(parts: &[&[u8]]) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+    const FNV_PRIME: u64 = 0x0100_0000_01B3;
+    let mut hash = FNV_OFFSET_BASIS;
+    for part in parts {
+        // Starting with the length keeps `["ab", "c"]` from hashing the same as `["a", "bc"]`
+        let length_bytes = (part.len() as u64).to_be_bytes();
+        for &byte in length_bytes.iter().chain(part.iter()) {
+            hash = (hash ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+    }
+    hash
+}
+
+/// Like `content_hash`, but with SHA-256 so that different objects can be taken to never match
+pub fn content_digest(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Picks whichever of the neighbouring bytes is closest to `left + up - up_left` (from PNG)
+pub fn paeth_predictor(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let left_distance = (estimate - left as i16).abs();
+    let up_distance = (estimate - up as i16).abs();
+    let up_left_distance = (estimate - up_left as i16).abs();
+    if left_distance <= up_distance && left_distance <= up_left_distance {
+        left
+    } else if up_distance <= up_left_distance {
+        up
+    } else {
+        up_left
+    }
 }
 
 pub fn to_utf16(string: &str) -> Vec<u8> {
@@ -29,3 +87,24 @@ pub fn to_utf16(string: &str) -> Vec<u8> {
     });
     utf_bytes
 }
+
+/// Full-width (CJK) characters take up a whole em and stay upright in vertical text
+pub fn is_wide(character: char) -> bool {
+    matches!(character as u32,
+        // Hangul Jamo
+        0x1100..=0x115F |
+        // Dashes and ellipses that Japanese text uses as full-width
+        0x2014..=0x2015 | 0x2025..=0x2026 | 0x203B |
+        // Shapes and symbols
+        0x25A0..=0x27BF |
+        // CJK radicals, punctuation, kana, and the CJK Unified Ideographs
+        0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF |
+        // Yi and Hangul syllables
+        0xA000..=0xA4CF | 0xAC00..=0xD7A3 |
+        // Compatibility ideographs and forms
+        0xF900..=0xFAFF | 0xFE30..=0xFE4F |
+        // Full-width forms (but not the half-width katakana)
+        0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 |
+        // The supplementary ideographic planes
+        0x20000..=0x3FFFD)
+}
@@ -0,0 +1,77 @@
+use crate::{
+    font::{PDFFont},
+};
+
+/// Runs of half-width digits up to this long are set horizontally (tate-chu-yoko)
+const TATE_CHU_YOKO_MAX_LENGTH: usize = 3;
+/// These get turned on their side if the font doesn't have a vertical version of them
+const ROTATED_PUNCTUATION: &[char] = &[
+    'ー', '－', '—', '―', '‥', '…', '～', '〜', '＝', '：', '；', '｜', '＿',
+    '（', '）', '「', '」', '『', '』', '【', '】', '〔', '〕', '〈', '〉', '《', '》',
+    '［', '］', '｛', '｝', '＜', '＞',
+];
+
+/// A piece of a vertical line of text that gets drawn in the same way
+pub enum VerticalRun {
+    /// Drawn with the vertical font, one glyph under the next. The glyphs are already swapped
+    /// for their vertical versions.
+    Upright(Vec<(u16, char)>),
+    /// Turned 90° clockwise and drawn like horizontal text (like Latin words)
+    Sideways(String),
+    /// A short number set horizontally to fit in a single character's space (tate-chu-yoko)
+    Horizontal(String),
+}
+impl VerticalRun {
+    /// How much of the line this run takes up
+    pub fn height(&self, font: &PDFFont, font_size: f64) -> f64 {
+        match self {
+            Self::Upright(glyphs) => {
+                let total_height: f64 = glyphs.iter()
+                    .map(|&(glyph_id, _)| font.advance_height(glyph_id))
+                    .sum();
+                total_height * font_size / 1000.0
+            },
+            Self::Sideways(text) => font.text_width(text, font_size),
+            Self::Horizontal(_) => font_size,
+        }
+    }
+}
+
+pub fn split_vertical_runs(font: &PDFFont, text: &str) -> Vec<VerticalRun> {
+    let mut vertical_runs = Vec::new();
+    let mut upright_glyphs = Vec::new();
+    let mut sideways_text = String::new();
+    for character in text.chars() {
+        let glyph_id = font.glyph_id(character);
+        let vertical_glyph_id = font.vertical_glyph_id(glyph_id);
+        let is_sideways = !crate::utils::is_wide(character) ||
+            (vertical_glyph_id.is_none() && ROTATED_PUNCTUATION.contains(&character));
+        if is_sideways {
+            if !upright_glyphs.is_empty() {
+                vertical_runs.push(VerticalRun::Upright(std::mem::take(&mut upright_glyphs)));
+            }
+            sideways_text.push(character);
+        } else {
+            if !sideways_text.is_empty() {
+                vertical_runs.push(make_sideways_run(std::mem::take(&mut sideways_text)));
+            }
+            upright_glyphs.push( (vertical_glyph_id.unwrap_or(glyph_id), character) );
+        }
+    }
+    if !upright_glyphs.is_empty() {
+        vertical_runs.push(VerticalRun::Upright(upright_glyphs));
+    }
+    if !sideways_text.is_empty() {
+        vertical_runs.push(make_sideways_run(sideways_text));
+    }
+    vertical_runs
+}
+
+/// Short numbers are kept upright, but anything else gets turned
+fn make_sideways_run(text: String) -> VerticalRun {
+    if text.chars().count() <= TATE_CHU_YOKO_MAX_LENGTH && text.chars().all(|character| character.is_ascii_digit()) {
+        VerticalRun::Horizontal(text)
+    } else {
+        VerticalRun::Sideways(text)
+    }
+}
@@ -0,0 +1,187 @@
+use std::{
+    io::{Write},
+};
+use crate::{
+    PDFError, PDFResult,
+    compression::{FlateLevel},
+    objects::{Dictionary, Name, Object, ObjectId, Stream},
+};
+
+/// How many objects get packed together before an object stream gets written out
+const OBJECTS_PER_STREAM: usize = 100;
+
+/// How the cross-reference section at the end of the document is written.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum XrefMode {
+    /// A classic text xref table. Every object is written on its own and byte offsets are limited
+    /// to 10 digits.
+    Table,
+    /// A PDF 1.5 cross-reference stream. Every object that isn't a stream gets packed into
+    /// compressed object streams.
+    Stream,
+}
+
+pub enum ObjectLocation {
+    Free,
+    Offset(u64),
+    /// The object is at `index` inside of the object stream `stream_id`
+    InObjectStream { stream_id: ObjectId, index: usize },
+}
+
+pub struct WrittenObject {
+    id: ObjectId,
+    location: ObjectLocation,
+}
+impl WrittenObject {
+    pub fn new(id: ObjectId, location: ObjectLocation) -> WrittenObject {
+        WrittenObject { id, location }
+    }
+    pub fn object_num(&self) -> u32 { self.id.object_num() }
+
+    fn write_xref_line<W: Write>(&self, writer: &mut W) -> PDFResult<()> {
+        let (byte_offset, object_type) = match self.location {
+            ObjectLocation::Free => (0, "f"),
+            ObjectLocation::Offset(byte_offset) => (byte_offset, "n"),
+            // This can only be written in a cross-reference stream
+            ObjectLocation::InObjectStream { .. } => return Err(PDFError::ObjectNotInTable),
+        };
+        let byte_index_string = format!("{:010}", byte_offset);
+        if byte_index_string.len() > 10 {
+            return Err(PDFError::ByteIndexTooLarge);
+        }
+        let gen_number = self.id.gen_string();
+        write!(writer, "{} {} {}\r\n", byte_index_string, gen_number, object_type)?;
+        Ok(())
+    }
+
+    /// The (type, field 2, field 3) for this object's entry in a cross-reference stream
+    fn xref_stream_fields(&self) -> (u8, u64, u64) {
+        match self.location {
+            // There aren't any other free objects, so the next free object is always 0
+            ObjectLocation::Free => (0, 0, self.id.gen_num() as u64),
+            ObjectLocation::Offset(byte_offset) => (1, byte_offset, self.id.gen_num() as u64),
+            ObjectLocation::InObjectStream { stream_id, index } => {
+                (2, stream_id.object_num() as u64, index as u64)
+            },
+        }
+    }
+}
+
+/// Collects objects until there are enough of them to write out as a compressed object stream.
+pub struct ObjectStreamBuilder {
+    objects: Vec<(ObjectId, Vec<u8>)>,
+}
+impl ObjectStreamBuilder {
+    pub fn new() -> ObjectStreamBuilder {
+        ObjectStreamBuilder { objects: Vec::new() }
+    }
+
+    pub fn is_empty(&self) -> bool { self.objects.is_empty() }
+    pub fn is_full(&self) -> bool { self.objects.len() >= OBJECTS_PER_STREAM }
+
+    /// Only objects with a generation number of 0 that aren't streams can go into an object stream.
+    pub fn push(&mut self, id: ObjectId, object: Object) -> PDFResult<()> {
+        let mut object_bytes = Vec::new();
+        object.write_to(&mut object_bytes)?;
+        self.objects.push( (id, object_bytes) );
+        Ok(())
+    }
+
+    /// Empties out the builder, giving back the stream and the IDs in the order they were packed.
+    pub fn take_stream(&mut self, flate_level: FlateLevel) -> PDFResult<(Stream, Vec<ObjectId>)> {
+        let objects = std::mem::take(&mut self.objects);
+        let mut header = Vec::new();
+        let mut body = Vec::new();
+        for (id, object_bytes) in &objects {
+            write!(&mut header, "{} {} ", id.object_num(), body.len())?;
+            body.extend_from_slice(object_bytes);
+            // Keep the objects from running into each other
+            body.push(b'\n');
+        }
+        let first_offset = header.len();
+        header.extend_from_slice(&body);
+
+        let compressed_contents = crate::utils::flate_compress(&header, None, flate_level)?;
+        let mut stream_dictionary = Dictionary::new();
+        stream_dictionary.insert(Name::type_name(), Name::obj_stm());
+        stream_dictionary.insert(Name::n(), objects.len());
+        stream_dictionary.insert(Name::first(), first_offset);
+        stream_dictionary.insert(Name::filter(), Name::flate_decode());
+
+        let ids = objects.into_iter().map(|(id, _)| id).collect();
+        Ok( (Stream::new(stream_dictionary, compressed_contents), ids) )
+    }
+}
+
+/// The trailer's `/Size` is one past the largest object number.
+pub fn xref_size(written_objects: &[WrittenObject]) -> u32 {
+    written_objects.iter()
+        .map(|written_object| written_object.object_num() + 1)
+        .max().unwrap_or(0)
+}
+
+pub fn write_xref_table<W: Write>(writer: &mut W, written_objects: &mut [WrittenObject])
+-> PDFResult<()> {
+    writer.write_all(b"xref\n")?;
+    for adjacent_objects in adjacent_object_runs(written_objects) {
+        let start_object_num = adjacent_objects[0].object_num();
+        writeln!(writer, "{} {}", start_object_num, adjacent_objects.len())?;
+        for written_object in adjacent_objects {
+            written_object.write_xref_line(writer)?;
+        }
+    }
+    Ok(())
+}
+
+/// `trailer` needs to have everything except for the stream specific entries.
+/// The cross-reference stream's own entry needs to be in `written_objects` already.
+pub fn make_xref_stream(written_objects: &mut [WrittenObject], mut trailer: Dictionary, flate_level: FlateLevel)
+-> PDFResult<Stream> {
+    let fields: Vec<(u8, u64, u64)> = written_objects.iter()
+        .map(|written_object| written_object.xref_stream_fields())
+        .collect();
+    let field_2_width = fields.iter().map(|&(_, field_2, _)| byte_width(field_2)).max().unwrap_or(1);
+    let field_3_width = fields.iter().map(|&(_, _, field_3)| byte_width(field_3)).max().unwrap_or(1);
+
+    let mut index = Vec::new();
+    let mut entries = Vec::new();
+    for adjacent_objects in adjacent_object_runs(written_objects) {
+        index.push(adjacent_objects[0].object_num());
+        index.push(adjacent_objects.len() as u32);
+        for written_object in adjacent_objects {
+            let (object_type, field_2, field_3) = written_object.xref_stream_fields();
+            entries.push(object_type);
+            entries.extend_from_slice(&field_2.to_be_bytes()[(8 - field_2_width)..]);
+            entries.extend_from_slice(&field_3.to_be_bytes()[(8 - field_3_width)..]);
+        }
+    }
+
+    trailer.insert(Name::type_name(), Name::xref());
+    trailer.insert(Name::w(), vec![1, field_2_width, field_3_width]);
+    trailer.insert(Name::index(), index);
+    trailer.insert(Name::filter(), Name::flate_decode());
+    let compressed_entries = crate::utils::flate_compress(&entries, None, flate_level)?;
+    Ok(Stream::new(trailer, compressed_entries))
+}
+
+/// Sorts the objects and splits them into runs of consecutive object numbers (xref subsections).
+fn adjacent_object_runs(written_objects: &mut [WrittenObject]) -> Vec<&[WrittenObject]> {
+    written_objects.sort_by_key(|written_object| written_object.id);
+    let mut runs = Vec::new();
+    let mut run_start = 0;
+    for i in 1..=written_objects.len() {
+        let is_run_end = i == written_objects.len() ||
+            written_objects[i - 1].object_num() + 1 != written_objects[i].object_num();
+        if is_run_end {
+            runs.push(&written_objects[run_start..i]);
+            run_start = i;
+        }
+    }
+    runs
+}
+
+/// The number of bytes needed to hold the value (always at least 1)
+fn byte_width(value: u64) -> usize {
+    let significant_bits = 64 - value.leading_zeros() as usize;
+    std::cmp::max(1, significant_bits.div_ceil(8))
+}
@@ -0,0 +1,32 @@
+use image::{DynamicImage, GrayImage, Luma};
+use lib_stream_pdf::{ImageEncoding, PDFError, PDFImage};
+
+fn make_line_art(dark: u8, light: u8) -> DynamicImage {
+    DynamicImage::ImageLuma8(GrayImage::from_fn(40, 30, |x, y| {
+        Luma([match (x + y) % 4 { 0 => 0, 1 => dark, 2 => light, _ => 255 }])
+    }))
+}
+
+#[test]
+fn black_and_white_images_are_bilevel() {
+    let pdf_image = PDFImage::from_image(make_line_art(0, 255), true).unwrap();
+    assert_eq!(pdf_image.encoding(), ImageEncoding::Bilevel);
+}
+
+#[test]
+fn nearly_bilevel_images_stay_lossless() {
+    // Thresholding them would change the grays near black and white
+    for (dark, light) in [(1, 255), (8, 247), (0, 254)] {
+        let pdf_image = PDFImage::from_image(make_line_art(dark, light), true).unwrap();
+        assert_ne!(pdf_image.encoding(), ImageEncoding::Bilevel, "{} {}", dark, light);
+    }
+}
+
+#[test]
+fn empty_images_give_an_error() {
+    for (width, height) in [(0, 0), (0, 10), (10, 0)] {
+        let image = DynamicImage::ImageLuma8(GrayImage::new(width, height));
+        assert!(matches!(PDFImage::from_image(image.clone(), true), Err(PDFError::EmptyImage)));
+        assert!(matches!(PDFImage::from_image_bilevel(image, 128), Err(PDFError::EmptyImage)));
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    io::{Cursor},
+    thread,
+    time::{Duration},
+};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage, Rgba, RgbaImage};
+use lib_stream_pdf::{ColourProfiles, DocumentInfo, DocumentReader, DocumentWriter, EncodingPolicy, Name, PDFImage};
+
+fn make_image(alpha: u8) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(30, 20, |x, y| Rgba([x as u8 * 8, y as u8 * 12, 60, alpha])))
+}
+
+#[test]
+fn duplicates_count_their_soft_masks() {
+    let mut writer = DocumentWriter::in_memory().unwrap();
+    writer.add_image(PDFImage::from_image(make_image(128), true).unwrap()).unwrap();
+    assert_eq!(writer.duplicate_images().count(), 0);
+
+    writer.add_image(PDFImage::from_image(make_image(128), true).unwrap()).unwrap();
+    // The image and its soft mask
+    assert_eq!(writer.duplicate_images().count(), 2);
+    let bytes_saved = writer.duplicate_images().bytes_saved();
+    assert!(bytes_saved > 0);
+
+    // Only the colours are the same, so just the soft mask is left out
+    writer.add_image(PDFImage::from_image(make_image(200), true).unwrap()).unwrap();
+    assert_eq!(writer.duplicate_images().count(), 2);
+    assert_eq!(writer.duplicate_images().bytes_saved(), bytes_saved);
+}
+
+fn make_converted_image() -> PDFImage {
+    let image = RgbImage::from_fn(16, 16, |x, y| Rgb([x as u8 * 16, y as u8 * 16, 0]));
+    let mut png_bytes = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(image).write_to(&mut png_bytes, ImageFormat::Png).unwrap();
+    let policy = EncodingPolicy::new().with_colour_profiles(ColourProfiles::ConvertToStandard);
+    PDFImage::from_bytes_with_policy(png_bytes.into_inner(), &policy).unwrap()
+}
+
+#[test]
+fn converted_images_made_at_different_times_are_duplicates() {
+    let mut writer = DocumentWriter::in_memory().unwrap();
+    writer.add_image(make_converted_image()).unwrap();
+    // Long enough for the time in the standard profile to change if it had one
+    thread::sleep(Duration::from_millis(1100));
+    writer.add_image(make_converted_image()).unwrap();
+    assert_eq!(writer.duplicate_images().count(), 1);
+    let pdf = writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner();
+
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    let profile_count = reader.object_ids().into_iter()
+        .filter_map(|id| reader.get_object(id).unwrap().as_stream().cloned())
+        .filter(|stream| stream.dictionary().get(&Name::new("Alternate")).is_some())
+        .count();
+    assert_eq!(profile_count, 1);
+}
@@ -0,0 +1,134 @@
+use std::{
+    io::{Cursor},
+};
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use lib_stream_pdf::{
+    ColourProfiles, DocumentInfo, DocumentReader, DocumentWriter, EncodingPolicy, Justify, Name, Object, PDFImage,
+    PDFPage,
+};
+
+/// A fragment whose page has a text annotation with a popup, and the popup points back to it with `/Parent`
+fn make_fragment() -> Vec<u8> {
+    let objects = [
+        "<</Type /Catalog /Pages 2 0 R>>",
+        "<</Type /Pages /Count 1 /Kids [3 0 R]>>",
+        "<</Type /Page /Parent 2 0 R /MediaBox [0 0 200 300] /Annots [4 0 R 5 0 R]>>",
+        "<</Type /Annot /Subtype /Text /Rect [0 0 10 10] /Popup 5 0 R>>",
+        "<</Type /Annot /Subtype /Popup /Rect [0 0 50 50] /Parent 4 0 R>>",
+    ];
+    let mut pdf = b"%PDF-1.7\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+    let xref_start = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f\r\n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n\r\n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(format!(
+        "trailer\n<</Size {} /Root 1 0 R>>\nstartxref\n{}\n%%EOF", objects.len() + 1, xref_start
+    ).as_bytes());
+    pdf
+}
+
+#[test]
+fn only_the_page_loses_its_parent() {
+    let mut writer = DocumentWriter::in_memory().unwrap();
+    writer.add_page(PDFPage::new(100.0, 100.0)).unwrap();
+    writer.append_fragment(&DocumentReader::from_bytes(make_fragment()).unwrap()).unwrap();
+    let pdf = writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner();
+
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    let pages = reader.pages().unwrap();
+    assert_eq!(pages.len(), 2);
+    let catalog = reader.catalog().unwrap();
+    assert_eq!(pages[1].1.get(&Name::parent()).and_then(Object::as_ref_id),
+        catalog.get(&Name::pages()).and_then(Object::as_ref_id));
+
+    let annotations = pages[1].1.get(&Name::new("Annots")).and_then(Object::as_array).unwrap();
+    let popup = reader.resolve_dictionary(&annotations[1]).unwrap();
+    let parent = reader.resolve_dictionary(popup.get(&Name::parent()).unwrap()).unwrap();
+    assert_eq!(parent.get(&Name::new("Subtype")).and_then(Object::as_name), Some(&Name::new("Text")));
+    assert_eq!(popup.get(&Name::parent()).and_then(Object::as_ref_id), annotations[0].as_ref_id());
+}
+
+#[test]
+fn fragment_writers_come_after_the_document() {
+    let mut writer = DocumentWriter::in_memory().unwrap();
+    writer.add_page(PDFPage::new(100.0, 100.0)).unwrap();
+    let mut fragment_writers: Vec<_> = (0..2)
+        .map(|_| writer.fragment_writer(Cursor::new(Vec::new())).unwrap())
+        .collect();
+    // The second fragment gets written first, like it could be on its own thread
+    for (i, fragment_writer) in fragment_writers.iter_mut().enumerate().rev() {
+        let image = RgbImage::from_pixel(8, 8, Rgb([i as u8 * 100, 20, 20]));
+        let image_ref = fragment_writer.add_image(PDFImage::from_image(DynamicImage::ImageRgb8(image), true).unwrap())
+            .unwrap();
+        let mut page = PDFPage::new(200.0 + i as f64, 300.0);
+        page.add_image(image_ref, 0.0, 1.0, Justify::Start);
+        fragment_writer.add_page(page).unwrap();
+    }
+    for fragment_writer in fragment_writers {
+        let fragment = fragment_writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner();
+        writer.append_fragment(&DocumentReader::from_bytes(fragment).unwrap()).unwrap();
+    }
+    let pdf = writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner();
+
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    let pages = reader.pages().unwrap();
+    let widths: Vec<f64> = pages.iter()
+        .map(|(_, page)| page.get(&Name::media_box()).and_then(Object::as_array).unwrap()[2].as_number().unwrap())
+        .collect();
+    assert_eq!(widths, vec![100.0, 200.0, 201.0]);
+}
+
+/// A noisy image that's given the standard RGB profile
+fn make_profiled_image(seed: u8) -> PDFImage {
+    let image = RgbImage::from_fn(16, 16, |x, y| Rgb([x as u8 * seed, y as u8 * 7, (x * y) as u8]));
+    let mut png_bytes = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(image).write_to(&mut png_bytes, ImageFormat::Png).unwrap();
+    let policy = EncodingPolicy::new().with_colour_profiles(ColourProfiles::ConvertToStandard);
+    PDFImage::from_bytes_with_policy(png_bytes.into_inner(), &policy).unwrap()
+}
+
+fn add_image_page<W: std::io::Write + std::io::Seek>(writer: &mut DocumentWriter<W>, pdf_image: PDFImage) {
+    let image_ref = writer.add_image(pdf_image).unwrap();
+    let mut page = PDFPage::new(100.0, 100.0);
+    page.add_image(image_ref, 0.0, 1.0, Justify::Start);
+    writer.add_page(page).unwrap();
+}
+
+#[test]
+fn images_in_fragments_are_shared_with_the_document() {
+    let mut writer = DocumentWriter::in_memory().unwrap();
+    add_image_page(&mut writer, make_profiled_image(3));
+    let fragment_writers: Vec<_> = (0..2)
+        .map(|_| writer.fragment_writer(Cursor::new(Vec::new())).unwrap())
+        .collect();
+    for mut fragment_writer in fragment_writers {
+        add_image_page(&mut fragment_writer, make_profiled_image(3));
+        add_image_page(&mut fragment_writer, make_profiled_image(5));
+        let fragment = fragment_writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner();
+        writer.append_fragment(&DocumentReader::from_bytes(fragment).unwrap()).unwrap();
+    }
+    // The first image again in both fragments, and the second one again in the second fragment
+    assert_eq!(writer.duplicate_images().count(), 3);
+    let pdf = writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner();
+
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    assert_eq!(reader.pages().unwrap().len(), 5);
+    let streams: Vec<Object> = reader.object_ids().into_iter()
+        .map(|id| reader.get_object(id).unwrap())
+        .filter(|object| object.as_stream().is_some())
+        .collect();
+    let image_count = streams.iter()
+        .filter(|stream| stream.as_stream().unwrap().dictionary().get(&Name::new("Subtype"))
+            .and_then(Object::as_name) == Some(&Name::new("Image")))
+        .count();
+    let profile_count = streams.iter()
+        .filter(|stream| stream.as_stream().unwrap().dictionary().get(&Name::new("Alternate")).is_some())
+        .count();
+    assert_eq!((image_count, profile_count), (2, 1));
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc},
+    thread,
+};
+use image::{DynamicImage, GrayImage, ImageFormat, Luma, Rgb, RgbImage};
+use lib_stream_pdf::{
+    DocumentInfo, DocumentWriter, EncodingPolicy, ImageJob, ImagePipeline, Justify, PDFError, PDFImage, PDFPage,
+};
+
+fn make_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("lib_stream_pdf_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    directory
+}
+
+fn make_good_png(directory: &Path) -> PathBuf {
+    let good_path = directory.join("good.png");
+    RgbImage::from_fn(20, 20, |x, y| Rgb([x as u8 * 10, y as u8 * 10, 0]))
+        .save_with_format(&good_path, ImageFormat::Png)
+        .unwrap();
+    good_path
+}
+
+/// Some good PNGs with a job in the middle that panics
+fn make_jobs(name: &str) -> Vec<ImageJob> {
+    let good_path = make_good_png(&make_directory(name));
+    let panicking_job = ImageJob::with_function(&good_path, Arc::new(|_| panic!("Preparing the image failed")));
+    vec![
+        ImageJob::new(&good_path, false),
+        ImageJob::new(&good_path, false),
+        panicking_job,
+        ImageJob::new(&good_path, false),
+        ImageJob::new(&good_path, false),
+    ]
+}
+
+#[test]
+fn panicking_image_gives_an_error() {
+    let pipeline = ImagePipeline::new().with_thread_count(2).with_memory_budget(4000);
+    let results: Vec<_> = pipeline.prepare(make_jobs("two_workers")).collect();
+    assert_eq!(results.len(), 5);
+    assert!(matches!(&results[2], Err(PDFError::ImageWorkerStopped(path)) if path.ends_with("good.png")));
+    // The other worker keeps going, and the memory that was taken for the image was given back
+    assert!(results[3].is_ok() && results[4].is_ok());
+}
+
+#[test]
+fn images_after_the_last_worker_stops_give_errors() {
+    let pipeline = ImagePipeline::new().with_thread_count(1);
+    let results: Vec<_> = pipeline.prepare(make_jobs("one_worker")).collect();
+    assert!(results[0].is_ok() && results[1].is_ok());
+    for result in &results[2..] {
+        assert!(matches!(result, Err(PDFError::ImageWorkerStopped(_))));
+    }
+}
+
+#[test]
+fn groups_share_the_budget_without_getting_stuck() {
+    let good_path = make_good_png(&make_directory("groups"));
+    let job_groups = (0..4).map(|_| (0..3).map(|_| ImageJob::new(&good_path, false)).collect()).collect();
+    // Even with no budget, the next image of each group gets prepared
+    let pipeline = ImagePipeline::new().with_thread_count(2).with_memory_budget(0);
+    let mut groups = pipeline.prepare_groups(job_groups);
+    assert_eq!(groups.len(), 4);
+    // A group that's dropped without being taken doesn't stop the others
+    drop(groups.remove(1));
+    let handles: Vec<_> = groups.into_iter().rev()
+        .map(|group| thread::spawn(move || group.map(|result| result.is_ok()).collect::<Vec<_>>()))
+        .collect();
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), vec![true; 3]);
+    }
+}
+
+/// Writes a page for each image
+fn write_document(pdf_images: impl Iterator<Item = PDFImage>) -> Vec<u8> {
+    let mut writer = DocumentWriter::in_memory().unwrap();
+    for pdf_image in pdf_images {
+        let image_ref = writer.add_image(pdf_image).unwrap();
+        let mut page = PDFPage::new(100.0, 100.0);
+        page.add_image(image_ref, 0.0, 100.0, Justify::Start);
+        writer.add_page(page).unwrap();
+    }
+    writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner()
+}
+
+#[test]
+fn pipeline_writes_the_same_document() {
+    let directory = make_directory("same_document");
+    let good_path = make_good_png(&directory);
+    let bilevel_path = directory.join("bilevel.png");
+    GrayImage::from_fn(64, 48, |x, y| Luma([if (x / 4 + y / 3) % 2 == 0 { 0 } else { 255 }]))
+        .save_with_format(&bilevel_path, ImageFormat::Png)
+        .unwrap();
+    let photo_path = directory.join("photo.jpg");
+    DynamicImage::ImageRgb8(RgbImage::from_fn(64, 48, |x, y| Rgb([x as u8 * 4, y as u8 * 5, (x * y) as u8])))
+        .save_with_format(&photo_path, ImageFormat::Jpeg)
+        .unwrap();
+
+    let policy = EncodingPolicy::new().with_min_psnr(30.0);
+    let jobs = vec![
+        ImageJob::new(&good_path, true),
+        ImageJob::new(&bilevel_path, true),
+        ImageJob::new(&photo_path, false),
+        ImageJob::with_policy(&good_path, policy.clone()),
+        ImageJob::new(&good_path, false),
+        ImageJob::with_policy(&bilevel_path, policy.clone()),
+        ImageJob::new(&bilevel_path, true),
+    ];
+    let sequential = vec![
+        PDFImage::from_path(&good_path, true),
+        PDFImage::from_path(&bilevel_path, true),
+        PDFImage::from_path(&photo_path, false),
+        PDFImage::from_path_with_policy(&good_path, &policy),
+        PDFImage::from_path(&good_path, false),
+        PDFImage::from_path_with_policy(&bilevel_path, &policy),
+        PDFImage::from_path(&bilevel_path, true),
+    ];
+    let sequential_pdf = write_document(sequential.into_iter().map(Result::unwrap));
+    // A small budget makes the workers wait, so the images get finished out of order
+    let pipeline = ImagePipeline::new().with_thread_count(3).with_memory_budget(10_000);
+    let pipeline_pdf = write_document(pipeline.prepare(jobs).map(Result::unwrap));
+    assert!(pipeline_pdf == sequential_pdf);
+}
@@ -0,0 +1,78 @@
+use std::{
+    io::{Cursor},
+};
+use lib_stream_pdf::{DocumentInfo, DocumentReader, DocumentWriter, Name, Object, ObjectId, PDFPage};
+
+/// A document whose only page inherits its size and rotation from the page tree root.
+/// It also has an outline, a file ID and info.
+fn make_existing() -> Vec<u8> {
+    let objects = [
+        "<</Type /Catalog /Pages 2 0 R /Outlines 4 0 R>>",
+        "<</Type /Pages /Count 1 /Kids [3 0 R] /MediaBox [0 0 200 300] /Rotate 90>>",
+        "<</Type /Page /Parent 2 0 R>>",
+        "<</Type /Outlines /Count 0>>",
+        "<</Title (Old Title) /Author (Someone) /Producer (Something)>>",
+    ];
+    let mut pdf = b"%PDF-1.7\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+    let xref_start = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f\r\n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n\r\n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(format!(
+        "trailer\n<</Size 6 /Root 1 0 R /Info 5 0 R /ID [<0102030405> (abc)]>>\nstartxref\n{}\n%%EOF", xref_start
+    ).as_bytes());
+    pdf
+}
+
+fn update(existing: Vec<u8>, document_info: DocumentInfo) -> DocumentReader {
+    let reader = DocumentReader::from_bytes(existing.clone()).unwrap();
+    let mut writer = DocumentWriter::append_to_writer(Cursor::new(existing), &reader).unwrap();
+    writer.add_page(PDFPage::new(100.0, 100.0)).unwrap();
+    let updated = writer.finish_writing(Vec::new(), document_info).unwrap().into_inner();
+    DocumentReader::from_bytes(updated).unwrap()
+}
+
+#[test]
+fn pages_keep_what_they_inherit_from_the_root() {
+    let updated = update(make_existing(), DocumentInfo::new());
+    let pages = updated.pages().unwrap();
+    assert_eq!(pages.len(), 2);
+    let media_box: Vec<f64> = pages[0].1.get(&Name::media_box()).and_then(Object::as_array).unwrap()
+        .iter()
+        .filter_map(Object::as_number)
+        .collect();
+    assert_eq!(media_box, vec![0.0, 0.0, 200.0, 300.0]);
+    assert_eq!(pages[0].1.get(&Name::new("Rotate")).and_then(Object::as_int), Some(90));
+}
+
+#[test]
+fn existing_outline_is_kept() {
+    let updated = update(make_existing(), DocumentInfo::new());
+    let catalog = updated.catalog().unwrap();
+    assert_eq!(catalog.get(&Name::outlines()).and_then(Object::as_ref_id), Some(ObjectId::new(4, 0)));
+}
+
+#[test]
+fn first_file_id_is_kept() {
+    let updated = update(make_existing(), DocumentInfo::new());
+    let document_id = updated.trailer().get(&Name::id()).and_then(Object::as_array).unwrap();
+    assert_eq!(document_id.len(), 2);
+    assert_eq!(document_id[0].as_string_bytes().unwrap(), vec![1, 2, 3, 4, 5]);
+    assert_ne!(document_id[1].as_string_bytes().unwrap(), b"abc".to_vec());
+}
+
+#[test]
+fn existing_info_is_kept_apart_from_what_gets_set() {
+    let updated = update(make_existing(), DocumentInfo::new().with_title("New Title"));
+    let document_info = updated.document_info().unwrap().unwrap();
+    let field = |name: &str| document_info.get(&Name::new(name)).and_then(Object::as_string_bytes);
+    assert_eq!(field("Title"), Some(b"New Title".to_vec()));
+    assert_eq!(field("Author"), Some(b"Someone".to_vec()));
+    assert_eq!(field("Producer"), Some(b"Something".to_vec()));
+}
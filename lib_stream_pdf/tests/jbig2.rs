@@ -0,0 +1,111 @@
+use image::{DynamicImage, GrayImage, Luma};
+use lib_stream_pdf::{
+    DocumentInfo, DocumentReader, DocumentWriter, Justify, Name, PDFError, PDFImage, PDFPage, SymbolMatching,
+};
+
+/// Gets each pixel out of the decoder (black is `true`)
+struct Pixels {
+    rows: Vec< Vec<bool> >,
+    row: Vec<bool>,
+}
+impl hayro_jbig2::Decoder for Pixels {
+    fn push_pixel(&mut self, black: bool) { self.row.push(black); }
+    fn push_pixel_chunk(&mut self, black: bool, chunk_count: u32) {
+        self.row.extend(std::iter::repeat_n(black, chunk_count as usize * 8));
+    }
+    fn next_line(&mut self) { self.rows.push(std::mem::take(&mut self.row)); }
+}
+
+/// Some "lettering" made of a few shapes that repeat, with a border around it
+fn make_lettering(width: u32, height: u32) -> GrayImage {
+    let shapes: [&[(u32, u32)]; 3] = [
+        &[(0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (1, 4), (2, 4), (3, 4)],
+        &[(1, 0), (0, 1), (2, 1), (0, 2), (1, 2), (2, 2), (0, 3), (2, 3), (0, 4), (2, 4)],
+        &[(0, 0), (1, 0), (2, 0), (1, 1), (1, 2), (1, 3), (0, 4), (1, 4), (2, 4), (4, 2)],
+    ];
+    let mut image = GrayImage::from_pixel(width, height, Luma([255]));
+    for (i, y) in (4..height - 8).step_by(9).enumerate() {
+        for (j, x) in (4..width - 8).step_by(7).enumerate() {
+            for &(shape_x, shape_y) in shapes[(i * 5 + j) % shapes.len()] {
+                image.put_pixel(x + shape_x, y + shape_y, Luma([0]));
+            }
+        }
+    }
+    for x in 0..width {
+        image.put_pixel(x, 0, Luma([0]));
+        image.put_pixel(x, height - 1, Luma([40]));
+    }
+    for y in 0..height {
+        image.put_pixel(0, y, Luma([0]));
+    }
+    image
+}
+
+/// Writes the image into a document, reads the JBIG2 stream back out and decodes it
+fn encode_and_decode(image: &GrayImage, symbol_matching: SymbolMatching) -> Vec< Vec<bool> > {
+    let pdf_image = PDFImage::from_image_jbig2(DynamicImage::ImageLuma8(image.clone()), 128, symbol_matching)
+        .unwrap();
+    let mut writer = DocumentWriter::in_memory().unwrap();
+    let image_ref = writer.add_image(pdf_image).unwrap();
+    let mut page = PDFPage::new(100.0, 100.0);
+    page.add_image(image_ref, 0.0, 100.0, Justify::Start);
+    writer.add_page(page).unwrap();
+    let pdf = writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner();
+
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    let pages = reader.pages().unwrap();
+    let resources = reader.resolve_dictionary(pages[0].1.get(&Name::new("Resources")).unwrap()).unwrap();
+    let xobjects = reader.resolve_dictionary(resources.get(&Name::new("XObject")).unwrap()).unwrap();
+    let (_, image_object) = xobjects.iter().next().unwrap();
+    let image_object = reader.resolve(image_object).unwrap();
+    let image_stream = image_object.as_stream().unwrap();
+    assert_eq!(image_stream.dictionary().get(&Name::new("Filter")).and_then(|filter| filter.as_name()),
+        Some(&Name::new("JBIG2Decode")));
+
+    let mut pixels = Pixels { rows: Vec::new(), row: Vec::new() };
+    hayro_jbig2::Image::new_embedded(image_stream.contents(), None).unwrap()
+        .decode(&mut pixels)
+        .unwrap();
+    pixels.rows
+}
+
+/// How many pixels are different from the image (with the same threshold as the encoder)
+fn count_differences(image: &GrayImage, rows: &[Vec<bool>]) -> usize {
+    assert_eq!(rows.len(), image.height() as usize);
+    image.enumerate_pixels()
+        .filter(|&(x, y, pixel)| rows[y as usize][x as usize] != (pixel[0] < 128))
+        .count()
+}
+
+#[test]
+fn generic_region_is_lossless() {
+    for (width, height) in [(61, 40), (300, 211)] {
+        let image = make_lettering(width, height);
+        let rows = encode_and_decode(&image, SymbolMatching::Off);
+        assert_eq!(count_differences(&image, &rows), 0);
+    }
+}
+
+#[test]
+fn exact_symbols_are_lossless() {
+    let image = make_lettering(300, 211);
+    let rows = encode_and_decode(&image, SymbolMatching::Exact);
+    assert_eq!(count_differences(&image, &rows), 0);
+}
+
+#[test]
+fn similar_symbols_stay_close() {
+    let image = make_lettering(300, 211);
+    let rows = encode_and_decode(&image, SymbolMatching::Similar(0.1));
+    let pixel_count = (image.width() * image.height()) as usize;
+    assert!(count_differences(&image, &rows) * 100 < pixel_count);
+}
+
+#[test]
+fn similar_needs_a_fraction() {
+    for max_difference in [f64::NAN, -0.1, 1.5, f64::INFINITY] {
+        let image = DynamicImage::ImageLuma8(make_lettering(61, 40));
+        let result = PDFImage::from_image_jbig2(image, 128, SymbolMatching::Similar(max_difference));
+        assert!(matches!(result, Err(PDFError::BadSymbolMatching(_))), "{}", max_difference);
+    }
+}
@@ -0,0 +1,41 @@
+use jpeg_encoder::{ColorType, Encoder};
+use lib_stream_pdf::{ImageEncoding, PDFImage};
+
+fn make_jpeg(progressive: bool) -> Vec<u8> {
+    let (width, height) = (32, 24);
+    let pixels: Vec<u8> = (0..width * height).flat_map(|i| [(i % 256) as u8, (i / 3 % 256) as u8, 90]).collect();
+    let mut jpeg_bytes = Vec::new();
+    let mut encoder = Encoder::new(&mut jpeg_bytes, 90);
+    encoder.set_progressive(progressive);
+    encoder.encode(&pixels, width as u16, height as u16, ColorType::Rgb).unwrap();
+    jpeg_bytes
+}
+
+/// Changes the start of frame marker, without changing anything else
+fn with_frame_marker(mut jpeg_bytes: Vec<u8>, marker: u8) -> Vec<u8> {
+    let frame_start = jpeg_bytes.windows(2)
+        .position(|window| window == [0xFF, 0xC0] || window == [0xFF, 0xC2])
+        .unwrap();
+    jpeg_bytes[frame_start + 1] = marker;
+    jpeg_bytes
+}
+
+#[test]
+fn huffman_jpegs_pass_through() {
+    for jpeg_bytes in [make_jpeg(false), with_frame_marker(make_jpeg(false), 0xC1), make_jpeg(true)] {
+        let pdf_image = PDFImage::from_bytes(jpeg_bytes, false).unwrap();
+        assert_eq!(pdf_image.encoding(), ImageEncoding::Passthrough);
+    }
+}
+
+#[test]
+fn other_jpegs_dont_pass_through() {
+    // Lossless, hierarchical and arithmetic-coded
+    for marker in [0xC3, 0xC5, 0xC7, 0xC9, 0xCA, 0xCB, 0xCD, 0xCF] {
+        let jpeg_bytes = with_frame_marker(make_jpeg(false), marker);
+        // The image data doesn't really match the marker, so it can fail to decode
+        if let Ok(pdf_image) = PDFImage::from_bytes(jpeg_bytes, false) {
+            assert_ne!(pdf_image.encoding(), ImageEncoding::Passthrough, "0x{:02X}", marker);
+        }
+    }
+}
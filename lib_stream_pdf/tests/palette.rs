@@ -0,0 +1,65 @@
+use image::{DynamicImage, Rgb, RgbImage};
+use lib_stream_pdf::{
+    CompressionOptions, DocumentInfo, DocumentReader, DocumentWriter, FlateLevel, ImageEncoding, Justify, Name, Object,
+    PDFImage, PDFPage,
+};
+
+/// Writes the image into a document and reads back the colours of its /Indexed colour space
+fn palette_colours(pdf_image: PDFImage) -> Vec< Vec<u8> > {
+    assert_eq!(pdf_image.encoding(), ImageEncoding::Indexed);
+    let mut writer = DocumentWriter::in_memory().unwrap();
+    let image_ref = writer.add_image(pdf_image).unwrap();
+    let mut page = PDFPage::new(100.0, 100.0);
+    page.add_image(image_ref, 0.0, 100.0, Justify::Start);
+    writer.add_page(page).unwrap();
+    let pdf = writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner();
+
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    let pages = reader.pages().unwrap();
+    let resources = reader.resolve_dictionary(pages[0].1.get(&Name::new("Resources")).unwrap()).unwrap();
+    let xobjects = reader.resolve_dictionary(resources.get(&Name::new("XObject")).unwrap()).unwrap();
+    let (_, image) = xobjects.iter().next().unwrap();
+    let image = reader.resolve_dictionary(image).unwrap();
+    let colour_space = reader.resolve(image.get(&Name::new("ColorSpace")).unwrap()).unwrap();
+    match colour_space.as_array().unwrap() {
+        [_, _, highest_index, Object::HexStr(palette)] => {
+            let colours: Vec< Vec<u8> > = palette.chunks(3).map(<[u8]>::to_vec).collect();
+            assert_eq!(highest_index.as_int(), Some(colours.len() as i64 - 1));
+            colours
+        },
+        other => panic!("Expected an /Indexed colour space, got {:?}", other),
+    }
+}
+
+#[test]
+fn close_colours_are_merged() {
+    let image = RgbImage::from_fn(20, 20, |x, _| if x < 12 { Rgb([100, 100, 100]) } else { Rgb([104, 98, 100]) });
+    let pdf_image = PDFImage::from_image_quantised(DynamicImage::ImageRgb8(image), 10).unwrap();
+    assert_eq!(palette_colours(pdf_image), vec![vec![100, 100, 100]]);
+}
+
+#[test]
+fn colours_past_the_tolerance_stay_lossless() {
+    // The last colour is within the tolerance of the first one, but closer overall to the second
+    // one, which is too far off in its green. So every colour has to be kept.
+    let colours = [Rgb([100, 100, 100]), Rgb([100, 121, 110]), Rgb([100, 110, 110])];
+    let image = RgbImage::from_fn(20, 20, |x, _| colours[match x { 0..=11 => 0, 12..=17 => 1, _ => 2 }]);
+    let pdf_image = PDFImage::from_image_quantised(DynamicImage::ImageRgb8(image), 10).unwrap();
+    assert_eq!(palette_colours(pdf_image).len(), 3);
+}
+
+#[test]
+fn quantised_images_use_the_compression() {
+    let image = RgbImage::from_fn(200, 200, |x, _| if x % 40 < 20 { Rgb([10, 20, 30]) } else { Rgb([200, 100, 0]) });
+    let stream_size = |flate_level| {
+        let compression = CompressionOptions::new().with_flate_level(flate_level);
+        let pdf_image = PDFImage::from_image_quantised_with_compression(DynamicImage::ImageRgb8(image.clone()), 10,
+            &compression).unwrap();
+        assert_eq!(pdf_image.encoding(), ImageEncoding::Indexed);
+        let mut writer = DocumentWriter::in_memory().unwrap();
+        writer.add_image(pdf_image).unwrap();
+        writer.finish_writing(Vec::new(), DocumentInfo::new()).unwrap().into_inner().len()
+    };
+    // Level 0 stores the samples (a bit for each pixel) without compressing them
+    assert!(stream_size(FlateLevel::Zlib(0)) > stream_size(FlateLevel::Zlib(9)) + 200 * 200 / 16);
+}
@@ -0,0 +1,119 @@
+use lib_stream_pdf::{DocumentWriter, FontRef, Justify, PDFFont, Paragraph};
+
+/// A TrueType font with only the missing glyph, which every character uses. It's 1 em wide (and
+/// tall, since there aren't any vertical metrics), so every character takes up the font size.
+fn make_square_font() -> PDFFont {
+    let mut head = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0x5F, 0x0F, 0x3C, 0xF5, 0, 0];
+    head.extend_from_slice(&1000u16.to_be_bytes());
+    head.extend_from_slice(&[0; 16]);
+    for bound in [0i16, -200, 1000, 800] {
+        head.extend_from_slice(&bound.to_be_bytes());
+    }
+    // The style, smallest size, direction, short loca offsets and glyph format
+    head.extend_from_slice(&[0, 0, 0, 8, 0, 2, 0, 0, 0, 0]);
+    let mut hhea = vec![0, 1, 0, 0];
+    for value in [800i16, -200, 0, 1000, 0, 0, 1000, 1, 0, 0, 0, 0, 0, 0, 0, 1] {
+        hhea.extend_from_slice(&value.to_be_bytes());
+    }
+    let maxp = vec![0, 0, 0x50, 0, 0, 1];
+    let hmtx = vec![0x03, 0xE8, 0, 0];
+    let loca = vec![0, 0, 0, 0];
+    let tables: [(&[u8; 4], Vec<u8>); 6] = [
+        (b"glyf", Vec::new()), (b"head", head), (b"hhea", hhea), (b"hmtx", hmtx), (b"loca", loca), (b"maxp", maxp),
+    ];
+
+    let mut font_bytes = vec![0, 1, 0, 0, 0, tables.len() as u8, 0, 0, 0, 0, 0, 0];
+    let mut offset = font_bytes.len() + tables.len() * 16;
+    for (tag, table) in &tables {
+        font_bytes.extend_from_slice(*tag);
+        font_bytes.extend_from_slice(&[0; 4]);
+        font_bytes.extend_from_slice(&(offset as u32).to_be_bytes());
+        font_bytes.extend_from_slice(&(table.len() as u32).to_be_bytes());
+        offset += table.len().next_multiple_of(4);
+    }
+    // Each table starts on 4 bytes
+    for (_, table) in tables {
+        font_bytes.extend(table);
+        font_bytes.resize(font_bytes.len().next_multiple_of(4), 0);
+    }
+    PDFFont::from_bytes(font_bytes, 0).unwrap()
+}
+
+fn make_font_ref() -> FontRef {
+    DocumentWriter::in_memory().unwrap().add_font(make_square_font())
+}
+
+/// The text of each line, with every character 10 wide
+fn line_texts(paragraph: &Paragraph, characters_per_line: usize) -> Vec<String> {
+    paragraph.layout_lines(characters_per_line as f64 * 10.0).iter()
+        .map(|line| line.segments.iter().map(|segment| segment.text.as_str()).collect())
+        .collect()
+}
+
+fn make_paragraph(text: &str, is_vertical: bool) -> Paragraph {
+    let mut paragraph = Paragraph::new(Justify::Start).with_vertical(is_vertical);
+    paragraph.add_text(&make_font_ref(), 10.0, text);
+    paragraph
+}
+
+#[test]
+fn lines_break_at_spaces_and_between_wide_characters() {
+    assert_eq!(line_texts(&make_paragraph("hello world", false), 5), vec!["hello", "world"]);
+    assert_eq!(line_texts(&make_paragraph("あいうえおかき", false), 5), vec!["あいうえお", "かき"]);
+}
+
+#[test]
+fn punctuation_never_starts_a_line() {
+    for is_vertical in [false, true] {
+        let paragraph = make_paragraph("あいうえお。かき", is_vertical);
+        assert_eq!(line_texts(&paragraph, 5), vec!["あいうえ", "お。かき"], "{}", is_vertical);
+        let paragraph = make_paragraph("あいうえおっと", is_vertical);
+        assert_eq!(line_texts(&paragraph, 5), vec!["あいうえ", "おっと"], "{}", is_vertical);
+    }
+}
+
+#[test]
+fn opening_brackets_never_end_a_line() {
+    for is_vertical in [false, true] {
+        let paragraph = make_paragraph("あいうえ「おか」", is_vertical);
+        assert_eq!(line_texts(&paragraph, 5), vec!["あいうえ", "「おか」"], "{}", is_vertical);
+    }
+}
+
+#[test]
+fn ruby_groups_never_split() {
+    let font_ref = make_font_ref();
+    for is_vertical in [false, true] {
+        let mut paragraph = Paragraph::new(Justify::Start).with_vertical(is_vertical);
+        paragraph.add_text(&font_ref, 10.0, "あいう");
+        paragraph.add_ruby_text(&font_ref, 10.0, "漢字", "かんじ");
+        paragraph.add_text(&font_ref, 10.0, "です");
+        assert_eq!(line_texts(&paragraph, 4), vec!["あいう", "漢字です"], "{}", is_vertical);
+    }
+}
+
+#[test]
+fn ruby_longer_than_a_line_runs_past_it() {
+    let font_ref = make_font_ref();
+    let mut paragraph = Paragraph::new(Justify::Start);
+    paragraph.add_ruby_text(&font_ref, 10.0, "一二三四五六", "いちにさんしごろく");
+    paragraph.add_text(&font_ref, 10.0, "あ");
+    assert_eq!(line_texts(&paragraph, 4), vec!["一二三四五六", "あ"]);
+}
+
+#[test]
+fn long_ruby_spreads_out_its_base_text() {
+    let font_ref = make_font_ref();
+    for is_vertical in [false, true] {
+        let mut paragraph = Paragraph::new(Justify::Start).with_vertical(is_vertical);
+        // The ruby is 4 characters at half the size, so it's twice as long as the base text
+        paragraph.add_ruby_text(&font_ref, 10.0, "字", "かんじじ");
+        paragraph.add_ruby_text(&font_ref, 10.0, "漢字", "かん");
+        assert_eq!(paragraph.ruby_padding(0), 10.0);
+        assert_eq!(paragraph.ruby_padding(1), 0.0);
+        let lines = paragraph.layout_lines(100.0);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].length, 40.0);
+        assert_eq!(lines[0].ruby_size, 5.0);
+    }
+}
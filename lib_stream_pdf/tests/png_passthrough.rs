@@ -0,0 +1,56 @@
+use std::{
+    io::{Cursor},
+};
+use image::{
+    DynamicImage, GrayImage, ImageEncoder, ImageFormat, Luma, Rgb, RgbImage, RgbaImage,
+    codecs::png::{CompressionType, FilterType, PngEncoder},
+};
+use lib_stream_pdf::{ImageEncoding, PDFImage};
+
+fn to_png(image: DynamicImage) -> Vec<u8> {
+    let mut png_bytes = Cursor::new(Vec::new());
+    image.write_to(&mut png_bytes, ImageFormat::Png).unwrap();
+    png_bytes.into_inner()
+}
+
+/// Like a PNG that was made as small as it can be
+fn to_best_png(image: DynamicImage) -> Vec<u8> {
+    let mut png_bytes = Vec::new();
+    PngEncoder::new_with_quality(&mut png_bytes, CompressionType::Best, FilterType::Adaptive)
+        .write_image(image.as_bytes(), image.width(), image.height(), image.color())
+        .unwrap();
+    png_bytes
+}
+
+#[test]
+fn gray_and_rgb_pngs_pass_through() {
+    let gray = GrayImage::from_fn(40, 30, |x, y| Luma([(x * 6 + y * 2) as u8]));
+    let rgb = RgbImage::from_fn(40, 30, |x, y| Rgb([x as u8 * 6, y as u8 * 8, 100]));
+    for image in [DynamicImage::ImageLuma8(gray), DynamicImage::ImageRgb8(rgb)] {
+        let pdf_image = PDFImage::from_bytes(to_best_png(image), true).unwrap();
+        assert_eq!(pdf_image.encoding(), ImageEncoding::Passthrough);
+    }
+}
+
+#[test]
+fn pngs_that_can_be_smaller_dont_pass_through() {
+    let bilevel = GrayImage::from_fn(40, 30, |x, y| Luma([if (x + y) % 2 == 0 { 0 } else { 255 }]));
+    let pdf_image = PDFImage::from_bytes(to_png(DynamicImage::ImageLuma8(bilevel)), true).unwrap();
+    assert_eq!(pdf_image.encoding(), ImageEncoding::Bilevel);
+
+    let colours = [Rgb([200, 10, 10]), Rgb([10, 200, 10]), Rgb([10, 10, 200])];
+    let few_colours = RgbImage::from_fn(40, 30, |x, y| colours[((x / 3 + y) % 3) as usize]);
+    let pdf_image = PDFImage::from_bytes(to_png(DynamicImage::ImageRgb8(few_colours)), true).unwrap();
+    assert_eq!(pdf_image.encoding(), ImageEncoding::Indexed);
+}
+
+#[test]
+fn other_pngs_get_encoded() {
+    let rgb = RgbImage::from_fn(40, 30, |x, y| Rgb([x as u8 * 6, y as u8 * 8, 100]));
+    let pdf_image = PDFImage::from_bytes(to_png(DynamicImage::ImageRgb8(rgb)), false).unwrap();
+    assert_eq!(pdf_image.encoding(), ImageEncoding::Jpeg);
+
+    let rgba = RgbaImage::from_fn(40, 30, |x, y| image::Rgba([x as u8 * 6, y as u8 * 8, 100, 255]));
+    let pdf_image = PDFImage::from_bytes(to_png(DynamicImage::ImageRgba8(rgba)), true).unwrap();
+    assert_ne!(pdf_image.encoding(), ImageEncoding::Passthrough);
+}
@@ -0,0 +1,227 @@
+use std::{
+    io::{Write},
+};
+use flate2::{Compression, write::{ZlibEncoder}};
+use image::{DynamicImage, RgbImage};
+use lib_stream_pdf::{
+    Dictionary, DocumentInfo, DocumentReader, DocumentWriter, Justify, Name, Object, ObjectId, PDFError, PDFImage,
+    PDFPage, Stream, XrefMode,
+};
+
+/// Writes a 2 page document with a title and an image that only has a few colours (so it gets a palette)
+fn write_document(xref_mode: XrefMode) -> Vec<u8> {
+    let mut writer = DocumentWriter::in_memory().unwrap().with_xref_mode(xref_mode);
+    let image = RgbImage::from_fn(16, 16, |x, _| if x < 8 { image::Rgb([200, 30, 30]) } else { image::Rgb([30, 30, 200]) });
+    let image_ref = writer.add_image(PDFImage::from_image(DynamicImage::ImageRgb8(image), true).unwrap()).unwrap();
+    let mut first_page = PDFPage::new(400.0, 600.0);
+    first_page.add_image(image_ref, 0.0, 100.0, Justify::Start);
+    writer.add_page(first_page).unwrap();
+    writer.add_page(PDFPage::new(300.0, 500.0)).unwrap();
+    writer.finish_writing(Vec::new(), DocumentInfo::new().with_title("Round Trip"))
+        .unwrap()
+        .into_inner()
+}
+
+/// Puts the objects into a PDF with an xref table, so that the offsets are right
+fn make_pdf(objects: &[&str], root: u32) -> Vec<u8> {
+    let mut pdf = b"%PDF-1.7\n".to_vec();
+    let mut offsets = Vec::new();
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+    }
+    let xref_start = pdf.len();
+    pdf.extend_from_slice(format!("xref\n0 {}\n0000000000 65535 f\r\n", objects.len() + 1).as_bytes());
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n\r\n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(format!(
+        "trailer\n<</Size {} /Root {} 0 R>>\nstartxref\n{}\n%%EOF", objects.len() + 1, root, xref_start
+    ).as_bytes());
+    pdf
+}
+
+fn check_round_trip(xref_mode: XrefMode, uses_xref_streams: bool) {
+    let reader = DocumentReader::from_bytes(write_document(xref_mode)).unwrap();
+    assert_eq!(reader.version(), "1.7");
+    assert_eq!(reader.uses_xref_streams(), uses_xref_streams);
+
+    let info = reader.document_info().unwrap().unwrap();
+    assert_eq!(info.get(&Name::new("Title")).and_then(Object::as_str), Some("Round Trip"));
+
+    let pages = reader.pages().unwrap();
+    assert_eq!(pages.len(), 2);
+    let media_box: Vec<f64> = pages[1].1.get(&Name::new("MediaBox")).and_then(Object::as_array).unwrap()
+        .iter()
+        .filter_map(Object::as_number)
+        .collect();
+    assert_eq!(media_box, vec![0.0, 0.0, 300.0, 500.0]);
+
+    // The palette is binary, so it has to come back as the same bytes
+    let resources = reader.resolve_dictionary(pages[0].1.get(&Name::new("Resources")).unwrap()).unwrap();
+    let xobjects = reader.resolve_dictionary(resources.get(&Name::new("XObject")).unwrap()).unwrap();
+    let (_, image) = xobjects.iter().next().unwrap();
+    let image = reader.resolve_dictionary(image).unwrap();
+    let colour_space = reader.resolve(image.get(&Name::new("ColorSpace")).unwrap()).unwrap();
+    match colour_space.as_array().unwrap() {
+        [indexed, _, _, Object::HexStr(palette)] => {
+            assert_eq!(indexed.as_name(), Some(&Name::new("Indexed")));
+            let mut colours: Vec<&[u8]> = palette.chunks(3).collect();
+            colours.sort();
+            assert_eq!(colours, vec![&[30, 30, 200][..], &[200, 30, 30][..]]);
+        },
+        other => panic!("Expected an /Indexed colour space, got {:?}", other),
+    }
+}
+
+#[test]
+fn round_trip_xref_table() {
+    check_round_trip(XrefMode::Table, false);
+}
+
+#[test]
+fn round_trip_xref_stream() {
+    check_round_trip(XrefMode::Stream, true);
+}
+
+#[test]
+fn truncated_documents_give_errors() {
+    for xref_mode in [XrefMode::Table, XrefMode::Stream] {
+        let pdf = write_document(xref_mode);
+        for length in (0..pdf.len()).step_by(7) {
+            // Anything is fine, as long as it doesn't panic
+            if let Ok(reader) = DocumentReader::from_bytes(pdf[..length].to_vec()) {
+                let _ = reader.pages();
+            }
+        }
+    }
+}
+
+#[test]
+fn missing_header_and_startxref() {
+    assert!(matches!(DocumentReader::from_bytes(Vec::new()), Err(PDFError::MalformedPDF(_))));
+    assert!(matches!(DocumentReader::from_bytes(b"%PDF-1.7\n1 0 obj\nnull\nendobj\n".to_vec()),
+        Err(PDFError::MalformedPDF(_))));
+}
+
+#[test]
+fn bad_stream_lengths_use_endstream() {
+    for length in ["-5", "9223372036854775807", "1000", "3.5"] {
+        let pdf = make_pdf(&[
+            "<</Type /Catalog>>",
+            &format!("<</Length {}>>\nstream\nabcdef\nendstream", length),
+        ], 1);
+        let reader = DocumentReader::from_bytes(pdf).unwrap();
+        let stream = reader.get_object(ObjectId::new(2, 0)).unwrap();
+        assert_eq!(stream.as_stream().unwrap().contents(), b"abcdef");
+    }
+}
+
+#[test]
+fn stream_length_that_needs_itself() {
+    let pdf = make_pdf(&[
+        "<</Type /Catalog>>",
+        "<</Length 2 0 R>>\nstream\nabcdef\nendstream",
+    ], 1);
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    assert!(matches!(reader.get_object(ObjectId::new(2, 0)), Err(PDFError::MalformedPDF(_))));
+}
+
+#[test]
+fn object_stream_inside_itself() {
+    // Object 2 claims to be the first object of object stream 2
+    let mut pdf = b"%PDF-1.7\n".to_vec();
+    let xref_start = pdf.len();
+    let entries = [
+        [0, 0, 0, 0, 0, 0xFF, 0xFF],
+        [1, 0, 0, 0, xref_start as u8, 0, 0],
+        [2, 0, 0, 0, 2, 0, 0],
+    ];
+    pdf.extend_from_slice(b"1 0 obj\n<</Type /XRef /Size 3 /W [1 4 2] /Root 2 0 R /Length 21>>\nstream\n");
+    pdf.extend(entries.iter().flatten());
+    pdf.extend_from_slice(format!("\nendstream\nendobj\nstartxref\n{}\n%%EOF", xref_start).as_bytes());
+
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    assert!(matches!(reader.get_object(ObjectId::new(2, 0)), Err(PDFError::MalformedPDF(_))));
+    assert!(reader.catalog().is_err());
+}
+
+#[test]
+fn garbage_objects_give_errors() {
+    for object in ["<</Type /Catalog", "[1 2", "(unterminated", "<0G>", "<<1 2>>", "endobj"] {
+        let reader = DocumentReader::from_bytes(make_pdf(&[object], 1)).unwrap();
+        assert!(matches!(reader.catalog(), Err(PDFError::MalformedPDF(_))), "{}", object);
+    }
+}
+
+#[test]
+fn deeply_nested_objects_give_errors() {
+    let object = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+    let reader = DocumentReader::from_bytes(make_pdf(&[&object], 1)).unwrap();
+    assert!(matches!(reader.catalog(), Err(PDFError::MalformedPDF(_))));
+    let object = "<</A ".repeat(100_000);
+    let reader = DocumentReader::from_bytes(make_pdf(&[&object], 1)).unwrap();
+    assert!(matches!(reader.catalog(), Err(PDFError::MalformedPDF(_))));
+}
+
+#[test]
+fn bad_xref_subsections_give_errors() {
+    for subsection in ["9223372036854775807 10", "-5 2", "4294967290 100", "0 -1"] {
+        let mut pdf = b"%PDF-1.7\n".to_vec();
+        let xref_start = pdf.len();
+        pdf.extend_from_slice(format!(
+            "xref\n{}\n0000000000 65535 f\r\ntrailer\n<</Size 1>>\nstartxref\n{}\n%%EOF", subsection, xref_start
+        ).as_bytes());
+        assert!(matches!(DocumentReader::from_bytes(pdf), Err(PDFError::MalformedPDF(_))), "{}", subsection);
+    }
+}
+
+#[test]
+fn bad_object_stream_offsets_give_errors() {
+    for (first, offset) in [("-100", "0"), ("0", "-100"), ("9223372036854775807", "1")] {
+        // Object 3 is the first object of object stream 2
+        let mut pdf = b"%PDF-1.7\n".to_vec();
+        let object_stream_start = pdf.len();
+        let contents = format!("3 {} null", offset);
+        pdf.extend_from_slice(format!(
+            "2 0 obj\n<</Type /ObjStm /N 1 /First {} /Length {}>>\nstream\n{}\nendstream\nendobj\n",
+            first, contents.len(), contents
+        ).as_bytes());
+        let xref_start = pdf.len();
+        let entries = [
+            [0, 0, 0, 0, 0, 0xFF, 0xFF],
+            [1, 0, 0, 0, xref_start as u8, 0, 0],
+            [1, 0, 0, 0, object_stream_start as u8, 0, 0],
+            [2, 0, 0, 0, 2, 0, 0],
+        ];
+        pdf.extend_from_slice(b"1 0 obj\n<</Type /XRef /Size 4 /W [1 4 2] /Root 3 0 R /Length 28>>\nstream\n");
+        pdf.extend(entries.iter().flatten());
+        pdf.extend_from_slice(format!("\nendstream\nendobj\nstartxref\n{}\n%%EOF", xref_start).as_bytes());
+
+        let reader = DocumentReader::from_bytes(pdf).unwrap();
+        assert!(matches!(reader.get_object(ObjectId::new(3, 0)), Err(PDFError::MalformedPDF(_))), "{} {}", first, offset);
+    }
+}
+
+#[test]
+fn bad_predictor_parameters_give_errors() {
+    let reader = DocumentReader::from_bytes(make_pdf(&["<</Type /Catalog>>"], 1)).unwrap();
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&[0, 1, 2, 3, 0, 4, 5, 6]).unwrap();
+    let contents = encoder.finish().unwrap();
+    let bad_parameters = [
+        ("Columns", -1), ("Columns", 0), ("Colors", -3), ("Colors", i64::MAX), ("BitsPerComponent", 3),
+        ("Columns", i64::MAX),
+    ];
+    for (name, value) in bad_parameters {
+        let mut parameters = Dictionary::new();
+        parameters.insert(Name::new("Predictor"), Object::Int(12));
+        parameters.insert(Name::new("Columns"), Object::Int(3));
+        parameters.insert(Name::new(name), Object::Int(value));
+        let mut dictionary = Dictionary::new();
+        dictionary.insert(Name::new("Filter"), Object::Name(Name::new("FlateDecode")));
+        dictionary.insert(Name::new("DecodeParms"), Object::Dictionary(parameters));
+        let stream = Stream::new(dictionary, contents.clone());
+        assert!(matches!(reader.decode_stream(&stream), Err(PDFError::MalformedPDF(_))), "{} {}", name, value);
+    }
+}
@@ -0,0 +1,95 @@
+use lib_stream_pdf::{PDFError, TextLayer};
+
+/// (left, top, right, bottom)
+type BoundingBox = (f64, f64, f64, f64);
+
+/// The text of each word with its bounding box
+fn read_words(text_layer: &TextLayer) -> Vec<(&str, BoundingBox)> {
+    text_layer.words().iter().map(|word| (word.text(), word.bounding_box())).collect()
+}
+
+#[test]
+fn hocr_words_are_fractions_of_the_page() {
+    let hocr = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml"><body>
+  <div class="ocr_page" id="page_1" title="image &quot;page.png&quot;; bbox 0 0 1000 2000; ppageno 0">
+    <span class="ocr_line" title="bbox 100 200 900 300">
+      <span class="ocrx_word" title="bbox 100 200 300 300; x_wconf 95">Hello</span>
+      <span class="ocrx_word" title="bbox 400 200 900 300; x_wconf 91">R&amp;D</span>
+      <span class="ocrx_word" title="bbox 950 200 990 300">   </span>
+    </span>
+  </div>
+</body></html>"#;
+    let text_layer = TextLayer::from_hocr(hocr).unwrap();
+    assert_eq!(read_words(&text_layer), vec![
+        ("Hello", (0.1, 0.1, 0.3, 0.15)),
+        ("R&D", (0.4, 0.1, 0.9, 0.15)),
+    ]);
+}
+
+#[test]
+fn hocr_words_keep_the_text_of_nested_spans() {
+    let hocr = r#"<div class='ocr_page' title='bbox 0 0 100 100'>
+  <span class='ocrx_word' title='bbox 10 10 50 20'><strong>Bo<em>ld</em></strong>er</span>
+  <span class='ocrx_word' title='bbox 60 10 90 20'><span class='ocr_glyph'>a</span>b</span>
+</div>"#;
+    let text_layer = TextLayer::from_hocr(hocr).unwrap();
+    let texts: Vec<&str> = text_layer.words().iter().map(|word| word.text()).collect();
+    assert_eq!(texts, vec!["Bolder", "ab"]);
+}
+
+#[test]
+fn hocr_can_have_unclosed_html_tags() {
+    let hocr = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>OCR</title></head>
+<body><p>
+<div class='ocr_page' title='bbox 0 0 200 100'>
+  <span class='ocrx_word' title='bbox 0 0 50 10'>One<br>
+  <span class='ocrx_word' title='bbox 60 0 100 10'>Two<b>!</span>
+  <span class='ocrx_word' title='bbox 110 0 150 10'>Three"#;
+    let text_layer = TextLayer::from_hocr(hocr).unwrap();
+    let texts: Vec<&str> = text_layer.words().iter().map(|word| word.text()).collect();
+    assert_eq!(texts, vec!["One", "Two!", "Three"]);
+    assert_eq!(text_layer.words()[2].bounding_box(), (0.55, 0.0, 0.75, 0.1));
+}
+
+#[test]
+fn alto_strings_are_fractions_of_the_page() {
+    let alto = r#"<?xml version="1.0" encoding="UTF-8"?>
+<alto xmlns="http://www.loc.gov/standards/alto/ns-v4#">
+  <Layout><Page ID="p1" WIDTH="1000" HEIGHT="2000" PHYSICAL_IMG_NR="1">
+    <PrintSpace><TextBlock><TextLine>
+      <String CONTENT="縦書き" HPOS="800" VPOS="100" WIDTH="100" HEIGHT="600"/><SP/>
+      <String CONTENT="Word" HPOS="100" VPOS="1000" WIDTH="200" HEIGHT="100"></String>
+      <String HPOS="0" VPOS="0" WIDTH="10" HEIGHT="10"/>
+    </TextLine></TextBlock></PrintSpace>
+  </Page></Layout>
+</alto>"#;
+    let text_layer = TextLayer::from_alto(alto).unwrap();
+    assert_eq!(read_words(&text_layer), vec![
+        ("縦書き", (0.8, 0.05, 0.9, 0.35)),
+        ("Word", (0.1, 0.5, 0.3, 0.55)),
+    ]);
+    assert!(text_layer.words()[0].is_vertical());
+    assert!(!text_layer.words()[1].is_vertical());
+}
+
+#[test]
+fn page_sizes_have_to_be_positive_and_finite() {
+    let hocr_sizes = ["0 0 0 100", "0 0 100 0", "0 0 1e400 100"];
+    for size in hocr_sizes {
+        let hocr = format!("<div class='ocr_page' title='bbox {}'>\
+            <span class='ocrx_word' title='bbox 0 0 1 1'>Word</span></div>", size);
+        assert!(matches!(TextLayer::from_hocr(&hocr), Err(PDFError::BadTextLayer(_))), "{}", size);
+    }
+    let alto_sizes = [("0", "100"), ("100", "-5"), ("inf", "100"), ("NaN", "100")];
+    for (width, height) in alto_sizes {
+        let alto = format!("<alto><Layout><Page WIDTH='{}' HEIGHT='{}'>\
+            <String CONTENT='Word' HPOS='0' VPOS='0' WIDTH='1' HEIGHT='1'/></Page></Layout></alto>", width, height);
+        assert!(matches!(TextLayer::from_alto(&alto), Err(PDFError::BadTextLayer(_))), "{} {}", width, height);
+    }
+    // Without a size, there's nothing to make the fractions from
+    assert!(matches!(TextLayer::from_hocr("<span class='ocrx_word' title='bbox 0 0 1 1'>Word</span>"),
+        Err(PDFError::BadTextLayer(_))));
+    assert!(matches!(TextLayer::from_alto("<alto><Page WIDTH='100'></Page></alto>"), Err(PDFError::BadTextLayer(_))));
+}
@@ -0,0 +1,73 @@
+use image::{DynamicImage, Rgb, RgbImage};
+use lib_stream_pdf::{
+    DocumentInfo, DocumentReader, DocumentWriter, Justify, Name, Object, PDFImage, PDFPage, XrefMode,
+};
+
+/// Noise that doesn't compress, so that the objects after it need offsets of 4 bytes
+fn make_noise(width: u32, height: u32, seed: u32) -> DynamicImage {
+    let mut state = seed;
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let [r, g, b, _] = state.to_le_bytes();
+        Rgb([r, g, b])
+    }))
+}
+
+#[test]
+fn large_document_round_trips() {
+    let (image_count, small_page_count) = (3, 250);
+    let mut writer = DocumentWriter::in_memory().unwrap().with_xref_mode(XrefMode::Stream);
+    for seed in 1..=image_count {
+        let image_ref = writer.add_image(PDFImage::from_image(make_noise(1500, 1300, seed), true).unwrap()).unwrap();
+        let mut page = PDFPage::new(1500.0, 1300.0);
+        page.add_image(image_ref, 0.0, 1.0, Justify::Start);
+        writer.add_page(page).unwrap();
+    }
+    // Enough pages for a few object streams
+    for i in 0..small_page_count {
+        writer.add_page(PDFPage::new(100.0 + i as f64, 200.0)).unwrap();
+    }
+    let pdf = writer.finish_writing(Vec::new(), DocumentInfo::new().with_title("Large"))
+        .unwrap()
+        .into_inner();
+    assert!(pdf.len() > 1 << 24);
+
+    let reader = DocumentReader::from_bytes(pdf).unwrap();
+    assert!(reader.uses_xref_streams());
+    let trailer = reader.trailer();
+    let field_widths: Vec<i64> = trailer.get(&Name::w()).and_then(Object::as_array).unwrap()
+        .iter()
+        .filter_map(Object::as_int)
+        .collect();
+    assert_eq!(field_widths, vec![1, 4, 2]);
+    assert_eq!(trailer.get(&Name::size()).and_then(Object::as_int), Some(reader.xref_size() as i64));
+    assert!(trailer.get(&Name::new("Root")).and_then(Object::as_ref_id).is_some());
+
+    // Every object can be read back, from the file or from its object stream
+    for id in reader.object_ids() {
+        reader.get_object(id).unwrap();
+    }
+    let info = reader.document_info().unwrap().unwrap();
+    assert_eq!(info.get(&Name::new("Title")).and_then(Object::as_str), Some("Large"));
+
+    let pages = reader.pages().unwrap();
+    assert_eq!(pages.len(), (image_count + small_page_count) as usize);
+    let widths: Vec<f64> = pages.iter()
+        .map(|(_, page)| page.get(&Name::media_box()).and_then(Object::as_array).unwrap()[2].as_number().unwrap())
+        .collect();
+    let expected_widths: Vec<f64> = std::iter::repeat_n(1500.0, image_count as usize)
+        .chain((0..small_page_count).map(|i| 100.0 + i as f64))
+        .collect();
+    assert_eq!(widths, expected_widths);
+
+    // The last image comes after 16 MiB of the others, and is still found whole
+    let resources = reader.resolve_dictionary(pages[2].1.get(&Name::new("Resources")).unwrap()).unwrap();
+    let xobjects = reader.resolve_dictionary(resources.get(&Name::new("XObject")).unwrap()).unwrap();
+    let (_, image) = xobjects.iter().next().unwrap();
+    let image = reader.resolve(image).unwrap();
+    let image = image.as_stream().unwrap();
+    assert_eq!(image.dictionary().get(&Name::new("Width")).and_then(Object::as_int), Some(1500));
+    assert_eq!(reader.decode_stream(image).unwrap(), make_noise(1500, 1300, 3).as_bytes());
+}
@@ -3,7 +3,6 @@ use std::{
     path::{Path},
 };
 use serde::de::{DeserializeOwned};
-use serde_json;
 
 pub fn read_json_file<T: DeserializeOwned>(path: impl AsRef<Path>) -> Result<T, String> {
     let path = path.as_ref();
@@ -21,7 +20,7 @@ pub fn file_name(path: &Path) -> &str {
 pub fn compare_file_name(path: &Path, other: &str) -> bool { file_name(path) == other }
 
 pub fn byte_size_string(byte_size: u64) -> String {
-    const PREFIXES: &'static [(&'static str, u64)] = &[
+    const PREFIXES: &[(&str, u64)] = &[
         ("GB", 1 << 30),
         ("MB", 1 << 20),
         ("KB", 1 << 10),
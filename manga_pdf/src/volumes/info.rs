@@ -1,8 +1,12 @@
 use std::{
+    num::{NonZeroU32},
     path::{Path, PathBuf},
 };
 use serde::{Deserialize};
-use lib_stream_pdf::{DocumentInfo, PDFImage};
+use lib_stream_pdf::{
+    CatalogOptions, ChromaSubsampling, ColourProfiles, CompressionOptions, DocumentInfo, EncodingPolicy, FlateLevel,
+    ImageJob, ImagePipeline, PageLabel, PageLabelStyle, PageLayout, PDFFont, ReadingDirection, TextLayer,
+};
 use super::{POINTS_PER_MM};
 
 #[derive(Deserialize)]
@@ -18,6 +22,53 @@ pub struct VolumeInfo {
     chapters: Vec<ChapterInfo>,
     page_info: Vec<PageInfo>,
     lossless_images: Vec<String>,
+    /// Manga is read right to left, so the viewer should turn the pages that way
+    #[serde(default)]
+    right_to_left: bool,
+    /// Show the pages side by side like an open book (with the cover by itself)
+    #[serde(default)]
+    two_page_spread: bool,
+    /// Where the printed page numbers change (like after a colour insert)
+    #[serde(default)]
+    page_labels: Vec<PageLabelInfo>,
+    /// The font for the OCR text. It's needed if any page has OCR files.
+    #[serde(default)]
+    ocr_font: Option<PathBuf>,
+    /// Pick the smallest encoding for each image, as long as its PSNR (in dB) is at least this.
+    /// The images in `lossless_images` only get to pick between lossless encodings.
+    #[serde(default)]
+    min_psnr: Option<f64>,
+    /// Change the colours of every image to sRGB (or Gray Gamma 2.2), so only those colour profiles
+    /// get embedded. The encodings get picked like with `min_psnr`, using its default if it isn't set.
+    #[serde(default)]
+    convert_colour_profiles: bool,
+    /// How many images get prepared at the same time. It defaults to the number of cores.
+    #[serde(default)]
+    image_threads: Option<usize>,
+    /// Roughly how much memory (in MiB) the images being prepared can use. It defaults to 1 GiB.
+    #[serde(default)]
+    image_memory_budget_mib: Option<u64>,
+    /// Write the pages in ranges of this many, each on its own thread, and put them together at the end.
+    /// The ranges share the image threads, the image memory budget and the OCR font. Identical images
+    /// and ICC profiles in different ranges are merged when the fragments are appended. Each fragment is
+    /// read back into memory to be appended, so on top of the image budget, there needs to be room for a
+    /// whole fragment (roughly this many pages of encoded images). The fragment files also take up as much disk space as the volume until
+    /// they're deleted.
+    #[serde(default)]
+    pages_per_fragment: Option<usize>,
+    /// The zlib level (0 to 9) for everything that's compressed with Flate. It defaults to 9.
+    #[serde(default)]
+    flate_level: Option<u32>,
+    /// Use zopfli instead of zlib (ignoring `flate_level`). It's a lot slower for a few percent
+    /// smaller, so it's for final archival builds.
+    #[serde(default)]
+    zopfli: bool,
+    /// The quality (1 to 100) of images that get encoded as JPEGs. It defaults to 90.
+    #[serde(default)]
+    jpeg_quality: Option<u8>,
+    /// How much colour the JPEGs keep. It defaults to keeping all of it.
+    #[serde(default)]
+    chroma_subsampling: Option<ChromaSubsamplingInfo>,
 }
 impl VolumeInfo {
     /// Gets the (width, height) dimensions usable for a PDF (units in device space)
@@ -25,9 +76,13 @@ impl VolumeInfo {
         (self.width * POINTS_PER_MM, self.height * POINTS_PER_MM)
     }
     pub fn save_path(&self, base_dir: impl AsRef<Path>) -> PathBuf {
-        base_dir.as_ref().join(&format!("{}.pdf", self.save_name))
+        base_dir.as_ref().join(format!("{}.pdf", self.save_name))
     }
     pub fn chapter_list(&self) -> &[ChapterInfo] { &self.chapters }
+    pub fn page_label_list(&self) -> &[PageLabelInfo] { &self.page_labels }
+    pub fn pages_per_fragment(&self) -> Option<usize> {
+        self.pages_per_fragment.map(|pages_per_fragment| pages_per_fragment.max(1))
+    }
     pub fn page_image_infos(&self) -> Vec<PageImageInfo> {
         // Ignore any empty page lists to make my life easier when making the info JSONs
         self.page_info.iter().filter_map(|page_info| {
@@ -35,16 +90,59 @@ impl VolumeInfo {
                 None
             } else {
                 let images = page_info.images.iter().map(|image_path| {
-                    let is_lossless = self.is_image_lossless(&image_path);
+                    let is_lossless = self.is_image_lossless(image_path);
                     (image_path.clone(), is_lossless)
                 }).collect();
                 Some(PageImageInfo {
                     image_gap: page_info.image_gap,
                     images,
+                    ocr_files: page_info.ocr_files.clone(),
+                    encoding_policy: self.make_encoding_policy(),
+                    compression: self.make_compression_options(),
                 })
             }
         }).collect()
     }
+    pub fn make_image_pipeline(&self) -> ImagePipeline {
+        let mut image_pipeline = ImagePipeline::new();
+        if let Some(image_threads) = self.image_threads {
+            image_pipeline = image_pipeline.with_thread_count(image_threads);
+        }
+        if let Some(image_memory_budget_mib) = self.image_memory_budget_mib {
+            image_pipeline = image_pipeline.with_memory_budget(image_memory_budget_mib << 20);
+        }
+        image_pipeline
+    }
+    pub fn make_compression_options(&self) -> CompressionOptions {
+        let mut compression = CompressionOptions::new();
+        if self.zopfli {
+            compression = compression.with_flate_level(FlateLevel::Zopfli);
+        } else if let Some(flate_level) = self.flate_level {
+            compression = compression.with_flate_level(FlateLevel::Zlib(flate_level));
+        }
+        if let Some(jpeg_quality) = self.jpeg_quality {
+            compression = compression.with_jpeg_quality(jpeg_quality);
+        }
+        if let Some(chroma_subsampling) = self.chroma_subsampling {
+            compression = compression.with_chroma_subsampling(chroma_subsampling.into_chroma_subsampling());
+        }
+        compression
+    }
+    /// There isn't one if the images should be made the old way
+    fn make_encoding_policy(&self) -> Option<EncodingPolicy> {
+        if self.min_psnr.is_none() && !self.convert_colour_profiles {
+            return None;
+        }
+        let mut encoding_policy = EncodingPolicy::new()
+            .with_compression(self.make_compression_options());
+        if let Some(min_psnr) = self.min_psnr {
+            encoding_policy = encoding_policy.with_min_psnr(min_psnr);
+        }
+        if self.convert_colour_profiles {
+            encoding_policy = encoding_policy.with_colour_profiles(ColourProfiles::ConvertToStandard);
+        }
+        Some(encoding_policy)
+    }
     pub fn make_document_info(&self) -> DocumentInfo {
         let document_info = DocumentInfo::new()
             .with_title(&self.title);
@@ -54,6 +152,22 @@ impl VolumeInfo {
             document_info
         }
     }
+    pub fn make_ocr_font(&self) -> Result<Option<PDFFont>, String> {
+        self.ocr_font.as_ref()
+            .map(|font_path| PDFFont::from_path(font_path, 0)
+                .map_err(|e| format!("Failed to read the OCR font {}: {:?}", font_path.display(), e)))
+            .transpose()
+    }
+    pub fn make_catalog_options(&self) -> CatalogOptions {
+        let mut catalog_options = CatalogOptions::new();
+        if self.right_to_left {
+            catalog_options = catalog_options.with_direction(ReadingDirection::RightToLeft);
+        }
+        if self.two_page_spread {
+            catalog_options = catalog_options.with_page_layout(PageLayout::TwoPageRight);
+        }
+        catalog_options
+    }
 }
 impl VolumeInfo {
     fn is_image_lossless(&self, image_path: &Path) -> bool {
@@ -70,16 +184,86 @@ pub struct ChapterInfo {
     pub children: Vec<ChapterInfo>,
 }
 
+/// The page labels will start on the page with this file name
+#[derive(Deserialize)]
+pub struct PageLabelInfo {
+    pub file_name: String,
+    /// Leave this out to only use the prefix
+    style: Option<PageLabelStyleInfo>,
+    prefix: Option<String>,
+    /// This has to be at least 1
+    start_number: Option<NonZeroU32>,
+}
+impl PageLabelInfo {
+    pub fn make_page_label(&self) -> PageLabel {
+        let mut page_label = PageLabel::new();
+        if let Some(style) = self.style {
+            page_label = page_label.with_style(style.into_page_label_style());
+        }
+        if let Some(prefix) = &self.prefix {
+            page_label = page_label.with_prefix(prefix);
+        }
+        if let Some(start_number) = self.start_number {
+            page_label = page_label.with_start_number(start_number);
+        }
+        page_label
+    }
+}
+#[derive(Copy, Clone, Deserialize)]
+enum PageLabelStyleInfo {
+    Decimal,
+    UpperRoman,
+    LowerRoman,
+    UpperAlpha,
+    LowerAlpha,
+}
+impl PageLabelStyleInfo {
+    fn into_page_label_style(self) -> PageLabelStyle {
+        match self {
+            Self::Decimal => PageLabelStyle::Decimal,
+            Self::UpperRoman => PageLabelStyle::UpperRoman,
+            Self::LowerRoman => PageLabelStyle::LowerRoman,
+            Self::UpperAlpha => PageLabelStyle::UpperAlpha,
+            Self::LowerAlpha => PageLabelStyle::LowerAlpha,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Deserialize)]
+enum ChromaSubsamplingInfo {
+    /// 4:4:4
+    Full,
+    /// 4:2:2
+    Half,
+    /// 4:2:0
+    Quarter,
+}
+impl ChromaSubsamplingInfo {
+    fn into_chroma_subsampling(self) -> ChromaSubsampling {
+        match self {
+            Self::Full => ChromaSubsampling::Full,
+            Self::Half => ChromaSubsampling::Half,
+            Self::Quarter => ChromaSubsampling::Quarter,
+        }
+    }
+}
+
 #[derive(Clone, Deserialize)]
 struct PageInfo {
     /// The percentage gap between each page in a wide page (1 is 100% of the total original width)
     image_gap: f64,
     /// Have a list of tupled image names that need to be combined (0: left -> len: right) together for an extra wide page (見開き)
     images: Vec<PathBuf>,
+    /// The hOCR or ALTO file for each image, in the same order. Images without one can be null.
+    #[serde(default)]
+    ocr_files: Vec<Option<PathBuf>>,
 }
 pub struct PageImageInfo {
     image_gap: f64,
     images: Vec<(PathBuf, bool)>,
+    ocr_files: Vec<Option<PathBuf>>,
+    encoding_policy: Option<EncodingPolicy>,
+    compression: CompressionOptions,
 }
 impl PageImageInfo {
     pub fn image_gap(&self) -> f64 { self.image_gap }
@@ -87,13 +271,31 @@ impl PageImageInfo {
         self.images.iter()
             .any(|(image, _)| crate::utils::compare_file_name(image, file_name))
     }
-    pub fn make_pdf_images(&self) -> Result<Vec<(PDFImage, &Path)>, String> {
-        let mut pdf_images = Vec::new();
-        for (image_path, lossless) in self.images.iter() {
-            let pdf_image = PDFImage::from_path(&image_path, *lossless)
-                .map_err(|e| format!("Failed to make the image: {:?}", e))?;
-            pdf_images.push( (pdf_image, image_path.as_path()) );
+    pub fn image_paths(&self) -> impl Iterator<Item = &Path> {
+        self.images.iter().map(|(image_path, _)| image_path.as_path())
+    }
+    /// One for each image, in the same order as `image_paths`
+    pub fn make_image_jobs(&self) -> Vec<ImageJob> {
+        self.images.iter().map(|(image_path, lossless)| {
+            match (&self.encoding_policy, lossless) {
+                (Some(encoding_policy), true) => ImageJob::with_policy(image_path,
+                    encoding_policy.clone().with_min_psnr(f64::INFINITY)),
+                (Some(encoding_policy), false) => ImageJob::with_policy(image_path, encoding_policy.clone()),
+                (None, _) => ImageJob::with_compression(image_path, *lossless, self.compression.clone()),
+            }
+        }).collect()
+    }
+    /// There's one for each image, even if it doesn't have an OCR file
+    pub fn make_text_layers(&self) -> Result<Vec<Option<TextLayer>>, String> {
+        let mut text_layers = Vec::new();
+        for i in 0..self.images.len() {
+            let text_layer = match self.ocr_files.get(i) {
+                Some(Some(ocr_path)) => Some(TextLayer::from_path(ocr_path)
+                    .map_err(|e| format!("Failed to read the OCR file {}: {:?}", ocr_path.display(), e))?),
+                _ => None,
+            };
+            text_layers.push(text_layer);
         }
-        Ok(pdf_images)
+        Ok(text_layers)
     }
 }
@@ -1,13 +1,16 @@
 use std::{
+    collections::{BTreeMap},
     io::{Write},
     path::{Path, PathBuf},
     fs::{self},
     time::{Instant},
 };
+use lib_stream_pdf::{DuplicateImages, ImageEncoding};
 
 pub struct Stats {
     images: Vec<ImageStats>,
     total_pdf_size: u64,
+    duplicate_images: DuplicateImages,
     start_time: Instant,
 }
 impl Stats {
@@ -15,6 +18,7 @@ impl Stats {
         Stats {
             images: Vec::new(),
             total_pdf_size: 0,
+            duplicate_images: DuplicateImages::default(),
             start_time: Instant::now(),
         }
     }
@@ -25,6 +29,14 @@ impl Stats {
     pub fn set_total_pdf_size(&mut self, total_pdf_size: u64) {
         self.total_pdf_size = total_pdf_size;
     }
+    pub fn add_duplicate_images(&mut self, duplicate_images: DuplicateImages) {
+        self.duplicate_images += duplicate_images;
+    }
+    /// The images from a fragment come after the ones that are already here
+    pub fn add_fragment_stats(&mut self, fragment_stats: Stats) {
+        self.images.extend(fragment_stats.images);
+        self.duplicate_images += fragment_stats.duplicate_images;
+    }
 
     pub fn write_stats<W: Write>(&self, writer: &mut W) -> Result<(), String> {
         let total_image_files_size = self.images.iter()
@@ -45,6 +57,19 @@ impl Stats {
         writeln!(writer, "Total Image Size in PDF: {}",
             crate::utils::byte_size_string(total_image_in_pdf_size))
             .map_err(|e| format!("Failed to write the total image size in PDF ({:?})", e))?;
+        writeln!(writer, "Duplicate Images:        {} (saved {})", self.duplicate_images.count(),
+            crate::utils::byte_size_string(self.duplicate_images.bytes_saved()))
+            .map_err(|e| format!("Failed to write the duplicate images ({:?})", e))?;
+
+        let mut encoding_counts = BTreeMap::new();
+        for image_stats in &self.images {
+            *encoding_counts.entry(image_stats.encoding).or_insert(0) += 1;
+        }
+        let encoding_counts: Vec<String> = encoding_counts.into_iter()
+            .map(|(encoding, count)| format!("{:?} {}", encoding, count))
+            .collect();
+        writeln!(writer, "Image Encodings:         {}", encoding_counts.join(", "))
+            .map_err(|e| format!("Failed to write the image encodings ({:?})", e))?;
 
         for image_stats in &self.images {
             if image_stats.pdf_to_file_ratio > 1.01 || image_stats.pdf_to_file_ratio < 0.99 {
@@ -60,15 +85,16 @@ pub struct ImageStats {
     file_size: u64,
     size_in_pdf: u64,
     pdf_to_file_ratio: f64,
+    encoding: ImageEncoding,
 }
 impl ImageStats {
-    pub fn new(path: impl AsRef<Path>, size_in_pdf: u64) -> Result<ImageStats, String> {
+    pub fn new(path: impl AsRef<Path>, size_in_pdf: u64, encoding: ImageEncoding) -> Result<ImageStats, String> {
         let path = path.as_ref().to_owned();
         let metadata = fs::metadata(&path)
             .map_err(|e| format!("Failed to get the metadata for {:?} ({:?})", &path, e))?;
         let file_size = metadata.len();
         let pdf_to_file_ratio = (size_in_pdf as f64) / (file_size as f64);
-        Ok(ImageStats { path, file_size, size_in_pdf, pdf_to_file_ratio })
+        Ok(ImageStats { path, file_size, size_in_pdf, pdf_to_file_ratio, encoding })
     }
 }
 impl ImageStats {
@@ -77,7 +103,8 @@ impl ImageStats {
         let o_bytes = crate::utils::byte_size_string(self.file_size);
         let n_bytes = crate::utils::byte_size_string(self.size_in_pdf);
         let ratio = self.pdf_to_file_ratio;
-        writeln!(writer, "{:?} (Original {}, In-PDF {}, {:.3}x)", file_name, o_bytes, n_bytes, ratio)
+        writeln!(writer, "{:?} (Original {}, In-PDF {}, {:.3}x, {:?})", file_name, o_bytes, n_bytes, ratio,
+            self.encoding)
             .map_err(|e| format!("Failed to write the image stats ({:?})", e))?;
         Ok(())
     }
@@ -1,20 +1,22 @@
 use std::{
-    fs,
-    path::{Path},
+    fs::{self, File},
+    io::{BufWriter, Seek, Write},
+    path::{Path, PathBuf},
 };
+use rayon::prelude::*;
 use lib_stream_pdf::{
-    DocumentWriter, PDFPage, ImageRef, Justify,
-    PageRef, OutlineItem,
+    DocumentInfo, DocumentReader, DocumentWriter, PDFPage, ImageJob, ImageRef, Justify,
+    FontRef, PageRef, OutlineItem, PreparedImages, TextLayer,
 };
 use super::{
-    info::{ChapterInfo, VolumeInfo, PageImageInfo},
+    info::{ChapterInfo, VolumeInfo, PageImageInfo, PageLabelInfo},
     stats::{Stats, ImageStats},
 };
 
 pub fn make_volume(info: VolumeInfo, out_dir: impl AsRef<Path>) -> Result<(), String> {
     let save_path = info.save_path(out_dir);
-    let (page_width, page_height) = info.dimensions_in_device_space();
     let mut outline_holders = OutlineItemHolder::from_chapter_infos(info.chapter_list());
+    let mut missing_page_labels: Vec<&PageLabelInfo> = info.page_label_list().iter().collect();
 
     let mut stats = Stats::new();
 
@@ -22,37 +24,48 @@ pub fn make_volume(info: VolumeInfo, out_dir: impl AsRef<Path>) -> Result<(), St
     fs::create_dir_all(save_path.parent().unwrap())
         .map_err(|e| format!("Failed to mkdirs for {}. {}", save_path.display(), e))?;
     let mut doc_writer = DocumentWriter::stream_to_file(&save_path, true)
-        .map_err(|e| format!("Failed to open the document writer: {:?}", e))?;
+        .map_err(|e| format!("Failed to open the document writer: {:?}", e))?
+        .with_catalog_options(info.make_catalog_options())
+        .with_compression(info.make_compression_options());
 
-    for page_image_info in info.page_image_infos() {
-        let mut pdf_image_refs = Vec::new();
-        for (pdf_image, image_path) in page_image_info.make_pdf_images()? {
-            let pdf_start_size = doc_writer.file_position()
-                .map_err(|e| format!("Failed to get the starting file position ({:?}", e))?;
+    let ocr_font_ref = info.make_ocr_font()?
+        .map(|ocr_font| doc_writer.add_font(ocr_font));
+    let page_image_infos = info.page_image_infos();
+    let page_text_layers = page_image_infos.iter()
+        .map(PageImageInfo::make_text_layers)
+        .collect::<Result<Vec<_>, String>>()?;
+    let page_refs = match info.pages_per_fragment() {
+        Some(pages_per_fragment) => {
+            let (page_refs, fragment_stats) = write_fragments(&info, &page_image_infos, &page_text_layers,
+                pages_per_fragment, ocr_font_ref.as_ref(), &save_path, &mut doc_writer)?;
+            stats.add_fragment_stats(fragment_stats);
+            page_refs
+        },
+        None => {
+            // The images get prepared ahead of the page that's being written
+            let pdf_images = info.make_image_pipeline().prepare(make_image_jobs(&page_image_infos));
+            write_pages(&info, &page_image_infos, &page_text_layers, ocr_font_ref.as_ref(), pdf_images,
+                &mut doc_writer, &mut stats)?
+        },
+    };
 
-                let pdf_image_ref = doc_writer.add_image(pdf_image)
-                .map_err(|e| format!("Failed to add the image: {:?}", e))?;
-            pdf_image_refs.push(pdf_image_ref);
+    for (page_index, (page_image_info, page_ref)) in page_image_infos.iter().zip(page_refs).enumerate() {
+        apply_to_holders_if_matching_page(&mut outline_holders, page_image_info, page_ref);
 
-            let pdf_end_size = doc_writer.file_position()
-                .map_err(|e| format!("Failed to get the ending file position ({:?})", e))?;
-            stats.add_image_stats(
-                ImageStats::new(image_path, pdf_end_size - pdf_start_size)
-                    .map_err(|e| format!("Failed to make new image stats for {:?} ({:?})",
-                        image_path, e))?
-            );
-        }
-        if pdf_image_refs.is_empty() {
-            return Err("A page can't be empty (aka. without images)".to_string());
+        missing_page_labels.retain(|page_label_info| {
+            if page_image_info.has_image(&page_label_info.file_name) {
+                doc_writer.add_page_label(page_index, page_label_info.make_page_label());
+                false
+            } else {
+                true
+            }
+        });
+    }
+    if !missing_page_labels.is_empty() {
+        for page_label_info in missing_page_labels {
+            println!("Failed to find a page with {} (page label)", page_label_info.file_name);
         }
-
-        let pdf_page = layout_page(
-            pdf_image_refs, page_image_info.image_gap(), page_width, page_height
-        );
-        let page_ref = doc_writer.add_page(pdf_page)
-            .map_err(|e| format!("Failed to add a page: {:?}", e))?;
-
-        apply_to_holders_if_matching_page(&mut outline_holders, &page_image_info, page_ref);
+        return Err("The page labels are incomplete".to_string());
     }
 
     let mut missed_outline_items = Vec::new();
@@ -64,7 +77,7 @@ pub fn make_volume(info: VolumeInfo, out_dir: impl AsRef<Path>) -> Result<(), St
         }
     }
 
-    if missed_outline_items.len() > 0 {
+    if !missed_outline_items.is_empty() {
         for missed_outline_item in missed_outline_items {
             println!("{}", missed_outline_item);
         }
@@ -82,6 +95,140 @@ pub fn make_volume(info: VolumeInfo, out_dir: impl AsRef<Path>) -> Result<(), St
     Ok(())
 }
 
+fn make_image_jobs(page_image_infos: &[PageImageInfo]) -> Vec<ImageJob> {
+    page_image_infos.iter()
+        .flat_map(PageImageInfo::make_image_jobs)
+        .collect()
+}
+
+/// Gives back the pages in the same order as the page infos. The prepared images have to be for
+/// the same pages.
+fn write_pages<W: Write + Seek>(info: &VolumeInfo, page_image_infos: &[PageImageInfo],
+page_text_layers: &[Vec<Option<TextLayer>>], ocr_font_ref: Option<&FontRef>, mut pdf_images: PreparedImages,
+doc_writer: &mut DocumentWriter<W>, stats: &mut Stats) -> Result<Vec<PageRef>, String> {
+    let (page_width, page_height) = info.dimensions_in_device_space();
+    let mut page_refs = Vec::new();
+    for (page_image_info, text_layers) in page_image_infos.iter().zip(page_text_layers) {
+        let mut pdf_image_refs = Vec::new();
+        for image_path in page_image_info.image_paths() {
+            // There's a prepared image for every job
+            let pdf_image = pdf_images.next().unwrap()
+                .map_err(|e| format!("Failed to make the image: {:?}", e))?;
+            let encoding = pdf_image.encoding();
+            let pdf_start_size = doc_writer.file_position()
+                .map_err(|e| format!("Failed to get the starting file position ({:?}", e))?;
+
+            let pdf_image_ref = doc_writer.add_image(pdf_image)
+                .map_err(|e| format!("Failed to add the image: {:?}", e))?;
+            pdf_image_refs.push(pdf_image_ref);
+
+            let pdf_end_size = doc_writer.file_position()
+                .map_err(|e| format!("Failed to get the ending file position ({:?})", e))?;
+            stats.add_image_stats(
+                ImageStats::new(image_path, pdf_end_size - pdf_start_size, encoding)
+                    .map_err(|e| format!("Failed to make new image stats for {:?} ({:?})",
+                        image_path, e))?
+            );
+        }
+        if pdf_image_refs.is_empty() {
+            return Err("A page can't be empty (aka. without images)".to_string());
+        }
+        if ocr_font_ref.is_none() && text_layers.iter().any(Option::is_some) {
+            return Err("The volume needs an ocr_font to use the OCR files".to_string());
+        }
+
+        let pdf_page = layout_page(
+            pdf_image_refs, text_layers, ocr_font_ref, page_image_info.image_gap(), page_width,
+            page_height
+        );
+        let page_ref = doc_writer.add_page(pdf_page)
+            .map_err(|e| format!("Failed to add a page: {:?}", e))?;
+        page_refs.push(page_ref);
+    }
+    stats.add_duplicate_images(doc_writer.duplicate_images());
+    Ok(page_refs)
+}
+
+/// Each range of pages gets written into its own fragment file on its own thread. The fragments are
+/// put together in order once they've all been written, and then they're deleted. They share the
+/// image threads and the OCR font, which only gets written once by the volume. The stats of every
+/// fragment are given back together.
+fn write_fragments(info: &VolumeInfo, page_image_infos: &[PageImageInfo], page_text_layers: &[Vec<Option<TextLayer>>],
+pages_per_fragment: usize, ocr_font_ref: Option<&FontRef>, save_path: &Path, doc_writer: &mut DocumentWriter)
+-> Result<(Vec<PageRef>, Stats), String> {
+    // The fragments use the font's glyphs in any order, so they get their places in page order first
+    if let Some(ocr_font_ref) = ocr_font_ref {
+        for word in page_text_layers.iter().flatten().flatten().flat_map(TextLayer::words) {
+            doc_writer.reserve_glyphs(ocr_font_ref, word.text())
+                .map_err(|e| format!("Failed to reserve the OCR font's glyphs: {:?}", e))?;
+        }
+    }
+    let fragment_paths: Vec<PathBuf> = (0..page_image_infos.len().div_ceil(pages_per_fragment))
+        .map(|fragment_index| save_path.with_extension(format!("fragment{}.pdf", fragment_index)))
+        .collect();
+    let mut stats = Stats::new();
+    let page_refs = start_fragments(&fragment_paths, doc_writer)
+        .and_then(|fragment_writers| {
+            let image_job_groups = page_image_infos.chunks(pages_per_fragment)
+                .map(make_image_jobs)
+                .collect();
+            let pdf_image_groups = info.make_image_pipeline().prepare_groups(image_job_groups);
+            let fragment_stats: Vec<Result<Stats, String>> = fragment_writers.into_par_iter()
+                .zip(pdf_image_groups)
+                .zip(page_image_infos.par_chunks(pages_per_fragment))
+                .zip(page_text_layers.par_chunks(pages_per_fragment))
+                .zip(&fragment_paths)
+                .map(|((((fragment_writer, pdf_images), fragment_page_infos), fragment_text_layers), fragment_path)| {
+                    write_fragment(info, fragment_page_infos, fragment_text_layers, ocr_font_ref, pdf_images,
+                        fragment_writer, fragment_path)
+                })
+                .collect();
+            append_fragments(fragment_stats, &fragment_paths, doc_writer, &mut stats)
+        });
+    for fragment_path in &fragment_paths {
+        // A fragment might not have been made if something went wrong
+        let _ = fs::remove_file(fragment_path);
+    }
+    Ok((page_refs?, stats))
+}
+/// The writers are made before any of them start, so that they can all use the volume's font
+fn start_fragments(fragment_paths: &[PathBuf], doc_writer: &mut DocumentWriter)
+-> Result<Vec<DocumentWriter>, String> {
+    fragment_paths.iter()
+        .map(|fragment_path| {
+            let file = File::create(fragment_path)
+                .map_err(|e| format!("Failed to create the fragment {}: {:?}", fragment_path.display(), e))?;
+            doc_writer.fragment_writer(BufWriter::new(file))
+                .map_err(|e| format!("Failed to open the fragment writer for {}: {:?}", fragment_path.display(), e))
+        })
+        .collect()
+}
+fn write_fragment(info: &VolumeInfo, page_image_infos: &[PageImageInfo], page_text_layers: &[Vec<Option<TextLayer>>],
+ocr_font_ref: Option<&FontRef>, pdf_images: PreparedImages, mut fragment_writer: DocumentWriter, fragment_path: &Path)
+-> Result<Stats, String> {
+    let mut fragment_stats = Stats::new();
+    write_pages(info, page_image_infos, page_text_layers, ocr_font_ref, pdf_images, &mut fragment_writer,
+        &mut fragment_stats)?;
+    fragment_writer.finish_writing(Vec::new(), DocumentInfo::new())
+        .map_err(|e| format!("Failed to finish writing {}: {:?}", fragment_path.display(), e))?;
+    Ok(fragment_stats)
+}
+fn append_fragments(fragment_stats: Vec<Result<Stats, String>>, fragment_paths: &[PathBuf],
+doc_writer: &mut DocumentWriter, stats: &mut Stats) -> Result<Vec<PageRef>, String> {
+    let mut page_refs = Vec::new();
+    for (fragment_stats, fragment_path) in fragment_stats.into_iter().zip(fragment_paths) {
+        stats.add_fragment_stats(fragment_stats?);
+        let fragment = DocumentReader::open(fragment_path)
+            .map_err(|e| format!("Failed to read the fragment {}: {:?}", fragment_path.display(), e))?;
+        let fragment_page_refs = doc_writer.append_fragment(&fragment)
+            .map_err(|e| format!("Failed to append the fragment {}: {:?}", fragment_path.display(), e))?;
+        page_refs.extend(fragment_page_refs);
+    }
+    // Images that more than one fragment has are only counted when they get merged here
+    stats.add_duplicate_images(doc_writer.duplicate_images());
+    Ok(page_refs)
+}
+
 struct OutlineItemHolder {
     name: String,
     file_name: String,
@@ -130,8 +277,8 @@ page_ref: PageRef) {
     }
 }
 
-fn layout_page(image_refs: Vec<ImageRef>, image_gap: f64, mut page_width: f64, page_height: f64)
--> PDFPage {
+fn layout_page(image_refs: Vec<ImageRef>, text_layers: &[Option<TextLayer>], ocr_font_ref: Option<&FontRef>,
+image_gap: f64, mut page_width: f64, page_height: f64) -> PDFPage {
     let num_images = image_refs.len();
     let total_image_width = image_refs.iter()
         .map(|image_ref| image_ref.dimensions().0)
@@ -166,8 +313,8 @@ fn layout_page(image_refs: Vec<ImageRef>, image_gap: f64, mut page_width: f64, p
     };
 
     let mut pdf_page = PDFPage::new(page_width, page_height);
-    let image_iterator = image_refs.into_iter().zip(image_width_ratios).enumerate();
-    for (i, (image_ref, image_width_ratio)) in image_iterator {
+    let image_iterator = image_refs.into_iter().zip(image_width_ratios).zip(text_layers).enumerate();
+    for (i, ((image_ref, image_width_ratio), text_layer)) in image_iterator {
         let justify = if num_images == 1 {
             Justify::Center
         } else {
@@ -178,7 +325,11 @@ fn layout_page(image_refs: Vec<ImageRef>, image_gap: f64, mut page_width: f64, p
                 Justify::Start
             }
         };
-        pdf_page.add_image(image_ref, x_progress, x_progress + image_width_ratio, justify);
+        pdf_page.add_image(image_ref.clone(), x_progress, x_progress + image_width_ratio, justify);
+        if let (Some(text_layer), Some(ocr_font_ref)) = (text_layer, ocr_font_ref) {
+            pdf_page.add_text_layer(&image_ref, x_progress, x_progress + image_width_ratio, justify, text_layer,
+                ocr_font_ref);
+        }
         x_progress += image_width_ratio + image_gap;
     }
     pdf_page